@@ -0,0 +1,32 @@
+use std::{env, fs, path::PathBuf};
+
+// Where distributions install usb.ids.
+const SYSTEM_PATHS: &[&str] = &[
+    "/usr/share/hwdata/usb.ids",
+    "/usr/share/misc/usb.ids",
+    "/usr/share/usb.ids",
+    "/var/lib/usbutils/usb.ids",
+];
+
+fn main() {
+    if env::var_os("CARGO_FEATURE_USB_IDS_EMBEDDED").is_none() {
+        return;
+    }
+
+    // Snapshot usb.ids into OUT_DIR so it can be compiled in.
+    println!("cargo:rerun-if-env-changed=USB_IDS_PATH");
+    let source = match env::var_os("USB_IDS_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => SYSTEM_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file())
+            .expect("usb-async: the usb-ids-embedded feature needs a copy of usb.ids; set USB_IDS_PATH to its location"),
+    };
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    let dest = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("usb.ids");
+    if let Err(err) = fs::copy(&source, &dest) {
+        panic!("usb-async: couldn't copy {}: {}", source.display(), err);
+    }
+}
@@ -26,6 +26,22 @@ fn main() -> Result<(), Box<dyn Error>> {
 
                         println!("{:04x}:{:04x} was unplugged", vendor_id, product_id);
                     },
+                    Some(usb_async::Event::Change(device, _)) => {
+                        let vendor_id = ctx.vendor_id(device).ok_or(usb_async::Error::NotConnected)?;
+                        let product_id = ctx.product_id(device).ok_or(usb_async::Error::NotConnected)?;
+
+                        println!("{:04x}:{:04x} changed", vendor_id, product_id);
+                    },
+                    Some(usb_async::Event::OverCurrent(hub, port)) => {
+                        let bus = ctx.bus_number(hub)?;
+
+                        println!("over-current on port {} of a hub on bus {}", port, bus);
+                    },
+                    Some(usb_async::Event::InterfaceAdd(..))
+                    | Some(usb_async::Event::InterfaceRemove(..)) => {},
+                    Some(usb_async::Event::EventsLost) => {
+                        println!("some events were lost; rescan to catch up");
+                    },
                     None => return Ok(())
                 };
                 chan
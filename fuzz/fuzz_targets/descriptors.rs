@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use usb_async::descriptor;
+
+fuzz_target!(|data: &[u8]| {
+    for _ in descriptor::walk(data) {}
+    let _ = descriptor::interface_classes(data);
+    if let Some((&configuration, bytes)) = data.split_first() {
+        let _ = descriptor::interface_associations(bytes, configuration);
+    }
+});
@@ -0,0 +1,305 @@
+//! A tamper-evident log of hotplug events.
+//!
+//! Each event is appended as a line of JSON carrying the SHA-256 hash of the line before it, so
+//! editing, inserting or removing a line breaks the chain from that point on, which `verify`
+//! detects.
+//!
+//! Nothing follows the last line, so cutting lines off the end leaves a shorter chain that is
+//! still intact. To catch that, keep the hash of the last line, `AuditLog::head`, somewhere the
+//! log's writer can't change, and check the log against it with `verify_head`.
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Context, Event, Id, ProductId, VendorId};
+
+// The hash the first record chains onto.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// What identifies a device in the log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    /// The USB vendor ID.
+    pub vendor_id: Option<VendorId>,
+    /// The USB product ID.
+    pub product_id: Option<ProductId>,
+    /// The USB serial number string, if the device has one.
+    pub serial_number: Option<String>,
+    /// The USB manufacturer string, if the device has one.
+    pub manufacturer_string: Option<String>,
+    /// The USB product string, if the device has one.
+    pub product_string: Option<String>,
+    /// The number of the bus the device is attached to.
+    pub bus_number: Option<u8>,
+    /// The port path of the device, like `1-4.2`.
+    pub port_path: Option<String>,
+}
+
+impl Identity {
+    fn of(context: &Context, id: Id) -> Self {
+        Self {
+            vendor_id: context.vendor_id(id),
+            product_id: context.product_id(id),
+            serial_number: context.serial_number(id).ok(),
+            manufacturer_string: context.manufacturer_string(id).ok(),
+            product_string: context.product_string(id).ok(),
+            bus_number: context.bus_number(id).ok(),
+            port_path: context.port_path(id).ok().map(|path| path.to_string()),
+        }
+    }
+}
+
+/// What a record logs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// A device was plugged in.
+    Add,
+    /// A device was removed.
+    Remove,
+    /// A device changed.
+    Change,
+    /// A hub reported an over-current condition.
+    OverCurrent,
+    /// Some hotplug events were missed.
+    EventsLost,
+}
+
+/// A line of the log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// The position of the record in the log, counting from 0.
+    pub sequence: u64,
+    /// When the record was written.
+    pub time: SystemTime,
+    /// What happened.
+    pub action: Action,
+    /// The device it happened to.
+    pub device: Option<Identity>,
+    /// The hub port an over-current condition was reported on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u8>,
+    /// The hex SHA-256 hash of the previous line.
+    pub previous: String,
+}
+
+fn hash(line: &str) -> String {
+    Sha256::digest(line.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Appends hotplug events to a log.
+pub struct AuditLog<W> {
+    writer: W,
+    sequence: u64,
+    previous: String,
+    // Removed devices can't be queried anymore, so remember who they were.
+    devices: HashMap<Id, Identity>,
+}
+
+impl<W: Write> AuditLog<W> {
+    /// Start a new log.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            sequence: 0,
+            previous: String::from(GENESIS),
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Log an event, flushing it to the writer.
+    pub fn record(&mut self, context: &Context, event: &Event) -> io::Result<()> {
+        let mut port = None;
+        let (action, device) = match *event {
+            Event::Add(id) => {
+                let identity = Identity::of(context, id);
+                self.devices.insert(id, identity.clone());
+                (Action::Add, Some(identity))
+            }
+            Event::Remove(id) => {
+                let identity = self
+                    .devices
+                    .remove(&id)
+                    .unwrap_or_else(|| Identity::of(context, id));
+                (Action::Remove, Some(identity))
+            }
+            Event::Change(id, _) => {
+                let identity = Identity::of(context, id);
+                self.devices.insert(id, identity.clone());
+                (Action::Change, Some(identity))
+            }
+            Event::OverCurrent(hub, number) => {
+                port = Some(number);
+                (Action::OverCurrent, Some(Identity::of(context, hub)))
+            }
+            // Interfaces come and go with their devices, which are logged already.
+            Event::InterfaceAdd(..) | Event::InterfaceRemove(..) => return Ok(()),
+            Event::EventsLost => (Action::EventsLost, None),
+        };
+
+        let record = Record {
+            sequence: self.sequence,
+            time: SystemTime::now(),
+            action,
+            device,
+            port,
+            previous: self.previous.clone(),
+        };
+        let line = serde_json::to_string(&record)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+
+        self.sequence += 1;
+        self.previous = hash(&line);
+        Ok(())
+    }
+
+    /// The hex SHA-256 hash of the last line written, which the next line will carry.
+    ///
+    /// This is the genesis hash of all zeroes for an empty log.
+    pub fn head(&self) -> &str {
+        &self.previous
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl AuditLog<File> {
+    /// Open a log file for appending, continuing its chain, or create it.
+    ///
+    /// The existing lines are verified first, so new records are never chained onto a log that
+    /// was tampered with; that fails with `io::ErrorKind::InvalidData`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).read(true).open(path)?;
+
+        let mut log = Self::new(file.try_clone()?);
+        let (tampered, lines, head) = walk(BufReader::new(file))?;
+        if let Some(number) = tampered {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("audit log tampered with at line {}", number),
+            ));
+        }
+        log.sequence = lines as u64;
+        log.previous = head;
+        Ok(log)
+    }
+}
+
+/// Check that every line of a log chains onto the one before it.
+///
+/// Returns the number of the first line, counting from 1, that was tampered with or follows a
+/// line that was, or `None` if the log is intact.
+///
+/// Lines cut off the end go unnoticed; use `verify_head` to catch that.
+pub fn verify<R: BufRead>(reader: R) -> io::Result<Option<usize>> {
+    Ok(walk(reader)?.0)
+}
+
+/// Check that every line of a log chains onto the one before it, and that the last line hashes
+/// to `head`, as returned by `AuditLog::head` when the log was last written.
+///
+/// Returns the number of the first line, counting from 1, that was tampered with or follows a
+/// line that was, or `None` if the log is intact. If the last line doesn't hash to `head`,
+/// because lines were cut off the end or the last line was edited, that's the number of the
+/// line after it.
+pub fn verify_head<R: BufRead>(reader: R, head: &str) -> io::Result<Option<usize>> {
+    let (tampered, lines, last) = walk(reader)?;
+    if tampered.is_none() && last != head {
+        return Ok(Some(lines + 1));
+    }
+    Ok(tampered)
+}
+
+// Follow the chain, returning the first line that breaks it, the number of lines and the hash
+// of the last one.
+fn walk<R: BufRead>(reader: R) -> io::Result<(Option<usize>, usize, String)> {
+    let mut previous = String::from(GENESIS);
+    let mut lines = 0;
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let intact = serde_json::from_str::<Record>(&line)
+            .map_or(false, |record| {
+                record.sequence == number as u64 && record.previous == previous
+            });
+        if !intact {
+            return Ok((Some(number + 1), number, previous));
+        }
+        previous = hash(&line);
+        lines += 1;
+    }
+    Ok((None, lines, previous))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Write a log of events that happened to no device in particular.
+    fn log(length: u64) -> (String, String) {
+        let mut log = AuditLog::new(Vec::new());
+        for sequence in 0..length {
+            let record = Record {
+                sequence,
+                time: SystemTime::UNIX_EPOCH,
+                action: Action::EventsLost,
+                device: None,
+                port: None,
+                previous: log.previous.clone(),
+            };
+            let line = serde_json::to_string(&record).unwrap();
+            writeln!(log.writer, "{}", line).unwrap();
+            log.sequence += 1;
+            log.previous = hash(&line);
+        }
+        let head = String::from(log.head());
+        (String::from_utf8(log.into_inner()).unwrap(), head)
+    }
+
+    #[test]
+    fn intact_logs_verify() {
+        let (lines, head) = log(3);
+        assert_eq!(verify(lines.as_bytes()).unwrap(), None);
+        assert_eq!(verify_head(lines.as_bytes(), &head).unwrap(), None);
+        assert_eq!(verify_head(&b""[..], GENESIS).unwrap(), None);
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let (lines, head) = log(3);
+        let mut edited: Vec<_> = lines.lines().map(String::from).collect();
+        edited[1] = edited[1].replace("events_lost", "add");
+        let edited = edited.join("\n");
+        // The edited line still chains on, but the next one no longer does.
+        assert_eq!(verify(edited.as_bytes()).unwrap(), Some(3));
+        assert_eq!(verify_head(edited.as_bytes(), &head).unwrap(), Some(3));
+
+        let removed: Vec<_> = lines.lines().skip(1).collect();
+        assert_eq!(verify(removed.join("\n").as_bytes()).unwrap(), Some(1));
+        assert_eq!(verify(&b"not json\n"[..]).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn truncation_needs_the_head() {
+        let (lines, head) = log(3);
+        let truncated: Vec<_> = lines.lines().take(2).collect();
+        let truncated = truncated.join("\n");
+        assert_eq!(verify(truncated.as_bytes()).unwrap(), None);
+        assert_eq!(verify_head(truncated.as_bytes(), &head).unwrap(), Some(3));
+        assert_eq!(verify_head(&b""[..], &head).unwrap(), Some(1));
+    }
+}
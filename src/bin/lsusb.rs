@@ -0,0 +1,174 @@
+//! List USB devices, like lsusb.
+use std::{env, error::Error, process};
+
+use usb_async::{Context, Id, ProductId, VendorId};
+
+const USAGE: &str = "\
+usage: usb-async-lsusb [-t] [-v] [-s [[bus]:][devnum]] [-d [vendor]:[product]]
+
+  -t    show the device hierarchy as a tree
+  -v    show more about each device
+  -s    only show devices with the given bus and/or device number
+  -d    only show devices with the given vendor and/or product ID (hex)";
+
+#[derive(Default)]
+struct Options {
+    tree: bool,
+    verbose: bool,
+    bus: Option<u8>,
+    address: Option<u8>,
+    vendor_id: Option<VendorId>,
+    product_id: Option<ProductId>,
+}
+
+// Parse "a:b", where either side may be empty and the colon is optional for a lone `b`.
+fn pair<T>(value: &str, parse: impl Fn(&str) -> Option<T>) -> Option<(Option<T>, Option<T>)> {
+    let (first, second) = match value.find(':') {
+        Some(split) => (&value[..split], &value[split + 1..]),
+        None => ("", value),
+    };
+    let part = |part: &str| if part.is_empty() { Some(None) } else { parse(part).map(Some) };
+    Some((part(first)?, part(second)?))
+}
+
+fn parse_options() -> Option<Options> {
+    let mut options = Options::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-t" => options.tree = true,
+            "-v" => options.verbose = true,
+            "-s" => {
+                let (bus, address) = pair(&args.next()?, |n| n.parse().ok())?;
+                options.bus = bus;
+                options.address = address;
+            }
+            "-d" => {
+                let hex = |n: &str| u16::from_str_radix(n, 16).ok();
+                let (vendor_id, product_id) = pair(&args.next()?, hex)?;
+                options.vendor_id = vendor_id.map(VendorId);
+                options.product_id = product_id.map(ProductId);
+            }
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+fn matches(ctx: &Context, options: &Options, dev: Id) -> bool {
+    fn wanted<T: PartialEq>(wanted: Option<T>, value: Option<T>) -> bool {
+        wanted.map_or(true, |wanted| Some(wanted) == value)
+    }
+
+    wanted(options.bus, ctx.bus_number(dev).ok())
+        && wanted(options.address, ctx.device_address(dev).ok())
+        && wanted(options.vendor_id, ctx.vendor_id(dev))
+        && wanted(options.product_id, ctx.product_id(dev))
+}
+
+// Describe a device by name, preferring usb.ids like lsusb does.
+fn name(ctx: &Context, dev: Id) -> String {
+    let vendor = ctx.vendor_name(dev).or_else(|| ctx.manufacturer_string(dev).ok());
+    let product = ctx.product_name(dev).or_else(|| ctx.product_string(dev).ok());
+    match (vendor, product) {
+        (Some(vendor), Some(product)) => format!("{} {}", vendor, product),
+        (vendor, product) => vendor.or(product).unwrap_or_default(),
+    }
+}
+
+fn print_device(ctx: &Context, dev: Id) -> Result<(), Box<dyn Error>> {
+    println!(
+        "Bus {:03} Device {:03}: ID {:04x}:{:04x} {}",
+        ctx.bus_number(dev)?,
+        ctx.device_address(dev)?,
+        ctx.vendor_id(dev).ok_or(usb_async::Error::NotConnected)?,
+        ctx.product_id(dev).ok_or(usb_async::Error::NotConnected)?,
+        name(ctx, dev),
+    );
+    Ok(())
+}
+
+fn print_verbose(ctx: &Context, dev: Id) -> Result<(), Box<dyn Error>> {
+    let optional = |value: Result<String, usb_async::Error>| value.unwrap_or_default();
+
+    println!("  Port path           {}", ctx.port_path(dev)?);
+    println!("  bDeviceClass        {:3}", ctx.device_class(dev)?);
+    println!("  iManufacturer       {}", optional(ctx.manufacturer_string(dev)));
+    println!("  iProduct            {}", optional(ctx.product_string(dev)));
+    println!("  iSerial             {}", optional(ctx.serial_number(dev)));
+    for interface in ctx.interfaces(dev)? {
+        println!("  Interface {}", interface.number);
+        println!("    bAlternateSetting   {:3}", interface.alternate_setting);
+        println!("    bNumEndpoints       {:3}", interface.num_endpoints);
+        println!("    bInterfaceClass     {:3}", interface.class);
+        println!("    bInterfaceSubClass  {:3}", interface.subclass);
+        println!("    bInterfaceProtocol  {:3}", interface.protocol);
+        println!("    iInterface          {}", interface.name.unwrap_or_default());
+        println!("    Driver              {}", interface.driver.unwrap_or_default());
+    }
+    println!();
+    Ok(())
+}
+
+fn print_tree(ctx: &Context, options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut devices = ctx
+        .connected_devices()
+        .filter_map(|dev| Some((ctx.port_path(dev).ok()?, dev)))
+        .collect::<Vec<_>>();
+    // Port paths order depth-first, so hubs come right before what's plugged into them.
+    devices.sort();
+
+    for (path, dev) in devices {
+        if !matches(ctx, options, dev) {
+            continue;
+        }
+        let address = ctx.device_address(dev)?;
+        let drivers = ctx
+            .interfaces(dev)?
+            .into_iter()
+            .filter_map(|interface| interface.driver)
+            .collect::<Vec<_>>()
+            .join(", ");
+        match path.ports().last() {
+            None => println!("/:  Bus {:03}: Dev {:03}, {}", path.bus(), address, name(ctx, dev)),
+            Some(port) => println!(
+                "{:indent$}|__ Port {}: Dev {:03}, {} [{}]",
+                "",
+                port,
+                address,
+                name(ctx, dev),
+                drivers,
+                indent = path.ports().len() * 4,
+            ),
+        }
+        if options.verbose {
+            print_verbose(ctx, dev)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = parse_options().unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    });
+    let ctx = Context::new()?;
+
+    if options.tree {
+        return print_tree(&ctx, &options);
+    }
+
+    let mut devices = ctx
+        .connected_devices()
+        .filter(|dev| matches(&ctx, &options, *dev))
+        .collect::<Vec<_>>();
+    devices.sort_by_key(|dev| (ctx.bus_number(*dev).ok(), ctx.device_address(*dev).ok()));
+    for dev in devices {
+        print_device(&ctx, dev)?;
+        if options.verbose {
+            print_verbose(&ctx, dev)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! Print a line of JSON for each USB hotplug event.
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+use tokio::{prelude::*, runtime::current_thread};
+use usb_async::{Context, DeviceInfo, Event, Id};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let ctx = Context::new()?;
+    // Removed devices can't be queried anymore, so remember what they were.
+    let mut known: HashMap<Id, DeviceInfo> = ctx
+        .snapshot()
+        .into_iter()
+        .map(|info| (info.id, info))
+        .collect();
+
+    let mut mon = ctx.monitor()?.into_future();
+    let mut rt = current_thread::Runtime::new()?;
+    let stdout = io::stdout();
+    loop {
+        let (event, rest) = rt.block_on(mon).map_err(|(err, _)| err)?;
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+        let line = match event {
+            Some(Event::Add(id)) | Some(Event::Change(id, _)) => {
+                let kind = if let Some(Event::Add(_)) = event { "add" } else { "change" };
+                match ctx.info(id) {
+                    Ok(info) => {
+                        let line = json!({ "time": time, "event": kind, "device": &info });
+                        known.insert(id, info);
+                        line
+                    }
+                    // Gone again before it could be looked at; its remove event follows.
+                    Err(_) => json!({ "time": time, "event": kind, "device": { "id": id } }),
+                }
+            }
+            Some(Event::Remove(id)) => match known.remove(&id) {
+                Some(info) => json!({ "time": time, "event": "remove", "device": info }),
+                None => json!({ "time": time, "event": "remove", "device": { "id": id } }),
+            },
+            Some(Event::OverCurrent(hub, port)) => json!({
+                "time": time,
+                "event": "over_current",
+                "device": known.get(&hub),
+                "port": port,
+            }),
+            // The context isn't built to report these.
+            Some(Event::InterfaceAdd(..)) | Some(Event::InterfaceRemove(..)) => {
+                mon = rest.into_future();
+                continue;
+            }
+            Some(Event::EventsLost) => json!({ "time": time, "event": "events_lost" }),
+            None => return Ok(()),
+        };
+
+        // Flush each line, so consumers see events as they happen even through a pipe.
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()?;
+        mon = rest.into_future();
+    }
+}
@@ -0,0 +1,88 @@
+//! Where transfers on claimed interfaces get the time from.
+//!
+//! Retries wait out their backoff, quirks their delays, and latencies are measured with the
+//! clock of the `InterfaceHandle` the transfers are made on. The default is the system's; a
+//! `ManualClock` lets tests check retry timing without really waiting. Transfer timeouts are
+//! kept by the kernel, and the backoff of `Context::open_devnode` and
+//! `Context::claim_interface_retry` by tokio's timer, so neither goes through a clock.
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A source of time.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// Block the thread for `duration`.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The system's clock, going by `Instant::now` and `thread::sleep`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when told to, for tests.
+///
+/// Sleeping returns at once, moving the clock forward by the time slept.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<Instant>,
+}
+
+impl ManualClock {
+    /// A clock starting at the current time.
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_sleeps_without_waiting() {
+        let clock = ManualClock::new();
+        let (start, real_start) = (clock.now(), Instant::now());
+        clock.sleep(Duration::from_secs(3600));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.now() - start, Duration::from_secs(3601));
+        assert!(real_start.elapsed() < Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,641 @@
+//! Building and walking the bytes of standard USB descriptors.
+//!
+//! The builders make what a gadget has to hand to the host, e.g. through FunctionFS, and what a
+//! parser of descriptors can be fed in tests. Lengths, counts and string indices are filled in
+//! from what was built, so the result is consistent.
+//!
+//! The walkers read descriptors as devices return them, such as the contents of a device's
+//! sysfs `descriptors` file from `Context::descriptors`. They never trust the lengths in the
+//! bytes, and stop at the first malformed descriptor.
+use std::convert::TryFrom;
+
+use crate::{setup::descriptor_type, ProductId, VendorId};
+
+/// A device descriptor.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceDescriptor {
+    usb_version: u16,
+    class: (u8, u8, u8),
+    max_packet_size: u8,
+    vendor_id: VendorId,
+    product_id: ProductId,
+    device_version: u16,
+    strings: (u8, u8, u8),
+    num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    /// Describe a USB 2.0 device with a vendor and product ID, whose class is declared by its
+    /// interfaces.
+    pub fn new(vendor_id: VendorId, product_id: ProductId) -> Self {
+        Self {
+            usb_version: 0x0200,
+            class: (0, 0, 0),
+            max_packet_size: 64,
+            vendor_id,
+            product_id,
+            device_version: 0,
+            strings: (0, 0, 0),
+            num_configurations: 1,
+        }
+    }
+
+    /// Set the USB specification release number (`bcdUSB`), e.g. 0x0210 for USB 2.1.
+    pub fn usb_version(mut self, bcd: u16) -> Self {
+        self.usb_version = bcd;
+        self
+    }
+
+    /// Set the device class, subclass and protocol.
+    pub fn class(mut self, class: u8, subclass: u8, protocol: u8) -> Self {
+        self.class = (class, subclass, protocol);
+        self
+    }
+
+    /// Set the largest packet endpoint 0 takes: 8, 16, 32 or 64, or 9 (meaning 512) for
+    /// SuperSpeed devices.
+    pub fn max_packet_size(mut self, max_packet_size: u8) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Set the device release number (`bcdDevice`).
+    pub fn device_version(mut self, bcd: u16) -> Self {
+        self.device_version = bcd;
+        self
+    }
+
+    /// Set the indices of the manufacturer, product and serial number strings, as returned by
+    /// `StringTable::add`, or 0 for none.
+    pub fn strings(mut self, manufacturer: u8, product: u8, serial_number: u8) -> Self {
+        self.strings = (manufacturer, product, serial_number);
+        self
+    }
+
+    /// Set the number of configurations.
+    pub fn num_configurations(mut self, num_configurations: u8) -> Self {
+        self.num_configurations = num_configurations;
+        self
+    }
+
+    /// Encode the descriptor.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![18, descriptor_type::DEVICE];
+        bytes.extend_from_slice(&self.usb_version.to_le_bytes());
+        bytes.extend_from_slice(&[self.class.0, self.class.1, self.class.2, self.max_packet_size]);
+        bytes.extend_from_slice(&self.vendor_id.0.to_le_bytes());
+        bytes.extend_from_slice(&self.product_id.0.to_le_bytes());
+        bytes.extend_from_slice(&self.device_version.to_le_bytes());
+        bytes.extend_from_slice(&[
+            self.strings.0,
+            self.strings.1,
+            self.strings.2,
+            self.num_configurations,
+        ]);
+        bytes
+    }
+}
+
+/// A configuration descriptor, with its interfaces and their endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ConfigurationDescriptor {
+    value: u8,
+    string: u8,
+    self_powered: bool,
+    remote_wakeup: bool,
+    max_power: u16,
+    interfaces: Vec<InterfaceDescriptor>,
+}
+
+impl ConfigurationDescriptor {
+    /// Describe a bus-powered configuration selected by a value, counting from 1.
+    pub fn new(value: u8) -> Self {
+        Self {
+            value,
+            string: 0,
+            self_powered: false,
+            remote_wakeup: false,
+            max_power: 100,
+            interfaces: Vec::new(),
+        }
+    }
+
+    /// Set the index of the configuration's description string.
+    pub fn string(mut self, index: u8) -> Self {
+        self.string = index;
+        self
+    }
+
+    /// Declare that the device has its own power supply in this configuration.
+    pub fn self_powered(mut self) -> Self {
+        self.self_powered = true;
+        self
+    }
+
+    /// Declare that the device can wake the host.
+    pub fn remote_wakeup(mut self) -> Self {
+        self.remote_wakeup = true;
+        self
+    }
+
+    /// Set the most power drawn from the bus, in mA.
+    ///
+    /// This is encoded in units of 2 mA, as for USB 2.0 devices.
+    pub fn max_power(mut self, milliamps: u16) -> Self {
+        self.max_power = milliamps;
+        self
+    }
+
+    /// Add an interface, or an alternate setting of one added before.
+    pub fn interface(mut self, interface: InterfaceDescriptor) -> Self {
+        self.interfaces.push(interface);
+        self
+    }
+
+    /// Encode the descriptor followed by those of its interfaces and endpoints, as returned for a
+    /// `GET_DESCRIPTOR` request.
+    ///
+    /// Returns `None` if it all takes more than the 65535 bytes `wTotalLength` can describe, or
+    /// has more than 255 interfaces.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let mut numbers = self
+            .interfaces
+            .iter()
+            .map(|interface| interface.number)
+            .collect::<Vec<_>>();
+        numbers.sort_unstable();
+        numbers.dedup();
+
+        // Bit 7 is reserved and must be set.
+        let mut attributes = 0x80;
+        if self.self_powered {
+            attributes |= 0x40;
+        }
+        if self.remote_wakeup {
+            attributes |= 0x20;
+        }
+
+        let mut bytes = vec![9, descriptor_type::CONFIGURATION, 0, 0];
+        bytes.extend_from_slice(&[
+            u8::try_from(numbers.len()).ok()?,
+            self.value,
+            self.string,
+            attributes,
+            (self.max_power / 2).min(0xff) as u8,
+        ]);
+        for interface in &self.interfaces {
+            interface.write(&mut bytes);
+        }
+        let total_length = u16::try_from(bytes.len()).ok()?.to_le_bytes();
+        bytes[2..4].copy_from_slice(&total_length);
+        Some(bytes)
+    }
+}
+
+/// An interface descriptor, with its endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InterfaceDescriptor {
+    number: u8,
+    alternate_setting: u8,
+    class: (u8, u8, u8),
+    string: u8,
+    extra: Vec<u8>,
+    endpoints: Vec<EndpointDescriptor>,
+}
+
+impl InterfaceDescriptor {
+    /// Describe an interface by number, with a class, subclass and protocol.
+    pub fn new(number: u8, class: u8, subclass: u8, protocol: u8) -> Self {
+        Self {
+            number,
+            alternate_setting: 0,
+            class: (class, subclass, protocol),
+            string: 0,
+            extra: Vec::new(),
+            endpoints: Vec::new(),
+        }
+    }
+
+    /// Make this an alternate setting of the interface.
+    pub fn alternate_setting(mut self, alternate_setting: u8) -> Self {
+        self.alternate_setting = alternate_setting;
+        self
+    }
+
+    /// Set the index of the interface's description string.
+    pub fn string(mut self, index: u8) -> Self {
+        self.string = index;
+        self
+    }
+
+    /// Add class-specific descriptors, such as a HID descriptor, to go between the interface
+    /// descriptor and its endpoints.
+    pub fn extra(mut self, descriptors: &[u8]) -> Self {
+        self.extra.extend_from_slice(descriptors);
+        self
+    }
+
+    /// Add an endpoint.
+    pub fn endpoint(mut self, endpoint: EndpointDescriptor) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&[
+            9,
+            descriptor_type::INTERFACE,
+            self.number,
+            self.alternate_setting,
+            self.endpoints.len() as u8,
+            self.class.0,
+            self.class.1,
+            self.class.2,
+            self.string,
+        ]);
+        bytes.extend_from_slice(&self.extra);
+        for endpoint in &self.endpoints {
+            endpoint.write(bytes);
+        }
+    }
+}
+
+/// The type of transfers an endpoint carries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum TransferType {
+    /// Control transfers.
+    Control,
+    /// Isochronous transfers.
+    Isochronous,
+    /// Bulk transfers.
+    Bulk,
+    /// Interrupt transfers.
+    Interrupt,
+}
+
+/// An endpoint descriptor.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EndpointDescriptor {
+    address: u8,
+    transfer_type: TransferType,
+    max_packet_size: u16,
+    interval: u8,
+}
+
+impl EndpointDescriptor {
+    /// Describe an endpoint by address, with the direction bit (0x80 for IN) included.
+    pub fn new(address: u8, transfer_type: TransferType, max_packet_size: u16) -> Self {
+        Self {
+            address,
+            transfer_type,
+            max_packet_size,
+            interval: 0,
+        }
+    }
+
+    /// Set the polling interval of an interrupt or isochronous endpoint, in the encoding of the
+    /// device's speed.
+    pub fn interval(mut self, interval: u8) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let attributes = match self.transfer_type {
+            TransferType::Control => 0,
+            TransferType::Isochronous => 1,
+            TransferType::Bulk => 2,
+            TransferType::Interrupt => 3,
+        };
+        bytes.extend_from_slice(&[7, descriptor_type::ENDPOINT, self.address, attributes]);
+        bytes.extend_from_slice(&self.max_packet_size.to_le_bytes());
+        bytes.push(self.interval);
+    }
+}
+
+/// The strings of a device, and the languages they're in.
+///
+/// Descriptors refer to strings by index; index 0 lists the supported languages.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct StringTable {
+    languages: Vec<u16>,
+    strings: Vec<Vec<String>>,
+}
+
+impl StringTable {
+    /// Make a table of strings in the given languages, such as 0x0409 for US English.
+    pub fn new(languages: &[u16]) -> Self {
+        Self {
+            languages: languages.to_vec(),
+            strings: Vec::new(),
+        }
+    }
+
+    /// Add a string, given in each of the table's languages, returning its index.
+    ///
+    /// Returns `None` if the wrong number of translations is given or the table is full.
+    pub fn add(&mut self, translations: &[&str]) -> Option<u8> {
+        if translations.len() != self.languages.len() || self.strings.len() >= 255 {
+            return None;
+        }
+        self.strings
+            .push(translations.iter().map(|string| String::from(*string)).collect());
+        Some(self.strings.len() as u8)
+    }
+
+    /// Encode the string descriptor at an index in a language.
+    ///
+    /// Index 0 is the list of languages, whatever `language` is. Returns `None` for unknown
+    /// indices and languages.
+    pub fn to_bytes(&self, index: u8, language: u16) -> Option<Vec<u8>> {
+        // Longer strings can't be described; cut them off.
+        const MAX_UNITS: usize = 126;
+
+        let units: Vec<u16> = if index == 0 {
+            self.languages.iter().copied().take(MAX_UNITS).collect()
+        } else {
+            let translation = self.languages.iter().position(|current| *current == language)?;
+            // Cut between characters, so a surrogate pair isn't split.
+            let mut units = Vec::new();
+            for c in self.strings.get(usize::from(index) - 1)?[translation].chars() {
+                let mut buffer = [0; 2];
+                let encoded = c.encode_utf16(&mut buffer);
+                if units.len() + encoded.len() > MAX_UNITS {
+                    break;
+                }
+                units.extend_from_slice(encoded);
+            }
+            units
+        };
+
+        let mut bytes = vec![(2 + units.len() * 2) as u8, descriptor_type::STRING];
+        for unit in units {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        Some(bytes)
+    }
+}
+
+/// An iterator over the descriptors packed in a buffer, from `walk`.
+#[derive(Clone, Debug)]
+pub struct Walk<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let length = usize::from(*self.rest.first()?);
+        if length < 2 || length > self.rest.len() {
+            self.rest = &[];
+            return None;
+        }
+        let (descriptor, rest) = self.rest.split_at(length);
+        self.rest = rest;
+        Some(descriptor)
+    }
+}
+
+/// Iterate over the descriptors packed in `bytes`, each starting with its length and type.
+///
+/// The walk ends at the first descriptor whose length is too short or runs past the end.
+pub fn walk(bytes: &[u8]) -> Walk<'_> {
+    Walk { rest: bytes }
+}
+
+/// An interface association descriptor, grouping the interfaces of one function.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InterfaceAssociation {
+    /// The number of the function's first interface (`bFirstInterface`).
+    pub first_interface: u8,
+    /// How many consecutive interfaces the function has (`bInterfaceCount`).
+    pub interface_count: u8,
+    /// The function class code (`bFunctionClass`).
+    pub class: u8,
+    /// The function subclass code (`bFunctionSubClass`).
+    pub subclass: u8,
+    /// The function protocol code (`bFunctionProtocol`).
+    pub protocol: u8,
+}
+
+impl InterfaceAssociation {
+    /// Is an interface part of the function?
+    pub fn contains(&self, interface: u8) -> bool {
+        interface >= self.first_interface
+            && u16::from(interface)
+                < u16::from(self.first_interface) + u16::from(self.interface_count)
+    }
+}
+
+/// List the interface association descriptors of the configuration whose value is
+/// `configuration`, out of `bytes` holding one or more configurations with their descriptors.
+pub fn interface_associations(bytes: &[u8], configuration: u8) -> Vec<InterfaceAssociation> {
+    let mut current = None;
+    let mut associations = Vec::new();
+    for descriptor in walk(bytes) {
+        match descriptor[1] {
+            descriptor_type::CONFIGURATION if descriptor.len() >= 9 => {
+                current = Some(descriptor[5])
+            }
+            descriptor_type::INTERFACE_ASSOCIATION
+                if descriptor.len() >= 8 && current == Some(configuration) =>
+            {
+                associations.push(InterfaceAssociation {
+                    first_interface: descriptor[2],
+                    interface_count: descriptor[3],
+                    class: descriptor[4],
+                    subclass: descriptor[5],
+                    protocol: descriptor[6],
+                });
+            }
+            _ => {}
+        }
+    }
+    associations
+}
+
+/// List the class, subclass and protocol of every interface descriptor in `bytes`, across all
+/// configurations and alternate settings.
+///
+/// Unlike the interfaces in sysfs, these are known before the device is configured, such as
+/// while it waits to be authorized.
+pub fn interface_classes(bytes: &[u8]) -> Vec<(u8, u8, u8)> {
+    walk(bytes)
+        .filter(|descriptor| descriptor[1] == descriptor_type::INTERFACE && descriptor.len() >= 9)
+        .map(|descriptor| (descriptor[5], descriptor[6], descriptor[7]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A modem's descriptors as sysfs has them: the device descriptor, then a configuration with
+    // a CDC ACM function grouped by an interface association and a mass storage interface, then
+    // a second configuration with a single vendor-specific interface.
+    #[rustfmt::skip]
+    const MODEM: &[u8] = &[
+        0x12, 0x01, 0x00, 0x02, 0xef, 0x02, 0x01, 0x40, 0x6b, 0x1d, 0x04, 0x01, 0x00, 0x01, 0x01,
+        0x02, 0x03, 0x02,
+        // Configuration 1.
+        0x09, 0x02, 0x54, 0x00, 0x03, 0x01, 0x00, 0x80, 0x32,
+        0x08, 0x0b, 0x00, 0x02, 0x02, 0x02, 0x01, 0x00,
+        0x09, 0x04, 0x00, 0x00, 0x01, 0x02, 0x02, 0x01, 0x00,
+        0x05, 0x24, 0x00, 0x10, 0x01,
+        0x07, 0x05, 0x83, 0x03, 0x08, 0x00, 0x10,
+        0x09, 0x04, 0x01, 0x00, 0x02, 0x0a, 0x00, 0x00, 0x00,
+        0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00,
+        0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00,
+        0x09, 0x04, 0x02, 0x00, 0x02, 0x08, 0x06, 0x50, 0x00,
+        0x07, 0x05, 0x84, 0x02, 0x00, 0x02, 0x00,
+        0x07, 0x05, 0x03, 0x02, 0x00, 0x02, 0x00,
+        // Configuration 2.
+        0x09, 0x02, 0x12, 0x00, 0x01, 0x02, 0x00, 0x80, 0x32,
+        0x09, 0x04, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn walk_splits_descriptors() {
+        let types: Vec<u8> = walk(MODEM).map(|descriptor| descriptor[1]).collect();
+        assert_eq!(types, [1, 2, 0x0b, 4, 0x24, 5, 4, 5, 5, 4, 5, 5, 2, 4]);
+        assert_eq!(walk(MODEM).map(<[u8]>::len).sum::<usize>(), MODEM.len());
+    }
+
+    #[test]
+    fn walk_stops_at_malformed_descriptors() {
+        // Cut off in the middle of the first configuration descriptor.
+        assert_eq!(walk(&MODEM[..22]).count(), 1);
+        // A length below 2 would never advance.
+        assert_eq!(walk(&[0x00, 0x04, 0x09]).count(), 0);
+        assert_eq!(walk(&[0x01, 0x04]).count(), 0);
+        assert_eq!(walk(&[]).count(), 0);
+    }
+
+    #[test]
+    fn interface_associations_of_a_configuration() {
+        let associations = interface_associations(MODEM, 1);
+        assert_eq!(
+            associations,
+            [InterfaceAssociation {
+                first_interface: 0,
+                interface_count: 2,
+                class: 0x02,
+                subclass: 0x02,
+                protocol: 0x01,
+            }],
+        );
+        assert!(associations[0].contains(0));
+        assert!(associations[0].contains(1));
+        assert!(!associations[0].contains(2));
+        assert!(interface_associations(MODEM, 2).is_empty());
+        assert!(interface_associations(MODEM, 3).is_empty());
+    }
+
+    #[test]
+    fn interface_classes_of_every_configuration() {
+        assert_eq!(
+            interface_classes(MODEM),
+            [
+                (0x02, 0x02, 0x01),
+                (0x0a, 0x00, 0x00),
+                (0x08, 0x06, 0x50),
+                (0xff, 0x00, 0x00),
+            ],
+        );
+    }
+
+    #[test]
+    fn association_reaching_past_the_last_interface() {
+        let association = InterfaceAssociation {
+            first_interface: 0xfe,
+            interface_count: 4,
+            class: 0,
+            subclass: 0,
+            protocol: 0,
+        };
+        assert!(association.contains(0xff));
+        assert!(!association.contains(0xfd));
+    }
+
+    #[test]
+    fn device_descriptor() {
+        let device = DeviceDescriptor::new(VendorId(0x1d6b), ProductId(0x0104))
+            .class(0xef, 0x02, 0x01)
+            .device_version(0x0100)
+            .strings(1, 2, 3)
+            .num_configurations(2);
+        assert_eq!(device.to_bytes(), &MODEM[..18]);
+    }
+
+    #[test]
+    fn configuration_total_length() {
+        let configuration = ConfigurationDescriptor::new(1)
+            .self_powered()
+            .max_power(500)
+            .interface(
+                InterfaceDescriptor::new(0, 0x03, 0x01, 0x01)
+                    .extra(&[0x09, 0x21, 0x11, 0x01, 0x00, 0x01, 0x22, 0x3f, 0x00])
+                    .endpoint(
+                        EndpointDescriptor::new(0x81, TransferType::Interrupt, 8).interval(10),
+                    ),
+            )
+            .interface(InterfaceDescriptor::new(0, 0x03, 0x01, 0x01).alternate_setting(1));
+        let bytes = configuration.to_bytes().unwrap();
+
+        // wTotalLength covers everything, and alternate settings don't count as interfaces.
+        assert_eq!(usize::from(u16::from_le_bytes([bytes[2], bytes[3]])), bytes.len());
+        assert_eq!(bytes.len(), 9 + 9 + 9 + 7 + 9);
+        assert_eq!(&bytes[4..9], &[1, 1, 0, 0xc0, 250]);
+        assert_eq!(&bytes[27..34], &[7, 5, 0x81, 3, 8, 0, 10]);
+        let types: Vec<u8> = walk(&bytes).map(|descriptor| descriptor[1]).collect();
+        assert_eq!(types, [2, 4, 0x21, 5, 4]);
+        assert_eq!(interface_classes(&bytes), [(3, 1, 1), (3, 1, 1)]);
+
+        let wakeup = ConfigurationDescriptor::new(2).remote_wakeup().max_power(1000).to_bytes();
+        assert_eq!(wakeup.unwrap(), [9, 2, 9, 0, 0, 2, 0, 0xa0, 0xff]);
+
+        // Too much to describe in wTotalLength.
+        let oversized = ConfigurationDescriptor::new(1)
+            .interface(InterfaceDescriptor::new(0, 0xff, 0, 0).extra(&[0; 0x10000]));
+        assert_eq!(oversized.to_bytes(), None);
+    }
+
+    #[test]
+    fn string_table() {
+        let mut strings = StringTable::new(&[0x0409, 0x0407]);
+        assert_eq!(strings.add(&["Modem"]), None);
+        assert_eq!(strings.add(&["Modem", "Modem"]), Some(1));
+        assert_eq!(strings.add(&["Red", "Rot"]), Some(2));
+
+        assert_eq!(strings.to_bytes(0, 0), Some(vec![6, 3, 0x09, 0x04, 0x07, 0x04]));
+        assert_eq!(strings.to_bytes(2, 0x0407), Some(vec![8, 3, b'R', 0, b'o', 0, b't', 0]));
+        assert_eq!(strings.to_bytes(2, 0x040c), None);
+        assert_eq!(strings.to_bytes(3, 0x0409), None);
+
+        // Characters outside the BMP take two UTF-16 units.
+        let mut strings = StringTable::new(&[0x0409]);
+        strings.add(&["\u{1f50c}"]).unwrap();
+        assert_eq!(strings.to_bytes(1, 0x0409).unwrap(), [6, 3, 0x3d, 0xd8, 0x0c, 0xdd]);
+
+        // Strings are cut off at the longest a descriptor can hold.
+        let mut strings = StringTable::new(&[0x0409]);
+        strings.add(&[&"x".repeat(200)]).unwrap();
+        let bytes = strings.to_bytes(1, 0x0409).unwrap();
+        assert_eq!((bytes.len(), bytes[0]), (254, 254));
+
+        // A character outside the BMP that doesn't fit is dropped whole, not half of it.
+        let mut strings = StringTable::new(&[0x0409]);
+        strings.add(&[&format!("{}\u{1f50c}", "x".repeat(125))]).unwrap();
+        let bytes = strings.to_bytes(1, 0x0409).unwrap();
+        assert_eq!((bytes.len(), bytes[0]), (252, 252));
+        assert_eq!(&bytes[250..], [b'x', 0]);
+
+        let mut strings = StringTable::new(&[0x0409]);
+        for _ in 0..255 {
+            strings.add(&["x"]).unwrap();
+        }
+        assert_eq!(strings.add(&["x"]), None);
+    }
+}
@@ -0,0 +1,252 @@
+//! Composing USB gadgets through configfs, for machines with a device controller (UDC).
+//!
+//! A gadget is built from functions, such as `acm` serial ports, `ecm` network links or `ffs`
+//! FunctionFS endpoints, grouped into configurations. Once bound to a UDC, it shows up as a
+//! device on the host it's plugged into. This needs root and the `libcomposite` module.
+use std::{
+    fs,
+    io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use crate::{ProductId, VendorId};
+
+// Where configfs is mounted.
+const CONFIGFS: &str = "/sys/kernel/config";
+
+/// A gadget in configfs.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Gadget {
+    path: PathBuf,
+}
+
+/// A function of a gadget.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Function {
+    path: PathBuf,
+}
+
+/// A configuration of a gadget.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Config {
+    path: PathBuf,
+}
+
+// Check a name that is joined into a configfs path, so it can't lead out of the gadget's
+// directory.
+fn check_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid configfs name"));
+    }
+    Ok(())
+}
+
+// configfs attributes take numbers in hex with a 0x prefix.
+fn write_hex(path: &Path, value: u16) -> io::Result<()> {
+    fs::write(path, format!("0x{:04x}", value))
+}
+
+impl Gadget {
+    /// Create a gadget, or open it if it already exists.
+    ///
+    /// Names that aren't a single path component fail with `io::ErrorKind::InvalidInput`, as do
+    /// those of functions and configurations.
+    pub fn create(name: &str) -> io::Result<Self> {
+        Self::create_in(Path::new(CONFIGFS), name)
+    }
+
+    /// Create a gadget in configfs mounted somewhere other than `/sys/kernel/config`.
+    pub fn create_in(configfs: &Path, name: &str) -> io::Result<Self> {
+        check_name(name)?;
+        let path = configfs.join("usb_gadget").join(name);
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    /// List the device controllers gadgets can be bound to.
+    pub fn udcs() -> io::Result<Vec<String>> {
+        Self::udcs_in(Path::new("/sys"))
+    }
+
+    /// List the device controllers gadgets can be bound to, with sysfs mounted somewhere other
+    /// than `/sys`.
+    pub fn udcs_in(sysfs: &Path) -> io::Result<Vec<String>> {
+        let mut udcs = Vec::new();
+        for entry in fs::read_dir(sysfs.join("class/udc"))? {
+            udcs.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        udcs.sort();
+        Ok(udcs)
+    }
+
+    /// The gadget's directory in configfs.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the vendor and product IDs the gadget presents.
+    pub fn set_ids(&self, vendor_id: VendorId, product_id: ProductId) -> io::Result<()> {
+        write_hex(&self.path.join("idVendor"), vendor_id.0)?;
+        write_hex(&self.path.join("idProduct"), product_id.0)
+    }
+
+    /// Set the device release number (`bcdDevice`).
+    pub fn set_device_version(&self, bcd: u16) -> io::Result<()> {
+        write_hex(&self.path.join("bcdDevice"), bcd)
+    }
+
+    /// Set the USB specification release number (`bcdUSB`), e.g. 0x0200 for USB 2.0.
+    pub fn set_usb_version(&self, bcd: u16) -> io::Result<()> {
+        write_hex(&self.path.join("bcdUSB"), bcd)
+    }
+
+    /// Set the device class, subclass and protocol.
+    pub fn set_class(&self, class: u8, subclass: u8, protocol: u8) -> io::Result<()> {
+        write_hex(&self.path.join("bDeviceClass"), class.into())?;
+        write_hex(&self.path.join("bDeviceSubClass"), subclass.into())?;
+        write_hex(&self.path.join("bDeviceProtocol"), protocol.into())
+    }
+
+    /// Set the manufacturer, product and serial number strings in a language, such as 0x409 for
+    /// US English.
+    pub fn set_strings(
+        &self,
+        language: u16,
+        manufacturer: &str,
+        product: &str,
+        serial_number: &str,
+    ) -> io::Result<()> {
+        let strings = self.path.join(format!("strings/0x{:x}", language));
+        fs::create_dir_all(&strings)?;
+        fs::write(strings.join("manufacturer"), manufacturer)?;
+        fs::write(strings.join("product"), product)?;
+        fs::write(strings.join("serialnumber"), serial_number)
+    }
+
+    /// Add a function of a kind, such as `acm`, `ecm`, `mass_storage` or `ffs`.
+    ///
+    /// The instance name tells functions of the same kind apart. For `ffs`, it's also the name to
+    /// mount FunctionFS with.
+    pub fn add_function(&self, kind: &str, instance: &str) -> io::Result<Function> {
+        check_name(kind)?;
+        check_name(instance)?;
+        let path = self.path.join("functions").join(format!("{}.{}", kind, instance));
+        fs::create_dir_all(&path)?;
+        Ok(Function { path })
+    }
+
+    /// Add a configuration, numbered from 1.
+    pub fn add_config(&self, name: &str, number: u8) -> io::Result<Config> {
+        check_name(name)?;
+        let path = self.path.join("configs").join(format!("{}.{}", name, number));
+        fs::create_dir_all(&path)?;
+        Ok(Config { path })
+    }
+
+    /// Bind the gadget to a device controller, making it visible to the host.
+    pub fn bind(&self, udc: &str) -> io::Result<()> {
+        fs::write(self.path.join("UDC"), udc)
+    }
+
+    /// Unbind the gadget from its device controller.
+    pub fn unbind(&self) -> io::Result<()> {
+        fs::write(self.path.join("UDC"), "\n")
+    }
+
+    /// Unbind and delete the gadget, with its configurations and functions.
+    ///
+    /// configfs only lets directories be removed in the reverse order of their creation, which
+    /// this takes care of.
+    pub fn remove(self) -> io::Result<()> {
+        // Unbinding fails if the gadget isn't bound.
+        let udc = fs::read_to_string(self.path.join("UDC"))?;
+        if !udc.trim().is_empty() {
+            self.unbind()?;
+        }
+
+        for config in fs::read_dir(self.path.join("configs"))? {
+            let config = config?.path();
+            for entry in fs::read_dir(&config)? {
+                let entry = entry?;
+                if entry.file_type()?.is_symlink() {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+            remove_children(&config.join("strings"))?;
+            fs::remove_dir(&config)?;
+        }
+        remove_children(&self.path.join("functions"))?;
+        remove_children(&self.path.join("strings"))?;
+        fs::remove_dir(&self.path)
+    }
+}
+
+// Remove the subdirectories of a configfs group.
+fn remove_children(path: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+impl Function {
+    /// The function's directory in configfs.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set an attribute of the function, such as `dev_addr` of `ecm` or `lun.0/file` of
+    /// `mass_storage`.
+    pub fn set_attribute(&self, name: &str, value: &str) -> io::Result<()> {
+        fs::write(self.path.join(name), value)
+    }
+}
+
+impl Config {
+    /// The configuration's directory in configfs.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the most power the gadget draws from the bus in this configuration, in mA.
+    pub fn set_max_power(&self, milliamps: u16) -> io::Result<()> {
+        fs::write(self.path.join("MaxPower"), milliamps.to_string())
+    }
+
+    /// Set the configuration's description in a language.
+    pub fn set_string(&self, language: u16, configuration: &str) -> io::Result<()> {
+        let strings = self.path.join(format!("strings/0x{:x}", language));
+        fs::create_dir_all(&strings)?;
+        fs::write(strings.join("configuration"), configuration)
+    }
+
+    /// Add a function to the configuration.
+    pub fn link(&self, function: &Function) -> io::Result<()> {
+        let name = function.path.file_name().unwrap_or_default();
+        symlink(&function.path, self.path.join(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        for name in &["g1", "usb0", "c", ".hidden", "a..b"] {
+            assert!(check_name(name).is_ok(), "{:?}", name);
+        }
+        for name in &["", ".", "..", "../g1", "a/b", "/"] {
+            assert_eq!(
+                check_name(name).map_err(|err| err.kind()),
+                Err(io::ErrorKind::InvalidInput),
+                "{:?}",
+                name,
+            );
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Building HID report descriptors.
+//!
+//! A report descriptor tells the host what the reports of a HID interface contain. It's a list of
+//! items: global ones set up state such as the usage page and report size, local ones name the
+//! usages of the next main item, and main items declare inputs, outputs and features grouped into
+//! collections.
+use std::convert::TryFrom;
+
+use crate::setup::descriptor_type;
+
+/// Flags of input, output and feature items. Each flag's opposite is the default.
+pub mod flags {
+    /// The field is constant padding rather than data.
+    pub const CONSTANT: u32 = 0x001;
+    /// Each usage has its own field, rather than the fields being an array of usage indices.
+    pub const VARIABLE: u32 = 0x002;
+    /// Values are relative to the previous report, such as mouse movement.
+    pub const RELATIVE: u32 = 0x004;
+    /// Values wrap around past the logical extremes.
+    pub const WRAP: u32 = 0x008;
+    /// Values aren't linear in what's measured.
+    pub const NON_LINEAR: u32 = 0x010;
+    /// The control doesn't return to a preferred state when not being acted on.
+    pub const NO_PREFERRED: u32 = 0x020;
+    /// The control has a state in which it sends no meaningful data.
+    pub const NULL_STATE: u32 = 0x040;
+    /// For outputs and features, the value may change without the host setting it.
+    pub const VOLATILE: u32 = 0x080;
+    /// The field is a fixed-size stream of bytes.
+    pub const BUFFERED_BYTES: u32 = 0x100;
+}
+
+/// The kind of a collection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Collection {
+    /// Items describing a physical measurement point, such as a sensor.
+    Physical,
+    /// Items making up one application, such as a keyboard or a mouse.
+    Application,
+    /// Items belonging together, such as the parts of a buffer.
+    Logical,
+    /// Items making up a report.
+    Report,
+    /// An array of selector usages.
+    NamedArray,
+    /// Items whose purpose is set by the collection's usage.
+    UsageSwitch,
+    /// Items modifying the meaning of a usage.
+    UsageModifier,
+}
+
+// Item types.
+const MAIN: u8 = 0;
+const GLOBAL: u8 = 1;
+const LOCAL: u8 = 2;
+
+/// A HID report descriptor under construction.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ReportDescriptor {
+    bytes: Vec<u8>,
+}
+
+impl ReportDescriptor {
+    /// Start an empty report descriptor.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a short item with data in as few bytes as it fits in.
+    fn item(self, item_type: u8, tag: u8, data: &[u8]) -> Self {
+        let mut length = data.len();
+        while length > 0 && data[length - 1] == 0 {
+            length -= 1;
+        }
+        self.push(item_type, tag, data, length)
+    }
+
+    // Add a short item with signed data, keeping the sign bit intact.
+    fn signed_item(self, item_type: u8, tag: u8, value: i32) -> Self {
+        let length = if i8::try_from(value).is_ok() {
+            1
+        } else if i16::try_from(value).is_ok() {
+            2
+        } else {
+            4
+        };
+        self.push(item_type, tag, &value.to_le_bytes(), length)
+    }
+
+    fn push(mut self, item_type: u8, tag: u8, data: &[u8], length: usize) -> Self {
+        // Short items hold 0, 1, 2 or 4 bytes of data; size code 3 means 4.
+        let (length, size) = match length {
+            0 => (0, 0),
+            1 => (1, 1),
+            2 => (2, 2),
+            _ => (4, 3),
+        };
+        self.bytes.push(tag << 4 | item_type << 2 | size);
+        self.bytes.extend_from_slice(&data[..length]);
+        self
+    }
+
+    /// Set the usage page of the usages that follow, such as 0x01 for Generic Desktop.
+    pub fn usage_page(self, page: u16) -> Self {
+        self.item(GLOBAL, 0x0, &page.to_le_bytes())
+    }
+
+    /// Set the smallest value the following fields report.
+    pub fn logical_minimum(self, minimum: i32) -> Self {
+        self.signed_item(GLOBAL, 0x1, minimum)
+    }
+
+    /// Set the largest value the following fields report.
+    pub fn logical_maximum(self, maximum: i32) -> Self {
+        self.signed_item(GLOBAL, 0x2, maximum)
+    }
+
+    /// Set the physical quantity the logical minimum stands for.
+    pub fn physical_minimum(self, minimum: i32) -> Self {
+        self.signed_item(GLOBAL, 0x3, minimum)
+    }
+
+    /// Set the physical quantity the logical maximum stands for.
+    pub fn physical_maximum(self, maximum: i32) -> Self {
+        self.signed_item(GLOBAL, 0x4, maximum)
+    }
+
+    /// Set the power of ten the physical quantities are scaled by.
+    pub fn unit_exponent(self, exponent: i8) -> Self {
+        // Encoded as a 4-bit two's complement nibble.
+        self.item(GLOBAL, 0x5, &[exponent as u8 & 0x0f])
+    }
+
+    /// Set the unit of the physical quantities, encoded as the HID specification describes.
+    pub fn unit(self, unit: u32) -> Self {
+        self.item(GLOBAL, 0x6, &unit.to_le_bytes())
+    }
+
+    /// Set the size of each of the following fields, in bits.
+    pub fn report_size(self, bits: u32) -> Self {
+        self.item(GLOBAL, 0x7, &bits.to_le_bytes())
+    }
+
+    /// Start a report with its own ID, prefixed to the report's data.
+    pub fn report_id(self, id: u8) -> Self {
+        self.item(GLOBAL, 0x8, &[id])
+    }
+
+    /// Set how many fields the following main item declares.
+    pub fn report_count(self, count: u32) -> Self {
+        self.item(GLOBAL, 0x9, &count.to_le_bytes())
+    }
+
+    /// Save the global state, to restore with `pop_state`.
+    pub fn push_state(self) -> Self {
+        self.item(GLOBAL, 0xa, &[])
+    }
+
+    /// Restore the global state saved by `push_state`.
+    pub fn pop_state(self) -> Self {
+        self.item(GLOBAL, 0xb, &[])
+    }
+
+    /// Name the usage of the next field or collection, on the current usage page.
+    pub fn usage(self, usage: u16) -> Self {
+        self.item(LOCAL, 0x0, &usage.to_le_bytes())
+    }
+
+    /// Name the usages of the next fields with a range, starting from this one.
+    pub fn usage_minimum(self, usage: u16) -> Self {
+        self.item(LOCAL, 0x1, &usage.to_le_bytes())
+    }
+
+    /// Name the usages of the next fields with a range, ending at this one.
+    pub fn usage_maximum(self, usage: u16) -> Self {
+        self.item(LOCAL, 0x2, &usage.to_le_bytes())
+    }
+
+    /// Declare input fields, sent from the device to the host, with the given `flags`.
+    pub fn input(self, flags: u32) -> Self {
+        self.item(MAIN, 0x8, &flags.to_le_bytes())
+    }
+
+    /// Declare output fields, sent from the host to the device, such as keyboard LEDs.
+    pub fn output(self, flags: u32) -> Self {
+        self.item(MAIN, 0x9, &flags.to_le_bytes())
+    }
+
+    /// Declare feature fields, read and written by the host through control requests.
+    pub fn feature(self, flags: u32) -> Self {
+        self.item(MAIN, 0xb, &flags.to_le_bytes())
+    }
+
+    /// Open a collection; the items up to the matching `end_collection` belong to it.
+    pub fn collection(self, collection: Collection) -> Self {
+        let kind = match collection {
+            Collection::Physical => 0x00,
+            Collection::Application => 0x01,
+            Collection::Logical => 0x02,
+            Collection::Report => 0x03,
+            Collection::NamedArray => 0x04,
+            Collection::UsageSwitch => 0x05,
+            Collection::UsageModifier => 0x06,
+        };
+        // Collections spell out their kind, even physical ones whose kind is 0.
+        self.push(MAIN, 0xa, &[kind], 1)
+    }
+
+    /// Close the most recently opened collection.
+    pub fn end_collection(self) -> Self {
+        self.item(MAIN, 0xc, &[])
+    }
+
+    /// The bytes of the report descriptor.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Encode the HID descriptor of an interface using this report descriptor, to add to its
+    /// interface descriptor with `descriptor::InterfaceDescriptor::extra`.
+    pub fn hid_descriptor(&self) -> Vec<u8> {
+        let [length_low, length_high] = (self.bytes.len() as u16).to_le_bytes();
+        vec![
+            9,
+            descriptor_type::HID,
+            // HID 1.11.
+            0x11,
+            0x01,
+            // Not localized.
+            0,
+            1,
+            descriptor_type::HID_REPORT,
+            length_low,
+            length_high,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_mouse() {
+        // The example from appendix E.10 of the HID specification.
+        #[rustfmt::skip]
+        const MOUSE: &[u8] = &[
+            0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01,
+            0x29, 0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01,
+            0x75, 0x05, 0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f,
+            0x75, 0x08, 0x95, 0x02, 0x81, 0x06, 0xc0, 0xc0,
+        ];
+        let mouse = ReportDescriptor::new()
+            .usage_page(0x01)
+            .usage(0x02)
+            .collection(Collection::Application)
+            .usage(0x01)
+            .collection(Collection::Physical)
+            .usage_page(0x09)
+            .usage_minimum(1)
+            .usage_maximum(3)
+            .logical_minimum(0)
+            .logical_maximum(1)
+            .report_count(3)
+            .report_size(1)
+            .input(flags::VARIABLE)
+            .report_count(1)
+            .report_size(5)
+            .input(flags::CONSTANT)
+            .usage_page(0x01)
+            .usage(0x30)
+            .usage(0x31)
+            .logical_minimum(-127)
+            .logical_maximum(127)
+            .report_size(8)
+            .report_count(2)
+            .input(flags::VARIABLE | flags::RELATIVE)
+            .end_collection()
+            .end_collection();
+        assert_eq!(mouse.as_bytes(), MOUSE);
+        assert_eq!(mouse.hid_descriptor(), [9, 0x21, 0x11, 0x01, 0, 1, 0x22, 50, 0]);
+    }
+
+    #[test]
+    fn item_sizes() {
+        let items = ReportDescriptor::new()
+            .usage_page(0xff00)
+            .logical_maximum(255)
+            .logical_minimum(-32768)
+            .physical_maximum(70000)
+            .unit_exponent(-2)
+            .report_size(0)
+            .push_state()
+            .pop_state()
+            .collection(Collection::Physical)
+            .feature(flags::BUFFERED_BYTES);
+        assert_eq!(
+            items.as_bytes(),
+            [
+                0x06, 0x00, 0xff, 0x26, 0xff, 0x00, 0x16, 0x00, 0x80, 0x47, 0x70, 0x11, 0x01, 0x00,
+                0x55, 0x0e, 0x74, 0xa4, 0xb4, 0xa1, 0x00, 0xb2, 0x00, 0x01,
+            ]
+            .as_ref(),
+        );
+    }
+}
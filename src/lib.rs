@@ -4,18 +4,57 @@
 
 use std::{
     cell::RefCell,
+    collections::{HashMap, VecDeque},
     convert::TryFrom,
     error::Error as StdError,
     fmt,
+    fs,
     io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use tokio::prelude::*;
+use futures::task::{self, Task};
+use tokio::{prelude::*, timer::Delay};
+
+use crate::{
+    descriptor::TransferType,
+    setup::{feature, DeviceStatus, Direction, InterfaceStatus, Recipient, SetupPacket, TestMode},
+};
 
 #[cfg(target_os = "linux")]
 #[path = "linux.rs"]
 mod os;
 
+pub mod clock;
+pub mod descriptor;
+pub mod gadget;
+pub mod hid;
+pub mod modeswitch;
+pub mod policy;
+pub mod quirks;
+pub mod reconnect;
+pub mod setup;
+pub mod uevent;
+
+#[cfg(feature = "audit")]
+pub mod audit;
+
+#[cfg(feature = "json")]
+pub mod registry;
+
+#[cfg(feature = "usb-ids")]
+mod usb_ids;
+
+#[cfg(feature = "usbmon")]
+pub mod usbmon;
+
 #[cfg(not(target_os = "linux"))]
 compile_error!("Sorry, usb-async has not been ported to your platform yet.");
 
@@ -28,6 +67,24 @@ pub enum Error {
     NotConnected,
     /// An io::Error occurred.
     Io(io::ErrorKind),
+    /// The endpoint stalled (`EPIPE`).
+    ///
+    /// The device rejected the request, or halted the endpoint because of an error in the
+    /// protocol running over it. Clearing the halt with `InterfaceHandle::clear_halt` usually
+    /// recovers a bulk or interrupt endpoint; a stalled control request needs no recovery.
+    Stall,
+    /// The device sent more data than expected (`EOVERFLOW`).
+    ///
+    /// This is babble: the device sent a packet larger than the endpoint's maximum packet size
+    /// or than the buffer. Reading with a buffer that's a multiple of the maximum packet size
+    /// avoids the latter; otherwise the device is misbehaving and needs a reset.
+    Overflow,
+    /// The transfer failed on the wire (`EPROTO` or `EILSEQ`).
+    ///
+    /// The host controller saw a CRC, bit stuffing or handshake error, or got no response at
+    /// all. This usually means a bad cable, a failing device, or one that is being unplugged;
+    /// retrying rarely helps, and a reset may.
+    Protocol,
 }
 
 impl From<os::UsbError> for Error {
@@ -36,6 +93,9 @@ impl From<os::UsbError> for Error {
             os::UsbError::InvalidId => Error::InvalidId,
             os::UsbError::NotConnected => Error::NotConnected,
             os::UsbError::Io(io) => Error::Io(io),
+            os::UsbError::Stall => Error::Stall,
+            os::UsbError::Overflow => Error::Overflow,
+            os::UsbError::Protocol => Error::Protocol,
         }
     }
 }
@@ -46,16 +106,108 @@ impl fmt::Display for Error {
             Error::InvalidId => write!(f, "an invalid device was specified"),
             Error::NotConnected => write!(f, "the specified device is not connected"),
             Error::Io(io) => write!(f, "an io error occurred: {:?}", io),
+            Error::Stall => write!(f, "the endpoint stalled"),
+            Error::Overflow => write!(f, "the device sent more data than expected"),
+            Error::Protocol => write!(f, "a USB protocol error occurred"),
         }
     }
 }
 
 impl StdError for Error {}
 
+/// How `Context::claim_interface_with` claims an interface.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ClaimOptions {
+    disconnect: Option<DisconnectDriver>,
+}
+
+impl ClaimOptions {
+    /// Take the interface from the kernel driver bound to it, if `driver` matches it.
+    ///
+    /// The kernel disconnects the driver and claims the interface in one step
+    /// (`USBDEVFS_DISCONNECT_CLAIM`), so unlike calling `Context::unbind_driver` first, the
+    /// driver can't probe the interface again in between. A driver `driver` doesn't match keeps
+    /// the interface busy. This needs root.
+    pub fn disconnect_driver(mut self, driver: DisconnectDriver) -> Self {
+        self.disconnect = Some(driver);
+        self
+    }
+}
+
+/// Which kernel drivers `ClaimOptions::disconnect_driver` takes an interface from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DisconnectDriver {
+    /// Whatever driver is bound.
+    Any,
+    /// Only the named driver.
+    Only(String),
+    /// Any driver but the named one.
+    Except(String),
+}
+
+/// Why `Context::claim_interface` failed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ClaimError {
+    /// Something else has the interface, or the whole device (`EBUSY`), or another process
+    /// holds the device locked (see `ContextBuilder::lock_devices`).
+    Busy {
+        /// The kernel driver bound to the interface, if any. `Context::unbind_driver` detaches
+        /// it, or `ClaimOptions::disconnect_driver` takes the interface from it.
+        driver: Option<String>,
+        /// The processes with the device's usbfs node open, such as one holding the interface.
+        /// Processes of other users can only be seen by root.
+        users: Vec<Process>,
+    },
+    /// Any other error.
+    Other(Error),
+}
+
+impl From<Error> for ClaimError {
+    fn from(err: Error) -> Self {
+        ClaimError::Other(err)
+    }
+}
+
+impl From<os::UsbError> for ClaimError {
+    fn from(err: os::UsbError) -> Self {
+        ClaimError::Other(err.into())
+    }
+}
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClaimError::Busy { driver, users } => {
+                write!(f, "the interface is busy")?;
+                if let Some(driver) = driver {
+                    write!(f, ", bound to driver {}", driver)?;
+                }
+                for user in users {
+                    write!(f, ", open in process {}", user.pid)?;
+                    if let Some(command) = &user.command {
+                        write!(f, " ({})", command)?;
+                    }
+                }
+                Ok(())
+            }
+            ClaimError::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl StdError for ClaimError {}
+
 /// A handle to a USB device.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct Id(os::Id);
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for Id {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32((self.0).0)
+    }
+}
+
 impl From<Id> for os::Id {
     fn from(id: Id) -> Self {
         id.0
@@ -68,13 +220,155 @@ impl From<os::Id> for Id {
     }
 }
 
+macro_rules! usb_id {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        ///
+        /// It formats as four hex digits, like `lsusb` shows it, and parses from hex.
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(feature = "serde", serde(transparent))]
+        pub struct $name(pub u16);
+
+        impl From<u16> for $name {
+            fn from(id: u16) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<$name> for u16 {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{:04x}", self.0)
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIdError;
+
+            fn from_str(id: &str) -> Result<Self, ParseIdError> {
+                let id = id.strip_prefix("0x").unwrap_or(id);
+                // from_str_radix takes a sign, which IDs don't have.
+                if id.is_empty() || id.len() > 4 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(ParseIdError);
+                }
+                u16::from_str_radix(id, 16).map(Self).map_err(|_| ParseIdError)
+            }
+        }
+    };
+}
+
+usb_id! {
+    /// A USB vendor ID, assigned by the USB-IF.
+    VendorId
+}
+
+usb_id! {
+    /// A USB product ID, assigned by the vendor.
+    ProductId
+}
+
+impl VendorId {
+    /// The Linux Foundation, the vendor of the kernel's root hubs and gadget defaults.
+    pub const LINUX_FOUNDATION: Self = Self(0x1d6b);
+    /// Intel.
+    pub const INTEL: Self = Self(0x8087);
+    /// Apple.
+    pub const APPLE: Self = Self(0x05ac);
+    /// Microsoft.
+    pub const MICROSOFT: Self = Self(0x045e);
+    /// Logitech.
+    pub const LOGITECH: Self = Self(0x046d);
+}
+
+impl ProductId {
+    /// A Linux Foundation USB 1.1 root hub.
+    pub const ROOT_HUB_1_1: Self = Self(0x0001);
+    /// A Linux Foundation USB 2.0 root hub.
+    pub const ROOT_HUB_2_0: Self = Self(0x0002);
+    /// A Linux Foundation USB 3 root hub.
+    pub const ROOT_HUB_3_0: Self = Self(0x0003);
+}
+
+/// A string that isn't a vendor or product ID.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParseIdError;
+
+impl fmt::Display for ParseIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid USB ID")
+    }
+}
+
+impl StdError for ParseIdError {}
+
+/// Parse a vendor and product ID written like `lsusb` shows them, such as `1d6b:0002`.
+pub fn parse_ids(ids: &str) -> Result<(VendorId, ProductId), ParseIdError> {
+    let split = ids.find(':').ok_or(ParseIdError)?;
+    Ok((ids[..split].parse()?, ids[split + 1..].parse()?))
+}
+
 /// A USB hotplug event.
 #[derive(Copy, Clone, Debug, PartialEq, Hash)]
 pub enum Event {
     /// A USB device was plugged in.
+    ///
+    /// Each attachment is reported once, even if the kernel repeats the device's uevent. Only
+    /// devices are reported, not their interfaces, unless the context was built to match the
+    /// `usb_interface` devtype.
     Add(Id),
     /// A USB device was removed.
     Remove(Id),
+    /// A USB device changed, e.g. after being authorized or switching modes.
+    ///
+    /// The cached metadata of the device has already been refreshed.
+    Change(Id, Changes),
+    /// A hub reported an over-current condition on one of its ports, given by number.
+    ///
+    /// The event source must be a socket; polling sysfs can't see these.
+    OverCurrent(Id, u8),
+    /// An interface of a device, given by number, was registered, which is when a class driver
+    /// gets the chance to claim it.
+    ///
+    /// Only reported by contexts built with `ContextBuilder::interface_events`, and only by
+    /// socket event sources.
+    InterfaceAdd(Id, u8),
+    /// An interface of a device, given by number, went away, e.g. because the device is being
+    /// removed or switched to another configuration.
+    ///
+    /// Only reported by contexts built with `ContextBuilder::interface_events`, and only by
+    /// socket event sources.
+    InterfaceRemove(Id, u8),
+    /// Some hotplug events were missed, so the device list may be out of date.
+    ///
+    /// Consumers should call `HotplugMonitor::rescan` or check `Context::connected_devices`
+    /// rather than trusting the events received so far. This is conservative: events delivered
+    /// out of order may cause it to be reported even though nothing was lost.
+    EventsLost,
+}
+
+/// Which metadata of a device differ after an `Event::Change`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Changes {
+    /// The USB vendor ID changed.
+    pub vendor_id: bool,
+    /// The USB product ID changed.
+    pub product_id: bool,
+    /// The USB manufacturer string changed.
+    pub manufacturer_string: bool,
+    /// The USB product string changed.
+    pub product_string: bool,
 }
 
 impl TryFrom<os::Event> for Event {
@@ -84,129 +378,3085 @@ impl TryFrom<os::Event> for Event {
         match event {
             os::Event::Add(id) => Ok(Event::Add(id.into())),
             os::Event::Remove(id) => Ok(Event::Remove(id.into())),
-            os::Event::Change(_) | os::Event::Unknown => Err(()),
+            os::Event::OverCurrent(hub, port) => Ok(Event::OverCurrent(hub.into(), port)),
+            os::Event::InterfaceAdd(id, interface) => Ok(Event::InterfaceAdd(id.into(), interface)),
+            os::Event::InterfaceRemove(id, interface) => {
+                Ok(Event::InterfaceRemove(id.into(), interface))
+            }
+            os::Event::EventsLost => Ok(Event::EventsLost),
+            // The changes are filled in once the metadata has been refreshed.
+            os::Event::Change(id) => Ok(Event::Change(id.into(), Changes::default())),
+            os::Event::Unknown => Err(()),
         }
     }
 }
 
+/// Where a `HotplugMonitor` receives its events from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum EventSource {
+    /// Use udevd if it is running, otherwise kernel uevents, and poll as a last resort.
+    #[default]
+    Auto,
+    /// Events rebroadcast by the udev daemon after its rules have run.
+    ///
+    /// Devices are fully set up (permissions, udev properties) by the time their event arrives.
+    Udev,
+    /// Raw uevents straight from the kernel.
+    ///
+    /// These arrive with lower latency and don't need udevd to be running, but udev rules may
+    /// not have been applied to the device yet. The kernel only sends uevents to the initial
+    /// network namespace, so containers with their own network namespace receive nothing.
+    Kernel,
+    /// Rescan for devices every given period and report the differences.
+    ///
+    /// This works everywhere sysfs is readable, at the cost of latency and missing devices
+    /// that come and go between scans.
+    Poll(Duration),
+}
+
 /// A USB hotplug event monitor.
 pub struct HotplugMonitor<'a> {
     monitor: os::Monitor<'a>,
     context: &'a Context,
 }
 
+impl HotplugMonitor<'_> {
+    /// Retrieve the kernel sequence number (SEQNUM) of the most recent uevent seen.
+    ///
+    /// Returns `None` if no numbered event has been received yet.
+    pub fn sequence_number(&self) -> Option<u64> {
+        self.monitor.sequence_number()
+    }
+
+    /// Compare the devices present now with the ones the context knows about, and queue the
+    /// differences as events ahead of any others.
+    ///
+    /// Devices that are gone are reported removed, and new ones added. A device that was
+    /// replugged unnoticed in the meantime is told apart by its new address on the bus, and is
+    /// reported removed and added again under a new ID. This is meant for catching up after
+    /// `Event::EventsLost`.
+    pub fn rescan(&mut self) -> Result<(), Error> {
+        self.monitor.rescan().map_err(std::convert::Into::into)
+    }
+
+    /// Limit the rate of events, coalescing the ones held back.
+    ///
+    /// A limit of 0 means no limit.
+    pub fn rate_limit(self, max_per_second: u32) -> RateLimited<Self> {
+        RateLimited::new(self, max_per_second)
+    }
+}
+
 impl Stream for HotplugMonitor<'_> {
     type Item = Event;
     type Error = Error;
 
     fn poll(&mut self) -> Result<Async<Option<Event>>, Error> {
-        match self.monitor.poll() {
-            Ok(Async::Ready(Some(ev))) => {
-                match Event::try_from(ev) {
-                    Ok(ev) => {
-                        match ev {
-                            Event::Add(id) => {
-                                self.context.add(id);
-                                Ok(Async::Ready(Some(Event::Add(id))))
-                            },
-                            Event::Remove(id) => {
-                                Ok(Async::Ready(Some(Event::Remove(id))))
-                            },
-                        }
-                    },
-                    // Drop messages we don't understand.
-                    Err(()) => Ok(Async::NotReady),
+        loop {
+            match self.monitor.poll() {
+                Ok(Async::Ready(Some(ev))) => {
+                    match Event::try_from(ev) {
+                        Ok(Event::Add(id)) => {
+                            self.context.add(id);
+                            self.context.remember(Event::Add(id));
+                            return Ok(Async::Ready(Some(Event::Add(id))));
+                        },
+                        Ok(Event::Remove(id)) => {
+                            self.context.remove(id);
+                            self.context.remember(Event::Remove(id));
+                            return Ok(Async::Ready(Some(Event::Remove(id))));
+                        },
+                        Ok(Event::Change(id, _)) => {
+                            let changes = self.context.refresh(id);
+                            self.context.remember(Event::Change(id, changes));
+                            return Ok(Async::Ready(Some(Event::Change(id, changes))));
+                        },
+                        Ok(ev) => {
+                            self.context.remember(ev);
+                            return Ok(Async::Ready(Some(ev)));
+                        },
+                        // Drop messages we don't understand.
+                        Err(()) => continue,
+                    }
                 }
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => return Err(err.into()),
             }
-            Ok(Async::Ready(None)) => Ok(Async::Ready(None)),
-            Ok(Async::NotReady) => Ok(Async::NotReady),
-            Err(err) => Err(err.into()),
         }
     }
 }
 
-struct Metadata {
-    vendor_id: Option<u16>,
-    product_id: Option<u16>,
-}
+// How many events a rate limited stream holds back before giving up and reporting them lost.
+const MAX_HELD_EVENTS: usize = 1024;
 
-/// A USB context.
-pub struct Context {
-    context: os::Context,
-    metadata: RefCell<Vec<Metadata>>,
+/// A stream of hotplug events delivering at most a given number of events per second.
+///
+/// Events beyond the limit are held back and coalesced until they can be delivered: a device
+/// added and removed again is dropped altogether along with its interfaces, changes to a device
+/// are merged, and changes to a device whose addition or removal is pending are dropped. If too
+/// many events pile up, they are all replaced by `Event::EventsLost`.
+pub struct RateLimited<S> {
+    stream: S,
+    max_per_second: u32,
+    window_start: Instant,
+    delivered: u32,
+    held: VecDeque<Event>,
+    delay: Option<Delay>,
+    done: bool,
 }
 
-impl Context {
-    fn add(&self, id: Id) {
-        let vendor_id = self.context.vendor_id(id.into()).ok();
-        let product_id = self.context.product_id(id.into()).ok();
-        let metadata = Metadata {
-            vendor_id,
-            product_id,
+impl<S: Stream<Item = Event, Error = Error>> RateLimited<S> {
+    /// Limit the rate of a stream of events.
+    ///
+    /// A limit of 0 means no limit, so events are delivered as they come.
+    pub fn new(stream: S, max_per_second: u32) -> Self {
+        Self {
+            stream,
+            max_per_second,
+            window_start: Instant::now(),
+            delivered: 0,
+            held: VecDeque::new(),
+            delay: None,
+            done: false,
+        }
+    }
+
+    fn hold(&mut self, event: Event) {
+        let is_change_of = |id: Id| {
+            move |event: &Event| match event {
+                Event::Change(changed, _) => *changed == id,
+                _ => false,
+            }
         };
-        self.metadata.borrow_mut().push(metadata);
+
+        match event {
+            Event::Add(_) => self.held.push_back(event),
+            Event::Remove(id) => {
+                self.held.retain(|event| !is_change_of(id)(event));
+                match self.held.iter().position(|event| *event == Event::Add(id)) {
+                    Some(add) => {
+                        self.held.remove(add);
+                        self.held.retain(|event| match event {
+                            Event::InterfaceAdd(device, _) | Event::InterfaceRemove(device, _) => {
+                                *device != id
+                            }
+                            _ => true,
+                        });
+                    }
+                    None => self.held.push_back(event),
+                }
+            }
+            Event::Change(id, changes) => {
+                if self.held.contains(&Event::Add(id)) {
+                    return;
+                }
+                match self.held.iter_mut().find(|event| is_change_of(id)(event)) {
+                    Some(Event::Change(_, merged)) => {
+                        merged.vendor_id |= changes.vendor_id;
+                        merged.product_id |= changes.product_id;
+                        merged.manufacturer_string |= changes.manufacturer_string;
+                        merged.product_string |= changes.product_string;
+                    }
+                    _ => self.held.push_back(event),
+                }
+            }
+            Event::OverCurrent(..) | Event::InterfaceAdd(..) | Event::InterfaceRemove(..) => {
+                self.held.push_back(event)
+            }
+            Event::EventsLost => {
+                if self.held.back() != Some(&Event::EventsLost) {
+                    self.held.push_back(event);
+                }
+            }
+        }
+
+        if self.held.len() > MAX_HELD_EVENTS {
+            self.held.clear();
+            self.held.push_back(Event::EventsLost);
+        }
     }
+}
 
-    /// Create a USB context.
-    pub fn new() -> Result<Self, Box<dyn StdError>> {
-        let context = Self {
-            context: os::Context::new()?,
-            metadata: RefCell::new(Vec::new()),
+impl<S: Stream<Item = Event, Error = Error>> Stream for RateLimited<S> {
+    type Item = Event;
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Event>>, Error> {
+        // Keep reading, so events are coalesced even while none can be delivered.
+        while !self.done {
+            match self.stream.poll()? {
+                Async::Ready(Some(event)) => self.hold(event),
+                Async::Ready(None) => self.done = true,
+                Async::NotReady => break,
+            }
+        }
+
+        loop {
+            if self.held.is_empty() {
+                return Ok(if self.done { Async::Ready(None) } else { Async::NotReady });
+            }
+
+            let window_end = self.window_start + Duration::from_secs(1);
+            if Instant::now() >= window_end {
+                self.window_start = Instant::now();
+                self.delivered = 0;
+                self.delay = None;
+            }
+            if self.max_per_second == 0 || self.delivered < self.max_per_second {
+                self.delivered += 1;
+                return Ok(Async::Ready(self.held.pop_front()));
+            }
+
+            let delay = self.delay.get_or_insert_with(|| Delay::new(window_end));
+            match delay.poll().map_err(|_| Error::Io(io::ErrorKind::Other))? {
+                Async::Ready(()) => continue,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// A stream of the values a device attribute changes to.
+///
+/// The stream ends when the device is removed. When polling sysfs instead of listening for
+/// uevents, the removal is noticed at the next poll.
+pub struct AttributeWatch {
+    watch: os::AttributeWatch,
+}
+
+impl Stream for AttributeWatch {
+    type Item = String;
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<Option<String>>, Error> {
+        self.watch.poll().map_err(std::convert::Into::into)
+    }
+}
+
+/// A USB bus, driven by a single host controller.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Bus {
+    /// The bus number, as shown by `lsusb`.
+    pub number: u8,
+    /// The bus's root hub.
+    pub root_hub: Id,
+    /// The name of the host controller device the bus belongs to, e.g. `0000:00:14.0` for a
+    /// PCI controller.
+    pub controller: String,
+    /// The kernel driver of the host controller, e.g. `xhci_hcd` or `ehci-pci`.
+    pub driver: Option<String>,
+    /// The PCI vendor and device IDs of the host controller, if it is a PCI device.
+    pub pci_id: Option<(u16, u16)>,
+}
+
+/// How much of a bus's periodic bandwidth is reserved, as returned by `Context::bus_usage`.
+///
+/// Interrupt and isochronous transfers reserve time in every (micro)frame. When a bus runs out,
+/// devices fail to set up their endpoints even though bulk transfers may be nearly idle.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BusUsage {
+    /// Microseconds per frame reserved by periodic transfers.
+    pub allocated_us: u32,
+    /// Microseconds per frame that periodic transfers may reserve in total.
+    pub available_us: u32,
+    /// The number of interrupt transfers holding a reservation.
+    pub interrupt_transfers: u32,
+    /// The number of isochronous transfers holding a reservation.
+    pub isochronous_transfers: u32,
+    /// The transfers made on the bus through the context's claimed interfaces.
+    pub transfers: TransferCounts,
+}
+
+/// How many transfers a context made on a bus, and how many failed in ways that point at the
+/// bus, from `Context::transfer_counts` or `BusUsage::transfers`.
+///
+/// Only transfers on the endpoints of interfaces the context claimed are counted, each retry
+/// separately.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TransferCounts {
+    /// Every transfer, however it ended.
+    pub transfers: u64,
+    /// Transfers that stalled (`Error::Stall`).
+    pub stalls: u64,
+    /// Transfers the device sent too much data for (`Error::Overflow`).
+    pub overflows: u64,
+    /// Transfers that failed on the wire (`Error::Protocol`).
+    pub protocol_errors: u64,
+    /// Transfers that timed out.
+    pub timeouts: u64,
+}
+
+// Counts the transfers on a bus, shared by the context and the interfaces claimed on the bus.
+#[derive(Debug, Default)]
+struct BusCounters {
+    transfers: AtomicU64,
+    stalls: AtomicU64,
+    overflows: AtomicU64,
+    protocol_errors: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+impl BusCounters {
+    fn record<T>(&self, result: &Result<T, os::UsbError>) {
+        self.transfers.fetch_add(1, Ordering::Relaxed);
+        let counter = match result {
+            Err(os::UsbError::Stall) => &self.stalls,
+            Err(os::UsbError::Overflow) => &self.overflows,
+            Err(os::UsbError::Protocol) => &self.protocol_errors,
+            Err(os::UsbError::Io(io::ErrorKind::TimedOut)) => &self.timeouts,
+            _ => return,
         };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
 
-        for dev in context.devices() {
-            context.add(dev);
+    fn counts(&self) -> TransferCounts {
+        TransferCounts {
+            transfers: self.transfers.load(Ordering::Relaxed),
+            stalls: self.stalls.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+            protocol_errors: self.protocol_errors.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
         }
+    }
+}
 
-        Ok(context)
+/// A USB4 or Thunderbolt tunnel a device is reached through.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Tunnel {
+    /// The Thunderbolt domain of the tunnel, like `domain0`, if the `thunderbolt` driver is
+    /// loaded.
+    pub domain: Option<String>,
+    /// The router the tunnel ends at, such as a dock, if it can be told apart from the other
+    /// routers in the domain.
+    pub router: Option<Router>,
+}
+
+/// A USB4 or Thunderbolt router.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Router {
+    /// The name of the router, made of the domain index and its route, like `0-1`.
+    pub name: String,
+    /// The vendor of the router.
+    pub vendor_name: Option<String>,
+    /// The name of the router's product.
+    pub device_name: Option<String>,
+}
+
+/// Where a device is plugged in: its bus and the hub ports leading to it.
+///
+/// Port paths are written like the sysfs names of devices, such as `1-4.2` for port 2 of the hub
+/// on port 4 of bus 1's root hub, or `usb1` for the root hub itself. They order by bus, then
+/// depth-first through the hubs.
+#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct PortPath {
+    bus: u8,
+    ports: Vec<u8>,
+}
+
+impl PortPath {
+    /// Create a port path from a bus number and the port numbers leading from its root hub.
+    pub fn new(bus: u8, ports: &[u8]) -> Self {
+        Self {
+            bus,
+            ports: ports.to_vec(),
+        }
     }
 
-    /// Create a USB hotplug monitor.
-    pub fn monitor(&self) -> Result<HotplugMonitor<'_>, Box<dyn StdError>> {
-        Ok(HotplugMonitor {
-            monitor: self.context.monitor()?,
-            context: self,
-        })
+    /// The number of the bus.
+    pub fn bus(&self) -> u8 {
+        self.bus
     }
 
-    /// Is a device plugged in?
-    pub fn is_connected(&self, id: Id) -> bool {
-        self.context.is_connected(id.into())
+    /// The port numbers leading from the root hub, empty for the root hub itself.
+    pub fn ports(&self) -> &[u8] {
+        &self.ports
     }
 
-    /// Retrieve the USB vendor ID of a device.
-    pub fn vendor_id(&self, id: Id) -> Option<u16> {
-        self.metadata.borrow()[(id.0).0 as usize].vendor_id
+    /// The port path of the hub this one is plugged into, or `None` for a root hub.
+    pub fn parent(&self) -> Option<Self> {
+        let (_, ports) = self.ports.split_last()?;
+        Some(Self::new(self.bus, ports))
     }
 
-    /// Retrieve the USB product ID of a device.
-    pub fn product_id(&self, id: Id) -> Option<u16> {
-        self.metadata.borrow()[(id.0).0 as usize].product_id
+    /// Is this port path behind a hub, directly or through other hubs?
+    pub fn is_behind(&self, hub: &Self) -> bool {
+        self.bus == hub.bus
+            && self.ports.len() > hub.ports.len()
+            && self.ports.starts_with(&hub.ports)
     }
+}
 
-    /// Retrieve the USB manufacturer string of a device.
-    pub fn manufacturer_string(&self, id: Id) -> Result<String, Error> {
-        self.context
-            .manufacturer_string(id.into())
-            .map_err(std::convert::Into::into)
+impl fmt::Display for PortPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ports.split_first() {
+            None => write!(f, "usb{}", self.bus),
+            Some((first, rest)) => {
+                write!(f, "{}-{}", self.bus, first)?;
+                for port in rest {
+                    write!(f, ".{}", port)?;
+                }
+                Ok(())
+            }
+        }
     }
+}
 
-    /// Retrieve the USB product string of a device.
-    pub fn product_string(&self, id: Id) -> Result<String, Error> {
-        self.context
-            .product_string(id.into())
-            .map_err(std::convert::Into::into)
+/// A string that isn't a port path.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ParsePortPathError;
+
+impl fmt::Display for ParsePortPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid port path")
     }
+}
 
-    /// Iterate through all devices, both connected and disconnected.
+impl StdError for ParsePortPathError {}
+
+impl FromStr for PortPath {
+    type Err = ParsePortPathError;
+
+    fn from_str(path: &str) -> Result<Self, ParsePortPathError> {
+        if let Some(bus) = path.strip_prefix("usb") {
+            return Ok(Self::new(bus.parse().map_err(|_| ParsePortPathError)?, &[]));
+        }
+
+        let mut parts = path.splitn(2, '-');
+        let bus = parts.next().unwrap_or_default().parse().map_err(|_| ParsePortPathError)?;
+        let ports = parts
+            .next()
+            .ok_or(ParsePortPathError)?
+            .split('.')
+            .map(|port| port.parse().map_err(|_| ParsePortPathError))
+            .collect::<Result<Vec<u8>, _>>()?;
+        Ok(Self { bus, ports })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PortPath {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A downstream port of a hub.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Port {
+    /// The port number, counting from 1.
+    pub number: u8,
+    /// How many over-current conditions the port has reported, if the kernel counts them.
+    pub over_current_count: Option<u32>,
+    /// How the port is connected, as reported by ACPI: `hotplug`, `hardwired`, `not used` or
+    /// `unknown`.
+    pub connect_type: Option<String>,
+    /// The device plugged into the port, if any.
+    pub device: Option<Id>,
+}
+
+/// The signalling speed of a USB link.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Speed {
+    /// USB 1.0 low speed, 1.5 Mbit/s.
+    Low,
+    /// USB 1.1 full speed, 12 Mbit/s.
+    Full,
+    /// USB 2.0 high speed, 480 Mbit/s.
+    High,
+    /// USB 3.0 SuperSpeed, 5 Gbit/s.
+    Super,
+    /// USB 3.1 and 3.2 SuperSpeed+, 10 or 20 Gbit/s depending on the generation and lanes.
+    SuperPlus,
+}
+
+/// The speed and lanes a device's link was negotiated at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LinkRate {
+    /// The signalling speed.
+    pub speed: Speed,
+    /// The SuperSpeed generation of each lane: 1 for 5 Gbit/s and 2 for 10 Gbit/s.
     ///
-    /// Use `connected_devices` to only iterate over currently plugged in devices.
-    pub fn devices(&self) -> impl Iterator<Item = Id> {
-        self.context.devices().map(Id)
+    /// Together with the lane counts, this gives the USB 3.2 name of the link, such as Gen 2x2
+    /// for two 10 Gbit/s lanes. It's `None` below SuperSpeed.
+    pub generation: Option<u8>,
+    /// The number of receive lanes.
+    pub rx_lanes: u8,
+    /// The number of transmit lanes.
+    pub tx_lanes: u8,
+}
+
+/// A process, as returned by `Context::devnode_users`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Process {
+    /// The process ID.
+    pub pid: u32,
+    /// The name of the process's executable, possibly cut short, as shown by `ps`.
+    pub command: Option<String>,
+}
+
+/// A USB language ID (LANGID), such as 0x0409 for US English.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LanguageId(pub u16);
+
+impl LanguageId {
+    /// US English, the language most devices provide their strings in.
+    pub const ENGLISH_US: Self = Self(0x0409);
+
+    /// The primary language, such as 0x09 for English.
+    pub fn primary(self) -> u16 {
+        self.0 & 0x3ff
     }
 
-    /// Iterate through connected devices.
-    pub fn connected_devices(&self) -> impl Iterator<Item = Id> + '_ {
-        self.devices().filter(move |id| self.is_connected(*id))
+    /// The sublanguage, such as 0x01 for US English.
+    pub fn sublanguage(self) -> u8 {
+        (self.0 >> 10) as u8
     }
 }
+
+/// The USB Type-C connector a device is plugged into.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TypeCPort {
+    /// The name of the connector in the `typec` class, like `port0`.
+    pub name: String,
+    /// The current data role: `host` or `device`.
+    pub data_role: Option<String>,
+    /// The current power role: `source` or `sink`.
+    pub power_role: Option<String>,
+    /// Which way round the plug is inserted: `normal`, `reverse` or `unknown`.
+    pub orientation: Option<String>,
+    /// Is a partner (a device, cable or dock) attached?
+    pub partner: bool,
+    /// The alternate modes the partner supports.
+    pub alternate_modes: Vec<AlternateMode>,
+}
+
+/// A Type-C alternate mode, such as DisplayPort or Thunderbolt.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AlternateMode {
+    /// The Standard or Vendor ID of the mode, e.g. 0xff01 for DisplayPort.
+    pub svid: u16,
+    /// The index of the mode among the ones with the same SVID, counting from 1.
+    pub mode: u8,
+    /// Has the mode been entered?
+    pub active: bool,
+    /// A description of the mode, if the driver provides one.
+    pub description: Option<String>,
+}
+
+/// An interface of a device's active configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Interface {
+    /// The interface number (`bInterfaceNumber`).
+    pub number: u8,
+    /// The currently selected alternate setting (`bAlternateSetting`).
+    pub alternate_setting: u8,
+    /// The interface class code (`bInterfaceClass`).
+    pub class: u8,
+    /// The interface subclass code (`bInterfaceSubClass`).
+    pub subclass: u8,
+    /// The interface protocol code (`bInterfaceProtocol`).
+    pub protocol: u8,
+    /// The number of endpoints, excluding endpoint zero (`bNumEndpoints`).
+    pub num_endpoints: u8,
+    /// The interface string descriptor (`iInterface`), if the device provides one.
+    ///
+    /// This is the string of the currently selected alternate setting; the kernel doesn't expose
+    /// the strings of the others.
+    pub name: Option<String>,
+    /// The kernel driver bound to the interface, if any.
+    pub driver: Option<String>,
+}
+
+/// A function of a composite device, such as the serial port or the storage of a modem stick,
+/// as returned by `Context::functions`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Function {
+    /// The function class code: `bFunctionClass` of its interface association, or the class of
+    /// its first interface.
+    pub class: u8,
+    /// The function subclass code.
+    pub subclass: u8,
+    /// The function protocol code.
+    pub protocol: u8,
+    /// The interfaces making up the function, ordered by interface number.
+    pub interfaces: Vec<Interface>,
+}
+
+/// An endpoint of an interface's current alternate setting.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Endpoint {
+    /// The endpoint address, with the direction bit (0x80 for IN) included (`bEndpointAddress`).
+    pub address: u8,
+    /// The type of transfers the endpoint carries.
+    pub transfer_type: TransferType,
+    /// The largest packet the endpoint takes (`wMaxPacketSize`).
+    ///
+    /// For high-bandwidth high speed endpoints, bits 11 and 12 hold the number of additional
+    /// transactions per microframe.
+    pub max_packet_size: u16,
+    /// The polling interval, in the encoding of the device's speed (`bInterval`).
+    pub interval: u8,
+}
+
+/// An interface claimed with `Context::claim_interface`.
+///
+/// The handle keeps the device's usbfs node open, and only operates on its own interface. The
+/// interface is released when the handle is dropped. The device's quirks, as they were when it
+/// was claimed, apply to its transfers.
+///
+/// # Blocking
+///
+/// Every request and transfer blocks the calling thread until it completes or times out. Never
+/// make them from a future polled by the reactor, since that stalls every other task on it;
+/// use a thread of their own, or `tokio_threadpool::blocking` on a thread pool.
+pub struct InterfaceHandle {
+    interface: os::ClaimedInterface,
+    quirks: quirks::Quirks,
+    quirk_hook: Option<Arc<dyn quirks::Quirk>>,
+    // Whether the device is gone, shared by its interfaces and the context that claimed them,
+    // so the context's hotplug monitors can tell them.
+    disconnection: Arc<Signal>,
+    watchdog: Option<Watchdog>,
+    // The latencies of each endpoint's transfers, by address, if they're recorded.
+    latencies: Option<Mutex<HashMap<u8, Latency>>>,
+    clock: Arc<dyn clock::Clock>,
+    bus_counters: Option<Arc<BusCounters>>,
+}
+
+// Something that happens once to a claimed interface, waking the tasks waiting for it.
+#[derive(Debug, Default)]
+struct Signal {
+    raised: AtomicBool,
+    tasks: Mutex<Vec<Task>>,
+}
+
+impl Signal {
+    fn raise(&self) {
+        self.raised.store(true, Ordering::SeqCst);
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.notify();
+        }
+    }
+
+    fn is_raised(&self) -> bool {
+        self.raised.load(Ordering::SeqCst)
+    }
+
+    fn poll(&self) -> Async<()> {
+        // Hold the lock while checking, so the signal can't be raised before the task is
+        // registered.
+        let mut tasks = self.tasks.lock().unwrap();
+        if self.is_raised() {
+            return Async::Ready(());
+        }
+        if !tasks.iter().any(Task::will_notify_current) {
+            tasks.push(task::current());
+        }
+        Async::NotReady
+    }
+}
+
+// Counts the transfers of an interface failing in a row the way wedged devices fail them.
+#[derive(Debug)]
+struct Watchdog {
+    limit: u32,
+    failures: AtomicU32,
+    unhealthy: Arc<Signal>,
+}
+
+impl Watchdog {
+    fn new(limit: u32) -> Self {
+        Self {
+            limit: limit.max(1),
+            failures: AtomicU32::new(0),
+            unhealthy: Arc::new(Signal::default()),
+        }
+    }
+
+    // Count a transfer's outcome towards the limit.
+    fn record<T>(&self, result: &Result<T, Error>) {
+        match result {
+            Err(Error::Io(io::ErrorKind::TimedOut)) | Err(Error::Protocol) => {
+                if self.failures.fetch_add(1, Ordering::SeqCst) + 1 >= self.limit {
+                    self.unhealthy.raise();
+                }
+            }
+            _ => self.failures.store(0, Ordering::SeqCst),
+        }
+    }
+}
+
+impl InterfaceHandle {
+    /// The interface number (`bInterfaceNumber`).
+    pub fn number(&self) -> u8 {
+        self.interface.number()
+    }
+
+    /// Has the interface's device been disconnected?
+    ///
+    /// A device is known to be gone once a hotplug monitor of the context that claimed the
+    /// interface reports its removal, or a request on it finds out. From then on, every request
+    /// and transfer fails with `Error::NotConnected` without going to the kernel, even if it
+    /// would still accept them.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnection.is_raised()
+    }
+
+    /// A future completing once the interface's device is known to be disconnected, as
+    /// `is_disconnected` tells.
+    ///
+    /// Transfer loops can select on it to stop as soon as the device is removed, rather than
+    /// when their next transfer fails. Removals are only noticed while a hotplug monitor of the
+    /// context that claimed the interface is polled. The future never fails.
+    pub fn on_disconnect(&self) -> OnDisconnect {
+        OnDisconnect(self.disconnection.clone())
+    }
+
+    /// Watch the interface's transfers for signs of wedged hardware: `limit` transfers in a row
+    /// timing out (`io::ErrorKind::TimedOut`) or failing on the wire (`Error::Protocol`).
+    ///
+    /// Once that happens, the interface stays unhealthy; recovering usually takes resetting
+    /// or replugging the device. Any other outcome of a transfer starts the count over. A
+    /// limit of 0 is taken as 1.
+    pub fn watchdog(mut self, limit: u32) -> Self {
+        self.watchdog = Some(Watchdog::new(limit));
+        self
+    }
+
+    /// Record how long each transfer on the interface's endpoints takes, from submitting it to
+    /// the kernel to its completion, for `latency`.
+    ///
+    /// Retries count as transfers of their own. Recording takes a lock per transfer.
+    pub fn record_latency(mut self) -> Self {
+        self.latencies = Some(Mutex::new(HashMap::new()));
+        self
+    }
+
+    /// The latencies of the transfers on an endpoint so far, if they're recorded.
+    pub fn latency(&self, endpoint: u8) -> Option<Latency> {
+        let latencies = self.latencies.as_ref()?.lock().unwrap();
+        Some(latencies.get(&endpoint).cloned().unwrap_or_default())
+    }
+
+    fn record_transfer(&self, endpoint: u8, duration: Duration) {
+        if let Some(latencies) = &self.latencies {
+            latencies.lock().unwrap().entry(endpoint).or_default().record(duration);
+        }
+    }
+
+    /// Take the time from `clock` instead of the system's, for retry backoff, quirk delays and
+    /// latencies.
+    pub fn clock(mut self, clock: Arc<dyn clock::Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Has the watchdog found the interface unhealthy? Always false without a watchdog.
+    pub fn is_unhealthy(&self) -> bool {
+        self.watchdog.as_ref().map_or(false, |watchdog| watchdog.unhealthy.is_raised())
+    }
+
+    /// A future completing once the watchdog finds the interface unhealthy, so long-running
+    /// services can reset or reclaim the device. Without a watchdog it never completes.
+    pub fn on_unhealthy(&self) -> OnUnhealthy {
+        OnUnhealthy(self.watchdog.as_ref().map(|watchdog| watchdog.unhealthy.clone()))
+    }
+
+    // Make a request on the interface, failing at once if its device is known to be gone and
+    // noting it if the request finds out.
+    fn request<T>(
+        &self,
+        request: impl FnOnce(&os::ClaimedInterface) -> Result<T, os::UsbError>,
+    ) -> Result<T, os::UsbError> {
+        if self.is_disconnected() {
+            return Err(os::UsbError::NotConnected);
+        }
+        let result = request(&self.interface);
+        if result.as_ref().err() == Some(&os::UsbError::NotConnected) {
+            self.disconnection.raise();
+        }
+        result
+    }
+
+    /// List the endpoints of the interface's current alternate setting, ordered by address.
+    pub fn endpoints(&self) -> Result<Vec<Endpoint>, Error> {
+        self.request(|interface| interface.endpoints())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Select an alternate setting of the interface.
+    ///
+    /// This blocks; see the type's documentation.
+    pub fn set_alternate_setting(&self, alternate_setting: u8) -> Result<(), Error> {
+        let result = self.request(|interface| interface.set_alternate_setting(alternate_setting));
+        self.control_message_sent();
+        result.map_err(std::convert::Into::into)
+    }
+
+    /// Use a bulk IN endpoint of the interface's current alternate setting.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the interface has no bulk IN endpoint at
+    /// `address`.
+    pub fn bulk_in(&self, address: u8) -> Result<BulkIn<'_>, Error> {
+        Ok(BulkIn {
+            interface: self,
+            endpoint: self.endpoint(address, TransferType::Bulk, Direction::In)?,
+            recover_stalls: false,
+            retry: RetryPolicy::default(),
+            short_not_ok: false,
+        })
+    }
+
+    /// Use a bulk OUT endpoint of the interface's current alternate setting.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the interface has no bulk OUT endpoint at
+    /// `address`.
+    pub fn bulk_out(&self, address: u8) -> Result<BulkOut<'_>, Error> {
+        Ok(BulkOut {
+            interface: self,
+            endpoint: self.endpoint(address, TransferType::Bulk, Direction::Out)?,
+            recover_stalls: false,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Use an interrupt IN endpoint of the interface's current alternate setting.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the interface has no interrupt IN endpoint at
+    /// `address`.
+    pub fn interrupt_in(&self, address: u8) -> Result<InterruptIn<'_>, Error> {
+        Ok(InterruptIn {
+            interface: self,
+            endpoint: self.endpoint(address, TransferType::Interrupt, Direction::In)?,
+            recover_stalls: false,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Use an interrupt OUT endpoint of the interface's current alternate setting.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the interface has no interrupt OUT endpoint
+    /// at `address`.
+    pub fn interrupt_out(&self, address: u8) -> Result<InterruptOut<'_>, Error> {
+        Ok(InterruptOut {
+            interface: self,
+            endpoint: self.endpoint(address, TransferType::Interrupt, Direction::Out)?,
+            recover_stalls: false,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    // Look up an endpoint, making sure it has the type and direction it's about to be used with.
+    fn endpoint(
+        &self,
+        address: u8,
+        transfer_type: TransferType,
+        direction: Direction,
+    ) -> Result<Endpoint, Error> {
+        let in_direction = direction == Direction::In;
+        self.endpoints()?
+            .into_iter()
+            .find(|endpoint| {
+                endpoint.address == address
+                    && endpoint.transfer_type == transfer_type
+                    && (address & 0x80 != 0) == in_direction
+            })
+            .ok_or(Error::Io(io::ErrorKind::InvalidInput))
+    }
+
+    /// Read the interface's status with a `GET_STATUS` request.
+    ///
+    /// This blocks; see the type's documentation.
+    pub fn status(&self) -> Result<InterfaceStatus, Error> {
+        let request = SetupPacket::get_status(Recipient::Interface, self.number().into());
+        Ok(InterfaceStatus::from_bytes(self.get_status(&request)?))
+    }
+
+    /// Ask the device whether one of the interface's endpoints is halted, with a `GET_STATUS`
+    /// request.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` for endpoints of other interfaces. This blocks;
+    /// see the type's documentation.
+    pub fn endpoint_halted(&self, endpoint: u8) -> Result<bool, Error> {
+        if !self.endpoints()?.iter().any(|current| current.address == endpoint) {
+            return Err(Error::Io(io::ErrorKind::InvalidInput));
+        }
+        let request = SetupPacket::get_status(Recipient::Endpoint, endpoint.into());
+        Ok(self.get_status(&request)?[0] & 0x01 != 0)
+    }
+
+    /// Halt one of the interface's endpoints with a `SET_FEATURE` request, as class protocols
+    /// do to report errors. `clear_halt` undoes it.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` for endpoints of other interfaces. This blocks;
+    /// see the type's documentation.
+    pub fn set_halt(&self, endpoint: u8) -> Result<(), Error> {
+        if !self.endpoints()?.iter().any(|current| current.address == endpoint) {
+            return Err(Error::Io(io::ErrorKind::InvalidInput));
+        }
+        let request =
+            SetupPacket::set_feature(Recipient::Endpoint, feature::ENDPOINT_HALT, endpoint.into());
+        let result =
+            self.request(|interface| interface.control(&request, &mut [], Duration::from_secs(1)));
+        self.control_message_sent();
+        result.map(|_| ()).map_err(std::convert::Into::into)
+    }
+
+    fn get_status(&self, request: &SetupPacket) -> Result<[u8; 2], Error> {
+        let mut status = [0; 2];
+        let result = self.request(|interface| {
+            interface.control(request, &mut status, Duration::from_secs(1))
+        });
+        self.control_message_sent();
+        if result? < status.len() {
+            return Err(Error::Io(io::ErrorKind::InvalidData));
+        }
+        Ok(status)
+    }
+
+    /// Clear the halt condition of one of the interface's endpoints.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` for endpoints of other interfaces. This blocks;
+    /// see the type's documentation.
+    pub fn clear_halt(&self, endpoint: u8) -> Result<(), Error> {
+        let result = self.request(|interface| interface.clear_halt(endpoint));
+        self.control_message_sent();
+        result.map_err(std::convert::Into::into)
+    }
+
+    // Give devices that need it their pause after a control request.
+    fn control_message_sent(&self) {
+        if self.quirks.delay_control_messages {
+            self.clock.sleep(quirks::CONTROL_MESSAGE_DELAY);
+        }
+    }
+
+    // Write to an OUT endpoint, leaving out zero-length packets for devices that choke on them.
+    fn write(&self, endpoint: u8, data: &[u8], timeout: Duration) -> Result<usize, os::UsbError> {
+        if data.is_empty() && self.quirks.no_zero_length_packets {
+            return Ok(0);
+        }
+        self.request(|interface| interface.write(endpoint, data, timeout))
+    }
+}
+
+// How many buckets a latency histogram has.
+const LATENCY_BUCKETS: usize = 32;
+
+/// How long the transfers on an endpoint took, from `InterfaceHandle::latency`.
+///
+/// Durations are sorted into buckets by powers of two microseconds, which is enough to spot
+/// transfers waiting longer than their endpoint's service interval.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Latency {
+    /// How many transfers completed, successfully or not.
+    pub transfers: u64,
+    /// The time the transfers took altogether.
+    pub total: Duration,
+    /// The longest a transfer took.
+    pub max: Duration,
+    /// How many transfers took less than 2^i microseconds but at least 2^(i - 1), for each
+    /// bucket `i`. The first counts transfers taking under a microsecond, and the last every
+    /// transfer too long for the others.
+    pub buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl Latency {
+    /// The average time a transfer took.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.transfers == 0 {
+            return None;
+        }
+        let nanos = self.total.as_nanos() / u128::from(self.transfers);
+        Some(Duration::from_nanos(nanos.min(u128::from(u64::MAX)) as u64))
+    }
+
+    fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros().min(u128::from(u64::MAX)) as u64;
+        let bucket = (64 - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+        self.transfers += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+}
+
+/// When transfers that fail are tried again, as set with `retry` on an endpoint handle.
+///
+/// The default policy makes a single attempt. Errors to retry have to be named with `retry_on`,
+/// since which are transient depends on the device and its protocol: `Error::Protocol` for
+/// a noisy cable, `io::ErrorKind::TimedOut` for a device that sometimes answers late, or
+/// `Error::Stall`, whose halt is cleared before trying again.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    errors: Vec<Error>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Duration::from_millis(10),
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Make at most `attempts` attempts at each transfer, counting the first.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Wait `backoff` before the first retry, doubling the wait before each one after it.
+    ///
+    /// The default is 10 ms. This blocks the thread like the transfer does, going by the
+    /// interface's clock (`InterfaceHandle::clock`).
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Try transfers failing with `error` again.
+    pub fn retry_on(mut self, error: Error) -> Self {
+        self.errors.push(error);
+        self
+    }
+
+    // How long to wait before trying again after attempt number `attempt` failed with `error`,
+    // if at all.
+    fn delay(&self, attempt: u32, error: Error) -> Option<Duration> {
+        if attempt >= self.attempts || !self.errors.contains(&error) {
+            return None;
+        }
+        let factor = 2u32.checked_pow(attempt - 1);
+        Some(factor.and_then(|factor| self.backoff.checked_mul(factor)).unwrap_or(Duration::MAX))
+    }
+}
+
+/// A future completing once the device of a claimed interface is disconnected, from
+/// `InterfaceHandle::on_disconnect`.
+#[derive(Debug)]
+pub struct OnDisconnect(Arc<Signal>);
+
+impl Future for OnDisconnect {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<()>, Error> {
+        Ok(self.0.poll())
+    }
+}
+
+/// A future completing once a claimed interface's watchdog finds it unhealthy, from
+/// `InterfaceHandle::on_unhealthy`.
+#[derive(Debug)]
+pub struct OnUnhealthy(Option<Arc<Signal>>);
+
+impl Future for OnUnhealthy {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<()>, Error> {
+        Ok(self.0.as_ref().map_or(Async::NotReady, |unhealthy| unhealthy.poll()))
+    }
+}
+
+macro_rules! endpoint_handle {
+    ($(#[$attr:meta])* $name:ident { $($field:ident: $field_type:ty),* }) => {
+        $(#[$attr])*
+        ///
+        /// Transfers block the calling thread until they complete or time out; see
+        /// `InterfaceHandle` on blocking. Their `_async` variants hand other tasks on a tokio
+        /// thread pool to another thread while they wait.
+        pub struct $name<'a> {
+            interface: &'a InterfaceHandle,
+            endpoint: Endpoint,
+            recover_stalls: bool,
+            retry: RetryPolicy,
+            $($field: $field_type,)*
+        }
+
+        impl $name<'_> {
+            /// The endpoint's descriptor.
+            pub fn endpoint(&self) -> &Endpoint {
+                &self.endpoint
+            }
+
+            /// Recover from stalls by clearing the endpoint's halt and retrying the transfer
+            /// once, as many class protocols expect.
+            ///
+            /// Only transfers of at most one packet (`max_packet_size`) are retried. Part of a
+            /// longer one may have gone through before the stall, so retrying it could repeat
+            /// or drop data; those still fail with `Error::Stall`, as does a transfer that
+            /// stalls again.
+            pub fn recover_stalls(mut self) -> Self {
+                self.recover_stalls = true;
+                self
+            }
+
+            /// Try transfers that fail again, as `policy` says.
+            ///
+            /// Unlike `recover_stalls`, this retries transfers of any length. Part of a long
+            /// transfer may have gone through before it failed, so only retry errors after
+            /// which the protocol running over the endpoint can take the data again.
+            pub fn retry(mut self, policy: RetryPolicy) -> Self {
+                self.retry = policy;
+                self
+            }
+
+            fn transfer<T>(
+                &self,
+                length: usize,
+                mut transfer: impl FnMut(&InterfaceHandle) -> Result<T, os::UsbError>,
+            ) -> Result<T, Error> {
+                let interface = self.interface;
+                if let Some(hook) = &interface.quirk_hook {
+                    hook.before_transfer(&self.endpoint, length)?;
+                }
+                // Bits 11 and 12 are for high-bandwidth endpoints, not the size.
+                let single_packet = length <= usize::from(self.endpoint.max_packet_size & 0x7ff);
+                let mut attempt = 1;
+                let mut timed = |interface: &InterfaceHandle| {
+                    let start = interface.clock.now();
+                    let result = transfer(interface);
+                    let duration = interface.clock.now().duration_since(start);
+                    interface.record_transfer(self.endpoint.address, duration);
+                    if let Some(counters) = &interface.bus_counters {
+                        counters.record(&result);
+                    }
+                    result
+                };
+                let result = loop {
+                    let result = match timed(interface) {
+                        Err(os::UsbError::Stall) if self.recover_stalls && single_packet => {
+                            interface.clear_halt(self.endpoint.address)?;
+                            timed(interface)
+                        }
+                        result => result,
+                    };
+                    let err = match result {
+                        Ok(value) => break Ok(value),
+                        Err(err) => Error::from(err),
+                    };
+                    let delay = match self.retry.delay(attempt, err) {
+                        Some(delay) => delay,
+                        None => break Err(err),
+                    };
+                    if err == Error::Stall {
+                        interface.clear_halt(self.endpoint.address)?;
+                    }
+                    interface.clock.sleep(delay);
+                    attempt += 1;
+                };
+                if let Some(watchdog) = &interface.watchdog {
+                    watchdog.record(&result);
+                }
+                result
+            }
+        }
+    };
+}
+
+endpoint_handle!(
+    /// A bulk IN endpoint of a claimed interface, from `InterfaceHandle::bulk_in`.
+    BulkIn { short_not_ok: bool }
+);
+endpoint_handle!(
+    /// A bulk OUT endpoint of a claimed interface, from `InterfaceHandle::bulk_out`.
+    BulkOut {}
+);
+endpoint_handle!(
+    /// An interrupt IN endpoint of a claimed interface, from `InterfaceHandle::interrupt_in`.
+    InterruptIn {}
+);
+endpoint_handle!(
+    /// An interrupt OUT endpoint of a claimed interface, from `InterfaceHandle::interrupt_out`.
+    InterruptOut {}
+);
+
+impl BulkIn<'_> {
+    /// Treat reads the device ends early with a short packet as errors.
+    ///
+    /// By default a short read returns the bytes received, for protocols where a short packet
+    /// terminates a transfer. With this set, it fails with `io::ErrorKind::UnexpectedEof`
+    /// instead, for protocols where it means something went wrong. Either way, the bytes
+    /// received are in the buffer.
+    pub fn short_not_ok(mut self) -> Self {
+        self.short_not_ok = true;
+        self
+    }
+
+    /// Read from the endpoint into `buffer`, returning how many bytes were read.
+    ///
+    /// This blocks the thread until the device ends the transfer with a short packet, `buffer`
+    /// is full or `timeout` passes; a zero timeout waits forever. See `InterfaceHandle` on
+    /// blocking.
+    pub fn read(&self, buffer: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let address = self.endpoint.address;
+        let length = self.transfer(buffer.len(), |interface| {
+            interface.request(|interface| interface.read(address, buffer, timeout))
+        })?;
+        if self.short_not_ok && length < buffer.len() {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof));
+        }
+        Ok(length)
+    }
+
+    /// Read from the endpoint like `read`, without holding up other tasks while waiting.
+    ///
+    /// The future resolves to the buffer and how many bytes were read into it. Like
+    /// `ContextBuilder::build_async`, this only helps on a tokio thread pool.
+    pub fn read_async(
+        &self,
+        mut buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> impl Future<Item = (Vec<u8>, usize), Error = Error> + '_ {
+        blocking(move || self.read(&mut buffer, timeout).map(|length| (buffer, length)))
+            .and_then(future::result)
+    }
+}
+
+impl BulkOut<'_> {
+    /// Write `data` to the endpoint, returning how many bytes were written.
+    ///
+    /// This blocks the thread until the transfer completes or `timeout` passes; a zero timeout
+    /// waits forever. See `InterfaceHandle` on blocking.
+    pub fn write(&self, data: &[u8], timeout: Duration) -> Result<usize, Error> {
+        let address = self.endpoint.address;
+        self.transfer(data.len(), |interface| interface.write(address, data, timeout))
+    }
+
+    /// Write `data` to the endpoint like `write`, without holding up other tasks while waiting.
+    ///
+    /// The future resolves to the data and how many bytes of it were written. Like
+    /// `ContextBuilder::build_async`, this only helps on a tokio thread pool.
+    pub fn write_async(
+        &self,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> impl Future<Item = (Vec<u8>, usize), Error = Error> + '_ {
+        blocking(move || self.write(&data, timeout).map(|length| (data, length)))
+            .and_then(future::result)
+    }
+}
+
+impl InterruptIn<'_> {
+    /// Wait for a report from the endpoint and read it into `buffer`, returning its length.
+    ///
+    /// This blocks the thread until the device sends one or `timeout` passes; a zero timeout
+    /// waits forever. See `InterfaceHandle` on blocking.
+    pub fn read(&self, buffer: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let address = self.endpoint.address;
+        self.transfer(buffer.len(), |interface| {
+            interface.request(|interface| interface.read(address, buffer, timeout))
+        })
+    }
+
+    /// Wait for a report like `read`, without holding up other tasks while waiting.
+    ///
+    /// The future resolves to the buffer and the report's length. Like
+    /// `ContextBuilder::build_async`, this only helps on a tokio thread pool.
+    pub fn read_async(
+        &self,
+        mut buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> impl Future<Item = (Vec<u8>, usize), Error = Error> + '_ {
+        blocking(move || self.read(&mut buffer, timeout).map(|length| (buffer, length)))
+            .and_then(future::result)
+    }
+}
+
+impl InterruptOut<'_> {
+    /// Send `data` to the endpoint, returning how many bytes were sent.
+    ///
+    /// This blocks the thread until the device takes it or `timeout` passes; a zero timeout
+    /// waits forever. See `InterfaceHandle` on blocking.
+    pub fn write(&self, data: &[u8], timeout: Duration) -> Result<usize, Error> {
+        let address = self.endpoint.address;
+        self.transfer(data.len(), |interface| interface.write(address, data, timeout))
+    }
+
+    /// Send `data` to the endpoint like `write`, without holding up other tasks while waiting.
+    ///
+    /// The future resolves to the data and how many bytes of it were sent. Like
+    /// `ContextBuilder::build_async`, this only helps on a tokio thread pool.
+    pub fn write_async(
+        &self,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> impl Future<Item = (Vec<u8>, usize), Error = Error> + '_ {
+        blocking(move || self.write(&data, timeout).map(|length| (data, length)))
+            .and_then(future::result)
+    }
+}
+
+/// A device a context has seen, as returned by `Context::history`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HistoryEntry {
+    /// The device's handle.
+    pub id: Id,
+    /// The USB vendor ID.
+    pub vendor_id: Option<VendorId>,
+    /// The USB product ID.
+    pub product_id: Option<ProductId>,
+    /// The USB serial number string, if the device has one.
+    pub serial_number: Option<String>,
+    /// When the context first saw the device: when it was plugged in, or when the context was
+    /// created if it was already there.
+    pub connected_at: SystemTime,
+    /// When a hotplug monitor reported the device's removal.
+    pub disconnected_at: Option<SystemTime>,
+    /// Is the device still connected?
+    pub connected: bool,
+    /// How many times a device with the same vendor ID, product ID and serial number has been
+    /// plugged in, counting this time.
+    ///
+    /// Devices without a serial number are only told apart by model.
+    pub connections: usize,
+}
+
+/// A summary of a connected device, as returned by `Context::snapshot`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceInfo {
+    /// The device's handle.
+    pub id: Id,
+    /// The USB vendor ID.
+    pub vendor_id: VendorId,
+    /// The USB product ID.
+    pub product_id: ProductId,
+    /// The USB manufacturer string, if the device has one.
+    pub manufacturer_string: Option<String>,
+    /// The USB product string, if the device has one.
+    pub product_string: Option<String>,
+    /// The USB serial number string, if the device has one.
+    pub serial_number: Option<String>,
+    /// The number of the bus the device is attached to.
+    pub bus_number: u8,
+    /// The path of the device's usbfs node.
+    pub devnode: PathBuf,
+    /// The interfaces of the device's active configuration.
+    pub interfaces: Vec<Interface>,
+}
+
+#[cfg(feature = "json")]
+impl DeviceInfo {
+    /// Render the device information as a JSON object.
+    ///
+    /// This fails if the device node's path isn't valid UTF-8, which JSON can't represent.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+/// Configures which devices a `Context` tracks.
+///
+/// Matches are applied both when scanning for devices and when monitoring them.
+#[derive(Clone, Debug)]
+pub struct ContextBuilder {
+    subsystems: Vec<String>,
+    devtypes: Vec<String>,
+    properties: Vec<(String, String)>,
+    tags: Vec<String>,
+    interface_events: bool,
+    lock_devices: bool,
+    sysfs: Option<(PathBuf, PathBuf)>,
+    event_history: usize,
+}
+
+// How many recent events a context keeps by default.
+const DEFAULT_EVENT_HISTORY: usize = 64;
+
+impl Default for ContextBuilder {
+    fn default() -> Self {
+        Self {
+            subsystems: vec![String::from("usb")],
+            devtypes: Vec::new(),
+            properties: Vec::new(),
+            tags: Vec::new(),
+            interface_events: false,
+            lock_devices: false,
+            sysfs: None,
+            event_history: DEFAULT_EVENT_HISTORY,
+        }
+    }
+}
+
+impl ContextBuilder {
+    /// Also track devices of another subsystem, such as `hidraw` or `tty`.
+    ///
+    /// Only devices belonging to a USB device are tracked; their metadata is that of the USB
+    /// device they belong to.
+    pub fn match_subsystem(mut self, subsystem: &str) -> Self {
+        self.subsystems.push(String::from(subsystem));
+        self
+    }
+
+    /// Only track devices of a device type (`DEVTYPE`), such as `usb_device`.
+    ///
+    /// This may be given several times to allow several device types. If it isn't given, devices
+    /// of the `usb` subsystem are restricted to `usb_device`, leaving out their interfaces.
+    pub fn match_devtype(mut self, devtype: &str) -> Self {
+        self.devtypes.push(String::from(devtype));
+        self
+    }
+
+    /// Only track devices with a udev property set to a value.
+    ///
+    /// If this is given several times, all of the properties have to match.
+    pub fn match_property(mut self, name: &str, value: &str) -> Self {
+        self.properties.push((String::from(name), String::from(value)));
+        self
+    }
+
+    /// Only track devices carrying a udev tag, such as `seat` or `uaccess`.
+    ///
+    /// If this is given several times, all of the tags have to be present. Combined with a
+    /// `match_property("ID_SEAT", ...)`, this restricts a context to the devices of one seat.
+    ///
+    /// Tags are applied by udev rules, so monitors should use `EventSource::Udev`: raw kernel
+    /// events can arrive before the rules have run, in which case the device won't match.
+    pub fn match_tag(mut self, tag: &str) -> Self {
+        self.tags.push(String::from(tag));
+        self
+    }
+
+    /// Also report interfaces of tracked devices coming and going, with
+    /// `Event::InterfaceAdd` and `Event::InterfaceRemove`.
+    pub fn interface_events(mut self) -> Self {
+        self.interface_events = true;
+        self
+    }
+
+    /// Take an exclusive advisory lock (`flock`) on a device's usbfs node while its interfaces
+    /// are claimed, or while a node from `Context::open_devnode` is open.
+    ///
+    /// Processes using this crate with locking enabled then can't claim interfaces of a device
+    /// another of them is using, even ones it left unclaimed; they get `ClaimError::Busy`
+    /// naming the process instead. Interfaces claimed through one context share its lock. The
+    /// lock is advisory, so processes that don't take it aren't kept out.
+    pub fn lock_devices(mut self) -> Self {
+        self.lock_devices = true;
+        self
+    }
+
+    /// Read sysfs directly instead of going through libudev.
+    ///
+    /// This is meant for containers, where the udev database is usually missing and the host's
+    /// sysfs and device nodes may be mounted somewhere other than `/sys` and `/dev`. `sysfs` and
+    /// `devfs` are the paths those are mounted at. Property matches only see the properties the
+    /// kernel provides and tag matches never succeed, since the udev database isn't consulted.
+    ///
+    /// udevd is rarely running in a container, so monitors created from such a context will
+    /// usually want `EventSource::Kernel`, or `EventSource::Poll` if the container has its own
+    /// network namespace.
+    pub fn sysfs<P, Q>(mut self, sysfs: P, devfs: Q) -> Self
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.sysfs = Some((sysfs.as_ref().to_path_buf(), devfs.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Keep the given number of the most recent hotplug events, for `Context::recent_events`.
+    ///
+    /// The default is 64; zero keeps none.
+    pub fn event_history(mut self, capacity: usize) -> Self {
+        self.event_history = capacity;
+        self
+    }
+
+    /// Create the USB context.
+    pub fn build(self) -> Result<Context, Box<dyn StdError>> {
+        Context::from_os(os::Context::new(&self)?, self.event_history)
+    }
+
+    /// Create the USB context without holding up other tasks while devices are enumerated.
+    ///
+    /// On a tokio thread pool, the pool is told the worker is about to block, so it can hand its
+    /// other tasks to another thread. Elsewhere, such as on a `current_thread` runtime, this
+    /// blocks like `build`. The context is created on the thread polling the future, since it
+    /// can't be moved between threads.
+    pub fn build_async(self) -> impl Future<Item = Context, Error = Box<dyn StdError>> {
+        blocking(move || self.build()).and_then(future::result)
+    }
+}
+
+// Run blocking work from a future. On a tokio thread pool, the pool is told the worker is about to
+// block so it can hand its other tasks to another thread; elsewhere, the work just runs.
+fn blocking<T, E>(work: impl FnOnce() -> T) -> impl Future<Item = T, Error = E> {
+    let mut work = Some(work);
+    future::poll_fn(move || {
+        let mut run = || (work.take().expect("polled after completion"))();
+        match tokio_threadpool::blocking(&mut run) {
+            Ok(Async::Ready(value)) => Ok(Async::Ready(value)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // Not on a thread pool.
+            Err(_) => Ok(Async::Ready(run())),
+        }
+    })
+}
+
+// What `Context::info` needs from the context, gathered up front so the rest can be read from
+// sysfs on another thread.
+struct DetachedInfo {
+    id: Id,
+    vendor_id: Option<VendorId>,
+    product_id: Option<ProductId>,
+    quirks: quirks::Quirks,
+    dir: os::DeviceDir,
+}
+
+impl DetachedInfo {
+    fn read(self) -> Result<DeviceInfo, Error> {
+        let ignore_strings = self.quirks.ignore_strings;
+        let string = |value: Result<String, os::UsbError>| value.ok().filter(|_| !ignore_strings);
+        Ok(DeviceInfo {
+            id: self.id,
+            vendor_id: self.vendor_id.ok_or(Error::NotConnected)?,
+            product_id: self.product_id.ok_or(Error::NotConnected)?,
+            manufacturer_string: string(self.dir.manufacturer_string()),
+            product_string: string(self.dir.product_string()),
+            serial_number: string(self.dir.serial_number())
+                .filter(|_| !self.quirks.non_unique_serial),
+            bus_number: self.dir.bus_number()?,
+            devnode: self.dir.devnode()?,
+            interfaces: self.dir.interfaces()?,
+        })
+    }
+}
+
+// When a device was attached and detached, and what's needed to recognise it again once it's
+// gone.
+struct Attachment {
+    serial_number: Option<String>,
+    connected_at: SystemTime,
+    disconnected_at: Option<SystemTime>,
+}
+
+#[derive(PartialEq)]
+struct Metadata {
+    vendor_id: Option<VendorId>,
+    product_id: Option<ProductId>,
+    manufacturer_string: Option<String>,
+    product_string: Option<String>,
+}
+
+/// A USB context.
+pub struct Context {
+    context: os::Context,
+    metadata: RefCell<Vec<Metadata>>,
+    attachments: RefCell<Vec<Attachment>>,
+    // The most recent events delivered by any of the context's monitors, oldest first.
+    recent_events: RefCell<VecDeque<(SystemTime, Event)>>,
+    event_history: usize,
+    // Quirks registered by the user, overriding the built-in ones.
+    quirks: RefCell<HashMap<(VendorId, ProductId), quirks::Quirks>>,
+    quirk_hooks: RefCell<HashMap<(VendorId, ProductId), Arc<dyn quirks::Quirk>>>,
+    // The devices with claimed interfaces, to tell the interfaces when their device is removed.
+    claimed: RefCell<HashMap<Id, Weak<Signal>>>,
+    // The transfers made through claimed interfaces, by bus number.
+    bus_counters: RefCell<HashMap<u8, Arc<BusCounters>>>,
+    #[cfg(feature = "usb-ids")]
+    usb_ids: RefCell<Option<usb_ids::UsbIds>>,
+}
+
+impl Context {
+    fn fetch(&self, id: os::Id) -> Metadata {
+        Metadata {
+            vendor_id: self.context.vendor_id(id).ok().map(VendorId),
+            product_id: self.context.product_id(id).ok().map(ProductId),
+            manufacturer_string: self.context.manufacturer_string(id).ok(),
+            product_string: self.context.product_string(id).ok(),
+        }
+    }
+
+    // Cache metadata for every device up to and including `id`. The monitor may register devices
+    // before their events are consumed, so this catches up on any that were skipped. Nothing is
+    // cached past the devices registered so far, or a device later registered under an Id asked
+    // about too early would be stuck with empty metadata.
+    fn add(&self, id: Id) {
+        let registered = self.context.devices().count();
+        while self.metadata.borrow().len() <= usize::from(id.0)
+            && self.metadata.borrow().len() < registered
+        {
+            let id = os::Id(self.metadata.borrow().len() as u32);
+            let metadata = self.fetch(id);
+            self.metadata.borrow_mut().push(metadata);
+            // Go through the quirks, so devices that share a serial number aren't taken for
+            // one another.
+            let serial_number = self.serial_number(Id(id)).ok();
+            // Metadata may be fetched long after the device was registered, even after it's gone.
+            let connected_at = self.context.connected_at(id).unwrap_or_else(SystemTime::now);
+            self.attachments.borrow_mut().push(Attachment {
+                serial_number,
+                connected_at,
+                disconnected_at: None,
+            });
+        }
+    }
+
+    fn remove(&self, id: Id) {
+        self.add(id);
+        let claimed = self.claimed.borrow_mut().remove(&id);
+        if let Some(disconnection) = claimed.as_ref().and_then(Weak::upgrade) {
+            disconnection.raise();
+        }
+        if let Some(attachment) = self.attachments.borrow_mut().get_mut(usize::from(id.0)) {
+            attachment.disconnected_at = Some(SystemTime::now());
+        }
+    }
+
+    fn remember(&self, event: Event) {
+        if self.event_history == 0 {
+            return;
+        }
+        let mut recent_events = self.recent_events.borrow_mut();
+        if recent_events.len() == self.event_history {
+            recent_events.pop_front();
+        }
+        recent_events.push_back((SystemTime::now(), event));
+    }
+
+    // Re-read the metadata of a device, reporting what differs from the cached copy.
+    fn refresh(&self, id: Id) -> Changes {
+        self.add(id);
+        let new = self.fetch(id.into());
+        let mut metadata = self.metadata.borrow_mut();
+        let old = match metadata.get_mut(usize::from(id.0)) {
+            Some(old) => old,
+            None => return Changes::default(),
+        };
+        let changes = Changes {
+            vendor_id: old.vendor_id != new.vendor_id,
+            product_id: old.product_id != new.product_id,
+            manufacturer_string: old.manufacturer_string != new.manufacturer_string,
+            product_string: old.product_string != new.product_string,
+        };
+        *old = new;
+        changes
+    }
+
+    /// Create a USB context tracking every USB device.
+    pub fn new() -> Result<Self, Box<dyn StdError>> {
+        Self::builder().build()
+    }
+
+    /// Create a USB context tracking every USB device, without holding up other tasks while
+    /// devices are enumerated.
+    ///
+    /// This is shorthand for `Context::builder().build_async()`.
+    pub fn new_async() -> impl Future<Item = Self, Error = Box<dyn StdError>> {
+        Self::builder().build_async()
+    }
+
+    /// Configure which devices a USB context tracks.
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::default()
+    }
+
+    /// Create a USB context that reads sysfs directly instead of going through libudev.
+    ///
+    /// This is shorthand for `Context::builder().sysfs(sysfs, devfs).build()`.
+    pub fn with_sysfs<P, Q>(sysfs: P, devfs: Q) -> Result<Self, Box<dyn StdError>>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        Self::builder().sysfs(sysfs, devfs).build()
+    }
+
+    fn from_os(context: os::Context, event_history: usize) -> Result<Self, Box<dyn StdError>> {
+        let context = Self {
+            context,
+            metadata: RefCell::new(Vec::new()),
+            attachments: RefCell::new(Vec::new()),
+            recent_events: RefCell::new(VecDeque::with_capacity(event_history)),
+            event_history,
+            quirks: RefCell::new(HashMap::new()),
+            quirk_hooks: RefCell::new(HashMap::new()),
+            claimed: RefCell::new(HashMap::new()),
+            bus_counters: RefCell::new(HashMap::new()),
+            #[cfg(feature = "usb-ids")]
+            usb_ids: RefCell::new(None),
+        };
+
+        for dev in context.devices() {
+            context.add(dev);
+        }
+
+        Ok(context)
+    }
+
+    /// Create a USB hotplug monitor listening to the default event source.
+    pub fn monitor(&self) -> Result<HotplugMonitor<'_>, Box<dyn StdError>> {
+        self.monitor_with_source(EventSource::default())
+    }
+
+    /// Create a USB hotplug monitor listening to the given event source.
+    pub fn monitor_with_source(
+        &self,
+        source: EventSource,
+    ) -> Result<HotplugMonitor<'_>, Box<dyn StdError>> {
+        Ok(HotplugMonitor {
+            monitor: self.context.monitor(source)?,
+            context: self,
+        })
+    }
+
+    /// List the most recent events delivered by the context's hotplug monitors, oldest first,
+    /// with the time each was delivered.
+    ///
+    /// How many are kept is set with `ContextBuilder::event_history`. Events only count once
+    /// they've been polled from a monitor, and a rate limited monitor's coalesced events appear
+    /// as they were before coalescing.
+    pub fn recent_events(&self) -> Vec<(SystemTime, Event)> {
+        self.recent_events.borrow().iter().copied().collect()
+    }
+
+    /// Watch a sysfs attribute of a device, such as `authorized` or `bConfigurationValue`.
+    ///
+    /// The attribute is re-read whenever the device reports a change, and the stream yields its
+    /// new value if it differs from the previous one. The value at the time of the call is not
+    /// yielded.
+    pub fn watch_attribute(&self, id: Id, name: &str) -> Result<AttributeWatch, Box<dyn StdError>> {
+        Ok(AttributeWatch {
+            watch: self
+                .context
+                .watch_attribute(id.into(), name, EventSource::default())?,
+        })
+    }
+
+    /// Is a device plugged in?
+    pub fn is_connected(&self, id: Id) -> bool {
+        self.context.is_connected(id.into())
+    }
+
+    /// Retrieve the device type (`DEVTYPE`) of a device, such as `usb_device` or `usb_interface`.
+    ///
+    /// Unless the context was built with `ContextBuilder::match_devtype`, devices of the `usb`
+    /// subsystem are always `usb_device`, so every event refers to a whole device. Like the IDs,
+    /// this stays available after the device is removed, to make sense of its remove event.
+    pub fn devtype(&self, id: Id) -> Option<String> {
+        self.context.devtype(id.into())
+    }
+
+    /// Retrieve the USB vendor ID of a device.
+    pub fn vendor_id(&self, id: Id) -> Option<VendorId> {
+        self.add(id);
+        self.metadata.borrow().get((id.0).0 as usize)?.vendor_id
+    }
+
+    /// Retrieve the USB product ID of a device.
+    pub fn product_id(&self, id: Id) -> Option<ProductId> {
+        self.add(id);
+        self.metadata.borrow().get((id.0).0 as usize)?.product_id
+    }
+
+    // Run a lookup against usb.ids, loading it on first use.
+    #[cfg(feature = "usb-ids")]
+    fn with_usb_ids<T>(&self, f: impl FnOnce(&usb_ids::UsbIds) -> T) -> T {
+        let mut usb_ids = self.usb_ids.borrow_mut();
+        f(usb_ids.get_or_insert_with(usb_ids::UsbIds::load))
+    }
+
+    /// Look up the vendor name of a device in the usb.ids database.
+    ///
+    /// Unlike `manufacturer_string`, this works for devices without string descriptors, but
+    /// only for vendors known to usb.ids. The system's copy is read the first time a name is
+    /// looked up, unless the `usb-ids-embedded` feature compiled one in.
+    #[cfg(feature = "usb-ids")]
+    pub fn vendor_name(&self, id: Id) -> Option<String> {
+        let vendor_id = self.vendor_id(id)?;
+        self.with_usb_ids(|usb_ids| usb_ids.vendor(vendor_id.0).map(String::from))
+    }
+
+    /// Look up the product name of a device in the usb.ids database.
+    #[cfg(feature = "usb-ids")]
+    pub fn product_name(&self, id: Id) -> Option<String> {
+        let vendor_id = self.vendor_id(id)?;
+        let product_id = self.product_id(id)?;
+        self.with_usb_ids(|usb_ids| usb_ids.product(vendor_id.0, product_id.0).map(String::from))
+    }
+
+    /// Register the quirks of a device model, replacing any the crate knows about.
+    pub fn register_quirks(
+        &self,
+        vendor_id: VendorId,
+        product_id: ProductId,
+        quirks: quirks::Quirks,
+    ) {
+        self.quirks.borrow_mut().insert((vendor_id, product_id), quirks);
+    }
+
+    /// Register the hooks working around a device model's quirks, replacing any registered
+    /// before.
+    pub fn register_quirk_hook(
+        &self,
+        vendor_id: VendorId,
+        product_id: ProductId,
+        hook: impl quirks::Quirk + 'static,
+    ) {
+        self.quirk_hooks.borrow_mut().insert((vendor_id, product_id), Arc::new(hook));
+    }
+
+    // Look up the hooks registered for a device's model.
+    fn quirk_hook(&self, id: Id) -> Option<Arc<dyn quirks::Quirk>> {
+        let key = (self.vendor_id(id)?, self.product_id(id)?);
+        self.quirk_hooks.borrow().get(&key).cloned()
+    }
+
+    // Run the hook registered for a device's model before its usbfs node is opened.
+    fn before_open(&self, id: Id) -> Result<(), Error> {
+        match self.quirk_hook(id) {
+            Some(hook) => hook.before_open(self, id),
+            None => Ok(()),
+        }
+    }
+
+    // Wrap a freshly claimed interface, running the hook registered for the device's model.
+    fn claimed(
+        &self,
+        id: Id,
+        interface: os::ClaimedInterface,
+    ) -> Result<InterfaceHandle, ClaimError> {
+        let disconnection = {
+            let mut claimed = self.claimed.borrow_mut();
+            claimed.retain(|_, disconnection| disconnection.strong_count() > 0);
+            let existing = claimed.get(&id).and_then(Weak::upgrade);
+            existing.unwrap_or_else(|| {
+                let disconnection = Arc::new(Signal::default());
+                claimed.insert(id, Arc::downgrade(&disconnection));
+                disconnection
+            })
+        };
+        let handle = InterfaceHandle {
+            interface,
+            quirks: self.quirks(id),
+            quirk_hook: self.quirk_hook(id),
+            disconnection,
+            watchdog: None,
+            latencies: None,
+            clock: Arc::new(clock::SystemClock),
+            bus_counters: self.bus_number(id).ok().map(|bus| {
+                let mut counters = self.bus_counters.borrow_mut();
+                counters.entry(bus).or_default().clone()
+            }),
+        };
+        if let Some(hook) = &handle.quirk_hook {
+            hook.after_claim(&handle)?;
+        }
+        Ok(handle)
+    }
+
+    /// Retrieve the quirks of a device.
+    pub fn quirks(&self, id: Id) -> quirks::Quirks {
+        let (vendor_id, product_id) = match (self.vendor_id(id), self.product_id(id)) {
+            (Some(vendor_id), Some(product_id)) => (vendor_id, product_id),
+            _ => return quirks::Quirks::default(),
+        };
+        self.quirks
+            .borrow()
+            .get(&(vendor_id, product_id))
+            .copied()
+            .unwrap_or_else(|| quirks::builtin(vendor_id.0, product_id.0))
+    }
+
+    /// Retrieve the USB manufacturer string of a device.
+    pub fn manufacturer_string(&self, id: Id) -> Result<String, Error> {
+        if self.quirks(id).ignore_strings {
+            return Err(Error::Io(io::ErrorKind::NotFound));
+        }
+        self.context
+            .manufacturer_string(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the USB product string of a device.
+    pub fn product_string(&self, id: Id) -> Result<String, Error> {
+        if self.quirks(id).ignore_strings {
+            return Err(Error::Io(io::ErrorKind::NotFound));
+        }
+        self.context
+            .product_string(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the USB serial number string of a device.
+    pub fn serial_number(&self, id: Id) -> Result<String, Error> {
+        let quirks = self.quirks(id);
+        if quirks.ignore_strings || quirks.non_unique_serial {
+            return Err(Error::Io(io::ErrorKind::NotFound));
+        }
+        self.context
+            .serial_number(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the path of a device's usbfs node, e.g. `/dev/bus/usb/001/004`.
+    pub fn devnode(&self, id: Id) -> Result<PathBuf, Error> {
+        self.context
+            .devnode(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the udev properties of a device, such as `ID_MODEL`, `ID_SERIAL` or
+    /// `ID_USB_INTERFACES`.
+    ///
+    /// A context reading sysfs directly only has the properties the kernel provides.
+    pub fn properties(&self, id: Id) -> Result<HashMap<String, String>, Error> {
+        self.context
+            .properties(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Open a device's usbfs node for reading and writing, retrying while it's busy or not yet
+    /// accessible.
+    ///
+    /// Right after a device is added, udev rules may still be setting its permissions and
+    /// drivers may still be probing it, so opening it fails for a moment. This retries with
+    /// exponential backoff until `timeout` has passed, then fails with the last error. The file
+    /// can be handed to a USB library that accepts file descriptors.
+    ///
+    /// If the context locks devices, the file holds the device's lock until it's closed, and a
+    /// device another process holds locked counts as busy.
+    pub fn open_devnode(
+        &self,
+        id: Id,
+        timeout: Duration,
+    ) -> impl Future<Item = fs::File, Error = Error> + '_ {
+        let deadline = Instant::now() + timeout;
+        future::lazy(move || self.before_open(id)).and_then(move |()| {
+            future::loop_fn(Duration::from_millis(10), move |backoff| {
+                let retry = Instant::now() + backoff;
+                match self.context.try_open(id.into()) {
+                    Ok(Ok(file)) => {
+                        return future::Either::A(future::ok(future::Loop::Break(file)))
+                    }
+                    Ok(Err(kind)) if retry > deadline => {
+                        return future::Either::A(future::err(Error::Io(kind)))
+                    }
+                    Ok(Err(_)) => {}
+                    Err(err) => return future::Either::A(future::err(err.into())),
+                }
+                future::Either::B(
+                    Delay::new(retry)
+                        .map_err(|_| Error::Io(io::ErrorKind::Other))
+                        .map(move |()| future::Loop::Continue(backoff * 2)),
+                )
+            })
+        })
+    }
+
+    /// List the processes with a device's usbfs node open, such as the one keeping it busy.
+    ///
+    /// Processes of other users can only be seen by root. Interfaces claimed by kernel drivers
+    /// show up in `Context::interfaces` instead.
+    pub fn devnode_users(&self, id: Id) -> Result<Vec<Process>, Error> {
+        self.context
+            .devnode_users(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// List the languages a device provides its strings in, read from string descriptor 0.
+    ///
+    /// The string accessors return strings in the first of these, as picked by the kernel. This
+    /// asks the device, so it needs write access to its device node. Devices without any strings
+    /// usually refuse the request, returning an I/O error.
+    pub fn languages(&self, id: Id) -> Result<Vec<LanguageId>, Error> {
+        let languages = self.context.languages(id.into());
+        if self.quirks(id).delay_control_messages {
+            thread::sleep(quirks::CONTROL_MESSAGE_DELAY);
+        }
+        languages
+            .map(|languages| languages.into_iter().map(LanguageId).collect())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Read a device's status with a `GET_STATUS` request.
+    ///
+    /// This asks the device, so it needs write access to its device node, and blocks until it
+    /// answers.
+    pub fn device_status(&self, id: Id) -> Result<DeviceStatus, Error> {
+        let mut status = [0; 2];
+        let request = SetupPacket::get_status(Recipient::Device, 0);
+        if self.control(id, &request, &mut status)? < status.len() {
+            return Err(Error::Io(io::ErrorKind::InvalidData));
+        }
+        Ok(DeviceStatus::from_bytes(status))
+    }
+
+    /// Allow or forbid a device to wake the host from suspend, with a `SET_FEATURE` or
+    /// `CLEAR_FEATURE` request.
+    ///
+    /// Only devices whose configuration declares remote wakeup support it. The kernel sets this
+    /// itself when suspending devices, according to their `power/wakeup` attribute. This needs
+    /// write access to the device node, and blocks until the device answers.
+    pub fn set_remote_wakeup(&self, id: Id, enabled: bool) -> Result<(), Error> {
+        let request = if enabled {
+            SetupPacket::set_feature(Recipient::Device, feature::DEVICE_REMOTE_WAKEUP, 0)
+        } else {
+            SetupPacket::clear_feature(Recipient::Device, feature::DEVICE_REMOTE_WAKEUP, 0)
+        };
+        self.control(id, &request, &mut []).map(|_| ())
+    }
+
+    /// Put a high speed device into one of the USB 2.0 electrical test modes, for compliance
+    /// testing.
+    ///
+    /// The device stops working as a USB device until it's power cycled, and the host
+    /// controller may report it disconnected. This needs write access to the device node.
+    pub fn enter_test_mode(&self, id: Id, mode: TestMode) -> Result<(), Error> {
+        self.control(id, &SetupPacket::set_test_mode(mode), &mut []).map(|_| ())
+    }
+
+    // Make a control transfer to a device, then give devices that need it their pause.
+    fn control(&self, id: Id, request: &SetupPacket, data: &mut [u8]) -> Result<usize, Error> {
+        let result = self.context.control(id.into(), request, data);
+        if self.quirks(id).delay_control_messages {
+            thread::sleep(quirks::CONTROL_MESSAGE_DELAY);
+        }
+        result.map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the libudev device behind a device's handle, to query attributes, properties and
+    /// tags this crate doesn't model.
+    ///
+    /// This ties callers to the Linux backend and to the version of the `udev` crate this crate
+    /// depends on, so prefer the typed accessors where they exist. libudev always reads `/sys`,
+    /// so this fails for contexts reading sysfs mounted elsewhere.
+    #[cfg(all(target_os = "linux", feature = "raw-udev"))]
+    pub fn udev_device(&self, id: Id) -> Result<udev::Device, Error> {
+        self.context
+            .udev_device(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the number of the bus a device is attached to.
+    pub fn bus_number(&self, id: Id) -> Result<u8, Error> {
+        self.context
+            .bus_number(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the address of a device on its bus.
+    pub fn device_address(&self, id: Id) -> Result<u8, Error> {
+        self.context
+            .device_address(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// List the connected USB buses, ordered by bus number.
+    pub fn buses(&self) -> Vec<Bus> {
+        self.context.buses()
+    }
+
+    /// Retrieve how much periodic bandwidth is reserved on a bus, given by number.
+    ///
+    /// This is read from debugfs, which has to be mounted and is usually only readable by root.
+    /// Host controllers that manage bandwidth themselves, like xHCI, report nothing reserved.
+    /// The kernel doesn't count frames or errors per bus, so those come from the transfers
+    /// made through this context, as `transfer_counts` also gives without needing debugfs.
+    pub fn bus_usage(&self, number: u8) -> Result<BusUsage, Error> {
+        let mut usage = self.context.bus_usage(number)?;
+        usage.transfers = self.transfer_counts(number);
+        Ok(usage)
+    }
+
+    /// Count the transfers made on a bus, given by number, through the interfaces this context
+    /// claimed, and how they failed.
+    pub fn transfer_counts(&self, number: u8) -> TransferCounts {
+        self.bus_counters
+            .borrow()
+            .get(&number)
+            .map(|counters| counters.counts())
+            .unwrap_or_default()
+    }
+
+    /// Find out whether a device is reached through a USB4 or Thunderbolt tunnel, and through
+    /// which router.
+    ///
+    /// Only tunnelled PCIe, as used by docks with their own host controller, is detected;
+    /// devices on USB3 tunnelled straight from the host controller look like any other.
+    pub fn tunnel(&self, id: Id) -> Result<Option<Tunnel>, Error> {
+        self.context
+            .tunnel(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Is a device a hub?
+    pub fn is_hub(&self, id: Id) -> Result<bool, Error> {
+        self.context
+            .is_hub(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the class code of a device, which is 0 when each interface declares its own.
+    pub fn device_class(&self, id: Id) -> Result<u8, Error> {
+        self.context
+            .device_class(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Is a device on a port users can plug devices into, as opposed to a fixed, internal one
+    /// such as a laptop's webcam?
+    ///
+    /// This is how the hub or ACPI describes the port the device is plugged into. Returns `None`
+    /// if they don't say, which is common for hubs and devices behind them.
+    pub fn is_removable(&self, id: Id) -> Result<Option<bool>, Error> {
+        self.context
+            .is_removable(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Can a device wake the system from suspend?
+    ///
+    /// Returns `None` if the device isn't capable of remote wakeup at all.
+    pub fn wakeup(&self, id: Id) -> Result<Option<bool>, Error> {
+        self.context
+            .wakeup(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Allow or forbid a device to wake the system from suspend. This needs root.
+    pub fn set_wakeup(&self, id: Id, enabled: bool) -> Result<(), Error> {
+        self.context
+            .set_wakeup(id.into(), enabled)
+            .map_err(std::convert::Into::into)
+    }
+
+    /// List the connected devices allowed to wake the system from suspend.
+    pub fn wakeup_devices(&self) -> Vec<Id> {
+        self.connected_devices()
+            .filter(|id| self.wakeup(*id) == Ok(Some(true)))
+            .collect()
+    }
+
+    /// Retrieve the speed and lanes of a device's link.
+    ///
+    /// A link slower than the device and the port it's plugged into support points at a poor
+    /// cable or a hub in between. Kernels before 4.18 don't report lanes, so they are taken to
+    /// be 1.
+    pub fn link_rate(&self, id: Id) -> Result<LinkRate, Error> {
+        self.context
+            .link_rate(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the port path of a device: its bus number and the hub ports leading to it, such
+    /// as `1-4.2`.
+    pub fn port_path(&self, id: Id) -> Result<PortPath, Error> {
+        let path = self.context.port_path(id.into())?;
+        path.parse().map_err(|_| Error::Io(io::ErrorKind::InvalidData))
+    }
+
+    /// List the downstream ports of a hub, with their over-current counters.
+    pub fn ports(&self, id: Id) -> Result<Vec<Port>, Error> {
+        self.context
+            .ports(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the Type-C connector a device is plugged into, directly or through hubs, with its
+    /// roles, orientation and alternate modes.
+    ///
+    /// Returns `None` if the device isn't behind a Type-C connector, or the kernel (before 5.19)
+    /// or the platform doesn't say which one.
+    pub fn typec_port(&self, id: Id) -> Result<Option<TypeCPort>, Error> {
+        self.context
+            .typec_port(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the number of downstream ports of a hub.
+    ///
+    /// This is 0 for devices that aren't hubs.
+    pub fn num_ports(&self, id: Id) -> Result<u8, Error> {
+        self.context
+            .num_ports(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the paths hidapi opens a device's HID interfaces by, with their interface
+    /// numbers.
+    ///
+    /// These are hidraw device nodes such as `/dev/hidraw3`, as used by hidapi's default hidraw
+    /// backend on Linux, and can be passed to `HidApi::open_path`. A device may have several HID
+    /// interfaces, such as a keyboard's keys and media controls.
+    pub fn hidapi_paths(&self, id: Id) -> Result<Vec<(u8, String)>, Error> {
+        Ok(self
+            .context
+            .hidraw_nodes(id.into())?
+            .into_iter()
+            .map(|(interface, node)| (interface, node.to_string_lossy().into_owned()))
+            .collect())
+    }
+
+    /// List the interfaces of a device's active configuration, ordered by interface number.
+    ///
+    /// This only reads sysfs, so it works without permission to open the device.
+    pub fn interfaces(&self, id: Id) -> Result<Vec<Interface>, Error> {
+        self.context
+            .interfaces(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Retrieve the value of a device's active configuration (`bConfigurationValue`).
+    ///
+    /// Returns `None` for unconfigured devices, such as those waiting to be authorized.
+    pub fn configuration(&self, id: Id) -> Result<Option<u8>, Error> {
+        self.context
+            .configuration(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Select a device's configuration by writing its value to the sysfs `bConfigurationValue`
+    /// file, or unconfigure it with 0.
+    ///
+    /// The kernel sends `SET_CONFIGURATION` and rebinds drivers to the new interfaces. This
+    /// needs root. For devices with `Quirks::delay_after_set_configuration`, it then pauses
+    /// before returning.
+    pub fn set_configuration(&self, id: Id, configuration: u8) -> Result<(), Error> {
+        self.context.set_configuration(id.into(), configuration)?;
+        if self.quirks(id).delay_after_set_configuration {
+            thread::sleep(quirks::CONFIGURATION_DELAY);
+        }
+        Ok(())
+    }
+
+    /// Read the raw descriptors of a device: its device descriptor, followed by each of its
+    /// configurations with their interface, endpoint and class-specific descriptors.
+    ///
+    /// These are cached by the kernel, so reading them doesn't disturb the device and works
+    /// without permission to open it, even before it is authorized. Use `descriptor::walk` to
+    /// go through them.
+    pub fn descriptors(&self, id: Id) -> Result<Vec<u8>, Error> {
+        self.context
+            .descriptors(id.into())
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Group the interfaces of a device's active configuration into the functions they
+    /// implement.
+    ///
+    /// Interfaces are grouped as the device's interface association descriptors say. Interfaces
+    /// outside of any association are grouped by class: a CDC communications interface takes
+    /// the CDC data interfaces right after it, and audio and video control interfaces take the
+    /// streaming interfaces right after them. Any other interface is a function of its own.
+    pub fn functions(&self, id: Id) -> Result<Vec<Function>, Error> {
+        // Audio, CDC and video interfaces that belong to the control interface before them.
+        fn follows(control: &Interface, interface: &Interface) -> bool {
+            matches!(
+                (control.class, control.subclass, interface.class, interface.subclass),
+                (0x01, 0x01, 0x01, 0x02)
+                    | (0x01, 0x01, 0x01, 0x03)
+                    | (0x02, _, 0x0a, _)
+                    | (0x0e, 0x01, 0x0e, 0x02)
+            )
+        }
+
+        let associations = match self.configuration(id)? {
+            Some(configuration) => {
+                descriptor::interface_associations(&self.descriptors(id)?, configuration)
+            }
+            None => Vec::new(),
+        };
+        let mut functions: Vec<Function> = Vec::new();
+        let mut association = None;
+        for interface in self.interfaces(id)? {
+            let current = associations
+                .iter()
+                .position(|association| association.contains(interface.number));
+            if let Some(function) = functions.last_mut() {
+                let grouped = match current {
+                    Some(_) => current == association,
+                    None => {
+                        association.is_none()
+                            && follows(&function.interfaces[0], &interface)
+                            && function.interfaces.last().map(|last| last.number)
+                                == interface.number.checked_sub(1)
+                    }
+                };
+                if grouped {
+                    function.interfaces.push(interface);
+                    continue;
+                }
+            }
+
+            association = current;
+            let (class, subclass, protocol) = match current {
+                Some(index) => {
+                    let association = &associations[index];
+                    (association.class, association.subclass, association.protocol)
+                }
+                None => (interface.class, interface.subclass, interface.protocol),
+            };
+            functions.push(Function {
+                class,
+                subclass,
+                protocol,
+                interfaces: vec![interface],
+            });
+        }
+
+        Ok(functions)
+    }
+
+    /// Claim every interface of a function, as returned by `Context::functions`.
+    ///
+    /// Either every interface is claimed or none is.
+    pub fn claim_function(
+        &self,
+        id: Id,
+        function: &Function,
+    ) -> Result<Vec<InterfaceHandle>, ClaimError> {
+        function
+            .interfaces
+            .iter()
+            .map(|interface| self.claim_interface(id, interface.number))
+            .collect()
+    }
+
+    /// Claim an interface of a device's active configuration, to use it from userspace.
+    ///
+    /// This opens the device's usbfs node, so it needs write permission on it. It fails with
+    /// `io::ErrorKind::NotFound` if the interface doesn't exist, and with `ClaimError::Busy`
+    /// while a kernel driver or another process has the interface claimed, naming them.
+    pub fn claim_interface(&self, id: Id, number: u8) -> Result<InterfaceHandle, ClaimError> {
+        self.claim_interface_with(id, number, &ClaimOptions::default())
+    }
+
+    /// Claim an interface of a device's active configuration, as set out in `options`.
+    ///
+    /// Like `claim_interface`, but driver names too long for the kernel fail with
+    /// `io::ErrorKind::InvalidInput`.
+    pub fn claim_interface_with(
+        &self,
+        id: Id,
+        number: u8,
+        options: &ClaimOptions,
+    ) -> Result<InterfaceHandle, ClaimError> {
+        self.before_open(id)?;
+        match self.context.claim_interface(id.into(), number, options.disconnect.as_ref()) {
+            Ok(interface) => self.claimed(id, interface),
+            Err(err) => Err(self.claim_error(id, number, err)),
+        }
+    }
+
+    /// Claim an interface through a device's usbfs node opened elsewhere, such as by a
+    /// privileged broker process, or passed in by systemd.
+    ///
+    /// File descriptors received that way become files with `File::from_raw_fd`, or `From`
+    /// an `OwnedFd`. The device is found from the node's device number, so the node needn't be
+    /// reachable from this process, but the context has to track the device. Otherwise this is
+    /// like `claim_interface_with`, failing with `Error::NotConnected` for nodes of devices the
+    /// context doesn't track. The node isn't locked, even if the context locks devices.
+    pub fn claim_interface_from_file(
+        &self,
+        device: fs::File,
+        number: u8,
+        options: &ClaimOptions,
+    ) -> Result<InterfaceHandle, ClaimError> {
+        let id = self.find_by_file(&device).ok_or(Error::NotConnected)?;
+        let disconnect = options.disconnect.as_ref();
+        match self.context.claim_interface_of(id.into(), number, disconnect, Arc::new(device)) {
+            Ok(interface) => self.claimed(id, interface),
+            Err(err) => Err(self.claim_error(id, number, err)),
+        }
+    }
+
+    /// Claim an interface, retrying while it's busy or the device node isn't accessible yet.
+    ///
+    /// Right after a device is added, udev rules may still be setting its permissions and
+    /// kernel drivers may still be probing it, so claiming it fails for a moment. Like
+    /// `open_devnode`, this retries with exponential backoff until `timeout` has passed, then
+    /// fails with the last error. A driver that stays bound keeps the interface busy, so unbind
+    /// it first.
+    pub fn claim_interface_retry(
+        &self,
+        id: Id,
+        number: u8,
+        timeout: Duration,
+    ) -> impl Future<Item = InterfaceHandle, Error = ClaimError> + '_ {
+        let deadline = Instant::now() + timeout;
+        future::lazy(move || self.before_open(id)).from_err().and_then(move |()| {
+            future::loop_fn(Duration::from_millis(10), move |backoff| {
+                let retry = Instant::now() + backoff;
+                match self.context.claim_interface(id.into(), number, None) {
+                    Ok(interface) => {
+                        let handle = self.claimed(id, interface).map(future::Loop::Break);
+                        return future::Either::A(future::result(handle));
+                    }
+                    Err(os::UsbError::Io(io::ErrorKind::ResourceBusy))
+                    | Err(os::UsbError::Io(io::ErrorKind::PermissionDenied))
+                        if retry <= deadline => {}
+                    Err(err) => {
+                        return future::Either::A(future::err(self.claim_error(id, number, err)))
+                    }
+                }
+                future::Either::B(
+                    Delay::new(retry)
+                        .map_err(|_| ClaimError::Other(Error::Io(io::ErrorKind::Other)))
+                        .map(move |()| future::Loop::Continue(backoff * 2)),
+                )
+            })
+        })
+    }
+
+    // Explain why claiming an interface failed, finding out what has it if it's busy.
+    fn claim_error(&self, id: Id, number: u8, err: os::UsbError) -> ClaimError {
+        if err != os::UsbError::Io(io::ErrorKind::ResourceBusy) {
+            return err.into();
+        }
+        ClaimError::Busy {
+            driver: self
+                .interfaces(id)
+                .ok()
+                .and_then(|interfaces| {
+                    interfaces.into_iter().find(|interface| interface.number == number)
+                })
+                .and_then(|interface| interface.driver),
+            users: self.devnode_users(id).unwrap_or_default(),
+        }
+    }
+
+    /// Gather what is known about a device.
+    pub fn info(&self, id: Id) -> Result<DeviceInfo, Error> {
+        self.detach(id)?.read()
+    }
+
+    fn detach(&self, id: Id) -> Result<DetachedInfo, Error> {
+        Ok(DetachedInfo {
+            id,
+            vendor_id: self.vendor_id(id),
+            product_id: self.product_id(id),
+            quirks: self.quirks(id),
+            dir: self.context.device_dir(id.into())?,
+        })
+    }
+
+    /// Gather what is known about every connected device.
+    pub fn snapshot(&self) -> Vec<DeviceInfo> {
+        self.connected_devices()
+            .filter_map(|id| self.info(id).ok())
+            .collect()
+    }
+
+    /// Gather what is known about a device, without holding up other tasks while sysfs is read.
+    ///
+    /// Only the cached IDs come from the context; the future doesn't borrow it, and can be sent
+    /// to another thread. Like `ContextBuilder::build_async`, this only helps on a tokio thread
+    /// pool.
+    pub fn info_async(&self, id: Id) -> impl Future<Item = DeviceInfo, Error = Error> + Send {
+        let detached = self.detach(id);
+        blocking(move || detached?.read()).and_then(future::result)
+    }
+
+    /// Gather what is known about every connected device, without holding up other tasks while
+    /// sysfs is read.
+    ///
+    /// Like `info_async`, the future can be sent to another thread.
+    pub fn snapshot_async(&self) -> impl Future<Item = Vec<DeviceInfo>, Error = Error> + Send {
+        let detached: Vec<_> = self
+            .connected_devices()
+            .filter_map(|id| self.detach(id).ok())
+            .collect();
+        blocking(move || {
+            detached
+                .into_iter()
+                .filter_map(|detached| detached.read().ok())
+                .collect()
+        })
+    }
+
+    /// Record a device in a registry as seen now.
+    ///
+    /// The registry isn't saved; call `Registry::save` when done recording.
+    #[cfg(feature = "json")]
+    pub fn record(&self, registry: &mut registry::Registry, id: Id) -> Result<(), Error> {
+        registry.see(
+            self.vendor_id(id).ok_or(Error::NotConnected)?,
+            self.product_id(id).ok_or(Error::NotConnected)?,
+            self.serial_number(id).ok(),
+            self.manufacturer_string(id).ok(),
+            self.product_string(id).ok(),
+        );
+        Ok(())
+    }
+
+    /// Find a device in a rusb context, by its bus number and address.
+    ///
+    /// This lets code built on rusb take over devices found through this crate's hotplug
+    /// monitors.
+    #[cfg(feature = "rusb")]
+    pub fn rusb_device<T: rusb::UsbContext>(
+        &self,
+        context: &T,
+        id: Id,
+    ) -> Result<rusb::Device<T>, Error> {
+        let bus_number = self.bus_number(id)?;
+        let address = self.device_address(id)?;
+        let devices = context.devices().map_err(|_| Error::Io(io::ErrorKind::Other))?;
+        devices
+            .iter()
+            .find(|dev| dev.bus_number() == bus_number && dev.address() == address)
+            .ok_or(Error::NotConnected)
+    }
+
+    /// Open a device with rusb.
+    #[cfg(feature = "rusb")]
+    pub fn open_rusb<T: rusb::UsbContext>(
+        &self,
+        context: &T,
+        id: Id,
+    ) -> Result<rusb::DeviceHandle<T>, Error> {
+        self.rusb_device(context, id)?.open().map_err(|err| match err {
+            rusb::Error::Access => Error::Io(io::ErrorKind::PermissionDenied),
+            rusb::Error::NoDevice | rusb::Error::NotFound => Error::NotConnected,
+            _ => Error::Io(io::ErrorKind::Other),
+        })
+    }
+
+    /// Find the nusb description of a device, by its bus number and address.
+    #[cfg(feature = "nusb")]
+    pub fn nusb_device_info(&self, id: Id) -> Result<nusb::DeviceInfo, Error> {
+        let bus_number = self.bus_number(id)?;
+        let address = self.device_address(id)?;
+        nusb::list_devices()
+            .map_err(|err| Error::Io(err.kind()))?
+            .find(|dev| dev.bus_number() == bus_number && dev.device_address() == address)
+            .ok_or(Error::NotConnected)
+    }
+
+    /// Open a device with nusb.
+    #[cfg(feature = "nusb")]
+    pub fn open_nusb(&self, id: Id) -> Result<nusb::Device, Error> {
+        self.nusb_device_info(id)?
+            .open()
+            .map_err(|err| Error::Io(err.kind()))
+    }
+
+    /// Authorize a device for use, or deauthorize it, by writing to its sysfs `authorized` file.
+    ///
+    /// Deauthorizing a device unbinds its drivers and keeps new ones from binding. This needs
+    /// root.
+    pub fn authorize(&self, id: Id, authorized: bool) -> Result<(), Error> {
+        self.context
+            .authorize(id.into(), authorized)
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Detach the kernel driver bound to an interface of a device, by writing to the driver's
+    /// sysfs `unbind` file.
+    ///
+    /// Succeeds without doing anything if no driver is bound. This needs root.
+    pub fn unbind_driver(&self, id: Id, interface: u8) -> Result<(), Error> {
+        self.context
+            .unbind_driver(id.into(), interface)
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Bind a kernel driver, such as `usbhid` or an out-of-tree one, to an interface of a device
+    /// by writing to the driver's sysfs `bind` file.
+    ///
+    /// The interface must not already have a driver bound. This needs root. Driver names that
+    /// aren't a single path component fail with `io::ErrorKind::InvalidInput`.
+    pub fn bind_driver(&self, id: Id, interface: u8, driver: &str) -> Result<(), Error> {
+        self.context
+            .bind_driver(id.into(), interface, driver)
+            .map_err(std::convert::Into::into)
+    }
+
+    /// Iterate through all devices, both connected and disconnected.
+    ///
+    /// Use `connected_devices` to only iterate over currently plugged in devices.
+    pub fn devices(&self) -> impl Iterator<Item = Id> {
+        self.context.devices().map(Id)
+    }
+
+    /// List every device seen since the context was created, in the order they were seen.
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        let mut connections = HashMap::new();
+        let mut history = Vec::new();
+        for id in self.devices() {
+            self.add(id);
+            let (vendor_id, product_id) = (self.vendor_id(id), self.product_id(id));
+            let attachments = self.attachments.borrow();
+            let attachment = &attachments[usize::from(id.0)];
+            let count = connections
+                .entry((vendor_id, product_id, attachment.serial_number.clone()))
+                .or_insert(0);
+            *count += 1;
+            history.push(HistoryEntry {
+                id,
+                vendor_id,
+                product_id,
+                serial_number: attachment.serial_number.clone(),
+                connected_at: attachment.connected_at,
+                disconnected_at: attachment.disconnected_at,
+                connected: self.is_connected(id),
+                connections: *count,
+            });
+        }
+        history
+    }
+
+    /// Iterate through connected devices.
+    pub fn connected_devices(&self) -> impl Iterator<Item = Id> + '_ {
+        self.devices().filter(move |id| self.is_connected(*id))
+    }
+
+    /// List the connected devices with a device class, such as 0x09 for hubs.
+    ///
+    /// Most devices declare their class per interface and have a device class of 0; use
+    /// `devices_with_interface_class` to find those.
+    pub fn devices_by_class(&self, class: u8) -> Vec<Id> {
+        self.connected_devices()
+            .filter(|id| self.device_class(*id) == Ok(class))
+            .collect()
+    }
+
+    /// List the connected devices with an interface of a class, such as 0x03 for HID.
+    pub fn devices_with_interface_class(&self, class: u8) -> Vec<Id> {
+        self.connected_devices()
+            .filter(|id| {
+                self.interfaces(*id)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|interface| interface.class == class)
+            })
+            .collect()
+    }
+
+    /// List the interfaces of connected devices claimed by a kernel driver, such as `cdc_acm`,
+    /// as pairs of a device and an interface number.
+    pub fn devices_bound_to(&self, driver: &str) -> Vec<(Id, u8)> {
+        self.connected_devices()
+            .flat_map(|id| {
+                self.interfaces(id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|interface| interface.driver.as_deref() == Some(driver))
+                    .map(move |interface| (id, interface.number))
+            })
+            .collect()
+    }
+
+    /// Find the connected device with a usbfs node, such as `/dev/bus/usb/001/004` or a symlink
+    /// to it.
+    pub fn find_by_devnode<P: AsRef<Path>>(&self, devnode: P) -> Option<Id> {
+        let devnode = devnode.as_ref();
+        let resolved = devnode.canonicalize().ok();
+        self.connected_devices().find(|id| {
+            self.devnode(*id).map_or(false, |node| {
+                node == devnode || Some(&node) == resolved.as_ref()
+            })
+        })
+    }
+
+    /// Find the connected device with a sysfs path, such as `/sys/bus/usb/devices/1-4` or the
+    /// `/sys/devices/...` path it links to.
+    pub fn find_by_syspath<P: AsRef<Path>>(&self, syspath: P) -> Option<Id> {
+        self.context.find_by_syspath(syspath.as_ref()).map(Id)
+    }
+
+    /// Find the connected device an open device node belongs to, such as one from
+    /// `open_devnode`.
+    pub fn find_by_file(&self, file: &fs::File) -> Option<Id> {
+        self.context.find_by_file(file).map(Id)
+    }
+
+    /// Find the connected device with a serial number.
+    ///
+    /// Devices whose quirks mark their serial numbers as not unique are never found. If several
+    /// models may share serial numbers, use `find_by_ids_and_serial` instead.
+    pub fn find_by_serial(&self, serial_number: &str) -> Option<Id> {
+        self.connected_devices()
+            .find(|id| self.serial_number(*id).ok().as_deref() == Some(serial_number))
+    }
+
+    /// Find the connected device with a vendor ID, product ID and serial number.
+    pub fn find_by_ids_and_serial(
+        &self,
+        vendor_id: VendorId,
+        product_id: ProductId,
+        serial_number: &str,
+    ) -> Option<Id> {
+        self.connected_devices().find(|id| {
+            self.vendor_id(*id) == Some(vendor_id)
+                && self.product_id(*id) == Some(product_id)
+                && self.serial_number(*id).ok().as_deref() == Some(serial_number)
+        })
+    }
+
+    /// Find the connected device with a bus number and address, as shown by `lsusb`.
+    pub fn find_by_address(&self, bus_number: u8, address: u8) -> Option<Id> {
+        self.connected_devices().find(|id| {
+            self.bus_number(*id).ok() == Some(bus_number)
+                && self.device_address(*id).ok() == Some(address)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usb_ids_parse_from_hex() {
+        assert_eq!("1d6b".parse(), Ok(VendorId::LINUX_FOUNDATION));
+        assert_eq!("0x1D6B".parse(), Ok(VendorId::LINUX_FOUNDATION));
+        assert_eq!("2".parse(), Ok(ProductId::ROOT_HUB_2_0));
+        for id in &["", "0x", "12345", "+1d6", "-1", "1d6g", " 1d6"] {
+            assert_eq!(id.parse::<VendorId>(), Err(ParseIdError), "{:?}", id);
+        }
+        assert_eq!(VendorId::LINUX_FOUNDATION.to_string(), "1d6b");
+        assert_eq!(ProductId::ROOT_HUB_2_0.to_string(), "0002");
+    }
+
+    #[test]
+    fn port_paths() {
+        let path: PortPath = "1-4.2".parse().unwrap();
+        assert_eq!((path.bus(), path.ports()), (1, &[4, 2][..]));
+        assert_eq!(path.to_string(), "1-4.2");
+        let root: PortPath = "usb3".parse().unwrap();
+        assert_eq!((root.bus(), root.ports()), (3, &[][..]));
+        assert_eq!(root.to_string(), "usb3");
+        for path in &["", "1", "1-", "1-4.", "1-4..2", "usb", "usbx", "x-1", "1-256", "1:1.0"] {
+            assert_eq!(path.parse::<PortPath>(), Err(ParsePortPathError), "{:?}", path);
+        }
+
+        let hub: PortPath = "1-4".parse().unwrap();
+        assert_eq!(path.parent(), Some(hub.clone()));
+        assert_eq!(hub.parent(), Some("usb1".parse().unwrap()));
+        assert_eq!(root.parent(), None);
+        assert!(path.is_behind(&hub));
+        assert!(path.is_behind(&"usb1".parse().unwrap()));
+        assert!(!hub.is_behind(&hub));
+        assert!(!hub.is_behind(&path));
+        assert!(!path.is_behind(&"2-4".parse().unwrap()));
+        assert!(!"1-40".parse::<PortPath>().unwrap().is_behind(&hub));
+        // Ports sort depth-first.
+        assert!(hub < path && path < "1-5".parse().unwrap());
+    }
+
+    fn held(events: &[Event]) -> Vec<Event> {
+        let mut limited = RateLimited::new(stream::empty(), 1);
+        for event in events {
+            limited.hold(*event);
+        }
+        limited.held.into_iter().collect()
+    }
+
+    #[test]
+    fn rate_limited_coalescing() {
+        let (a, b) = (Id(os::Id(1)), Id(os::Id(2)));
+        let vendor_id = Changes {
+            vendor_id: true,
+            ..Changes::default()
+        };
+        let product_string = Changes {
+            product_string: true,
+            ..Changes::default()
+        };
+
+        // A device that came and went is forgotten, interfaces and all.
+        assert_eq!(
+            held(&[Event::Add(a), Event::InterfaceAdd(a, 0), Event::Add(b), Event::Remove(a)]),
+            [Event::Add(b)],
+        );
+        // Changes are merged, and dropped for a device that's pending addition or removal.
+        assert_eq!(
+            held(&[
+                Event::Change(a, vendor_id),
+                Event::Change(b, vendor_id),
+                Event::Change(a, product_string),
+            ]),
+            [
+                Event::Change(a, Changes { product_string: true, ..vendor_id }),
+                Event::Change(b, vendor_id),
+            ],
+        );
+        assert_eq!(held(&[Event::Add(a), Event::Change(a, vendor_id)]), [Event::Add(a)]);
+        assert_eq!(held(&[Event::Change(a, vendor_id), Event::Remove(a)]), [Event::Remove(a)]);
+        // Consecutive losses are reported once.
+        assert_eq!(
+            held(&[Event::EventsLost, Event::EventsLost, Event::Add(a), Event::EventsLost]),
+            [Event::EventsLost, Event::Add(a), Event::EventsLost],
+        );
+        // Too many held events are given up on.
+        let flood: Vec<_> = (0..=MAX_HELD_EVENTS as u32)
+            .map(|id| Event::Add(Id(os::Id(id))))
+            .collect();
+        assert_eq!(held(&flood), [Event::EventsLost]);
+    }
+
+    #[test]
+    fn rate_limit_of_zero_passes_everything() {
+        let events: Vec<_> = (0..10).map(|id| Event::Add(Id(os::Id(id)))).collect();
+        let limited = RateLimited::new(stream::iter_ok(events.clone()), 0);
+        assert_eq!(limited.collect().wait(), Ok(events));
+    }
+
+    // Make an empty sysfs tree to build contexts on, unique to a test.
+    fn fake_sysfs(test: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("usb-async-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sys/bus/usb/devices")).unwrap();
+        root
+    }
+
+    // Add a USB device to a fake sysfs tree.
+    fn fake_device(root: &Path, name: &str, vendor_id: &str, product_id: &str) -> PathBuf {
+        let bus = root.join("sys/bus/usb");
+        let device = root.join("sys/devices").join(name);
+        fs::create_dir_all(&device).unwrap();
+        fs::write(device.join("uevent"), "DEVTYPE=usb_device\n").unwrap();
+        fs::write(device.join("idVendor"), vendor_id).unwrap();
+        fs::write(device.join("idProduct"), product_id).unwrap();
+        std::os::unix::fs::symlink(&bus, device.join("subsystem")).unwrap();
+        std::os::unix::fs::symlink(&device, bus.join("devices").join(name)).unwrap();
+        device
+    }
+
+    #[test]
+    fn unregistered_ids_cache_nothing() {
+        let root = fake_sysfs("cache");
+        let context = Context::with_sysfs(root.join("sys"), root.join("dev")).unwrap();
+
+        // Asking about an Id before anything is registered under it...
+        let id = Id(os::Id(0));
+        assert_eq!(context.vendor_id(id), None);
+
+        // ...doesn't keep the device later registered under it from being cached.
+        fake_device(&root, "usb1", "1d6b", "0002");
+        let mut monitor = context
+            .monitor_with_source(EventSource::Poll(Duration::from_secs(3600)))
+            .unwrap();
+        monitor.rescan().unwrap();
+        let registered = (context.vendor_id(id), context.product_id(id));
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(registered, (Some(VendorId::LINUX_FOUNDATION), Some(ProductId::ROOT_HUB_2_0)));
+    }
+
+    #[test]
+    fn builtin_quirks_apply() {
+        let root = fake_sysfs("quirks");
+        // A Corsair Strafe RGB keyboard, in Linux's quirk list.
+        let device = fake_device(&root, "1-1", "1b1c", "1b20");
+        fs::write(device.join("bConfigurationValue"), "").unwrap();
+        let context = Context::with_sysfs(root.join("sys"), root.join("dev")).unwrap();
+        let id = context.connected_devices().next().unwrap();
+
+        let quirks = context.quirks(id);
+        let start = Instant::now();
+        let configured = context.set_configuration(id, 1);
+        let elapsed = start.elapsed();
+        let written = fs::read_to_string(device.join("bConfigurationValue"));
+        let _ = fs::remove_dir_all(&root);
+
+        assert!(quirks.delay_after_set_configuration && quirks.delay_control_messages);
+        assert_eq!((configured, written.unwrap()), (Ok(()), String::from("1")));
+        assert!(elapsed >= quirks::CONFIGURATION_DELAY);
+    }
+
+    #[test]
+    fn devices_found_by_file() {
+        let root = fake_sysfs("file");
+        let device = fake_device(&root, "1-1", "1d6b", "0002");
+        fs::create_dir_all(root.join("sys/dev/char")).unwrap();
+        // Any character device will do, such as /dev/null (1:3).
+        std::os::unix::fs::symlink(&device, root.join("sys/dev/char/1:3")).unwrap();
+        let context = Context::with_sysfs(root.join("sys"), root.join("dev")).unwrap();
+        let id = context.connected_devices().next();
+        let null = context.find_by_file(&fs::File::open("/dev/null").unwrap());
+        let directory = context.find_by_file(&fs::File::open(&device).unwrap());
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!((null, directory), (id, None));
+    }
+
+    #[test]
+    fn removal_disconnects_claimed_interfaces() {
+        let root = fake_sysfs("disconnect");
+        fake_device(&root, "1-1", "1d6b", "0002");
+        let context = Context::with_sysfs(root.join("sys"), root.join("dev")).unwrap();
+        let id = context.connected_devices().next().unwrap();
+        let _ = fs::remove_dir_all(&root);
+
+        // What claiming an interface of the device registers.
+        let disconnection = Arc::new(Signal::default());
+        context.claimed.borrow_mut().insert(id, Arc::downgrade(&disconnection));
+        let mut on_disconnect = future::lazy(|| {
+            let mut on_disconnect = OnDisconnect(disconnection.clone());
+            assert_eq!(on_disconnect.poll(), Ok(Async::NotReady));
+            Ok::<_, ()>(on_disconnect)
+        })
+        .wait()
+        .unwrap();
+        assert!(!disconnection.is_raised());
+        context.remove(id);
+        assert!(disconnection.is_raised());
+        assert!(context.claimed.borrow().is_empty());
+        assert_eq!(on_disconnect.poll(), Ok(Async::Ready(())));
+    }
+
+    #[test]
+    fn watchdog_counts_failures_in_a_row() {
+        let watchdog = Watchdog::new(2);
+        let mut unhealthy = OnUnhealthy(Some(watchdog.unhealthy.clone()));
+        watchdog.record::<()>(&Err(Error::Protocol));
+        watchdog.record(&Ok(()));
+        watchdog.record::<()>(&Err(Error::Io(io::ErrorKind::TimedOut)));
+        watchdog.record::<()>(&Err(Error::Stall));
+        assert!(!watchdog.unhealthy.is_raised());
+        watchdog.record::<()>(&Err(Error::Protocol));
+        watchdog.record::<()>(&Err(Error::Io(io::ErrorKind::TimedOut)));
+        assert!(watchdog.unhealthy.is_raised());
+        assert_eq!(unhealthy.poll(), Ok(Async::Ready(())));
+    }
+
+    #[test]
+    fn latency_buckets() {
+        let mut latency = Latency::default();
+        assert_eq!(latency.mean(), None);
+        latency.record(Duration::from_nanos(500));
+        latency.record(Duration::from_micros(3));
+        latency.record(Duration::from_micros(1000));
+        latency.record(Duration::from_secs(1 << 40));
+        assert_eq!(latency.buckets[..3], [1, 0, 1]);
+        assert_eq!((latency.buckets[10], latency.buckets[LATENCY_BUCKETS - 1]), (1, 1));
+        assert_eq!(latency.transfers, 4);
+        assert_eq!(latency.max, Duration::from_secs(1 << 40));
+    }
+
+    #[test]
+    fn bus_counters_sort_errors() {
+        let counters = BusCounters::default();
+        counters.record(&Ok(64));
+        counters.record::<()>(&Err(os::UsbError::Stall));
+        counters.record::<()>(&Err(os::UsbError::Protocol));
+        counters.record::<()>(&Err(os::UsbError::Io(io::ErrorKind::TimedOut)));
+        counters.record::<()>(&Err(os::UsbError::Io(io::ErrorKind::TimedOut)));
+        counters.record::<()>(&Err(os::UsbError::NotConnected));
+        let counts = TransferCounts {
+            transfers: 6,
+            stalls: 1,
+            overflows: 0,
+            protocol_errors: 1,
+            timeouts: 2,
+        };
+        assert_eq!(counters.counts(), counts);
+    }
+
+    #[test]
+    fn retry_delays() {
+        let policy = RetryPolicy::default()
+            .attempts(3)
+            .backoff(Duration::from_millis(5))
+            .retry_on(Error::Protocol);
+        assert_eq!(policy.delay(1, Error::Protocol), Some(Duration::from_millis(5)));
+        assert_eq!(policy.delay(2, Error::Protocol), Some(Duration::from_millis(10)));
+        assert_eq!(policy.delay(3, Error::Protocol), None);
+        assert_eq!(policy.delay(1, Error::Stall), None);
+        assert_eq!(RetryPolicy::default().delay(1, Error::Protocol), None);
+    }
+
+    #[test]
+    fn locked_devices_are_busy() {
+        let root = fake_sysfs("lock");
+        let device = fake_device(&root, "1-1", "1d6b", "0002");
+        fs::write(device.join("busnum"), "1").unwrap();
+        fs::write(device.join("devnum"), "2").unwrap();
+        fs::create_dir_all(root.join("dev/bus/usb/001")).unwrap();
+        fs::write(root.join("dev/bus/usb/001/002"), "").unwrap();
+        let context = || {
+            ContextBuilder::default()
+                .sysfs(root.join("sys"), root.join("dev"))
+                .lock_devices()
+                .build()
+                .unwrap()
+        };
+        let (first, second) = (context(), context());
+        let unlocked = Context::with_sysfs(root.join("sys"), root.join("dev")).unwrap();
+        let id = first.connected_devices().next().unwrap();
+        let open = |context: &Context| context.open_devnode(id, Duration::from_secs(0)).wait();
+
+        let file = open(&first);
+        let busy = open(&second);
+        let ignored = open(&unlocked);
+        let opened = file.is_ok();
+        drop(file);
+        let released = open(&second);
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(busy.unwrap_err(), Error::Io(io::ErrorKind::ResourceBusy));
+        assert!(opened && ignored.is_ok() && released.is_ok());
+    }
+
+    #[test]
+    fn reconnecting_follows_device() {
+        use reconnect::{Connection, Reconnecting};
+
+        let root = fake_sysfs("reconnect");
+        fake_device(&root, "1-1", "1d6b", "0002");
+        let context = Context::with_sysfs(root.join("sys"), root.join("dev")).unwrap();
+        let monitor = context
+            .monitor_with_source(EventSource::Poll(Duration::from_millis(10)))
+            .unwrap();
+        let rule = policy::Rule::allow()
+            .vendor_ids(VendorId::LINUX_FOUNDATION..=VendorId::LINUX_FOUNDATION);
+        let mut reconnecting =
+            Reconnecting::new(monitor, rule, 0, |_, interface| Ok(interface.number()));
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let mut next = || {
+            let next = future::poll_fn(|| reconnecting.poll());
+            runtime.block_on(next).unwrap().unwrap()
+        };
+
+        // The device has no interface 0, so claiming it fails.
+        let first = next();
+        fs::remove_file(root.join("sys/bus/usb/devices/1-1")).unwrap();
+        let removed = next();
+        fake_device(&root, "1-2", "1d6b", "0002");
+        let second = next();
+        let _ = fs::remove_dir_all(&root);
+
+        let first = match first {
+            Connection::Failed(id, ClaimError::Other(Error::Io(io::ErrorKind::NotFound))) => id,
+            connection => panic!("unexpected {:?}", connection),
+        };
+        assert!(match removed {
+            Connection::Disconnected(id) => id == first,
+            _ => false,
+        });
+        assert!(match second {
+            Connection::Failed(id, _) => id != first,
+            _ => false,
+        });
+    }
+}
+
@@ -1,13 +1,31 @@
 use std::{
     cell::RefCell,
-    error, io,
-    os::unix::io::AsRawFd,
+    collections::{HashMap, VecDeque},
+    error,
+    ffi::{OsStr, OsString},
+    fs, io, mem,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+        io::{AsRawFd, RawFd},
+    },
     path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
 };
 
+use libc;
 use udev;
 use mio;
-use tokio::{prelude::*, reactor};
+use tokio::{prelude::*, reactor, timer::Interval};
+
+use crate::{
+    descriptor::TransferType,
+    setup::{descriptor_type, SetupPacket},
+    uevent::{self, Action},
+    AlternateMode, Bus, BusUsage, ContextBuilder, DisconnectDriver, Endpoint, EventSource, Interface,
+    LinkRate, Port, Process, Router, Speed, Tunnel, TypeCPort,
+};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub struct Id(pub u32);
@@ -24,6 +42,10 @@ pub enum Event {
     Add(Id),
     Remove(Id),
     Change(Id),
+    OverCurrent(Id, u8),
+    InterfaceAdd(Id, u8),
+    InterfaceRemove(Id, u8),
+    EventsLost,
     Unknown,
 }
 
@@ -32,6 +54,25 @@ pub enum UsbError {
     InvalidId,
     NotConnected,
     Io(io::ErrorKind),
+    Stall,
+    Overflow,
+    Protocol,
+}
+
+impl UsbError {
+    // The error of a failed transfer ioctl, telling apart the errors usbfs reports for failures
+    // on the bus.
+    fn transfer() -> Self {
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EPIPE) => UsbError::Stall,
+            Some(libc::EOVERFLOW) => UsbError::Overflow,
+            Some(libc::EPROTO) | Some(libc::EILSEQ) => UsbError::Protocol,
+            // The device is gone, though its node is still open.
+            Some(libc::ENODEV) => UsbError::NotConnected,
+            _ => err.into(),
+        }
+    }
 }
 
 impl From<udev::Error> for UsbError {
@@ -51,10 +92,328 @@ impl From<io::Error> for UsbError {
     }
 }
 
+// The parts of a uevent the monitor cares about, from either event source.
+struct Uevent {
+    action: Action,
+    syspath: PathBuf,
+    subsystem: Option<OsString>,
+    seqnum: u64,
+    // The port a hub reported an over-current condition on.
+    over_current_port: Option<PathBuf>,
+}
+
+impl Uevent {
+    fn from_udev(event: &udev::Event) -> Self {
+        let device = event.device();
+        let action = match event.event_type() {
+            udev::EventType::Add => Action::Add,
+            udev::EventType::Remove => Action::Remove,
+            udev::EventType::Change => Action::Change,
+            udev::EventType::Unknown => Action::Other,
+        };
+
+        Self {
+            action,
+            syspath: device.syspath().to_path_buf(),
+            subsystem: device.property_value("SUBSYSTEM").map(OsStr::to_os_string),
+            seqnum: event.sequence_number(),
+            over_current_port: device.property_value("OVER_CURRENT_PORT").map(PathBuf::from),
+        }
+    }
+
+    // Parse a kernel uevent message.
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let event = uevent::Uevent::parse(buf)?;
+
+        // DEVPATH is absolute, so it can't be joined onto /sys.
+        let mut syspath = OsString::from("/sys");
+        syspath.push(event.devpath());
+        Some(Self {
+            action: event.action(),
+            syspath: PathBuf::from(syspath),
+            subsystem: event.subsystem().map(OsStr::to_os_string),
+            seqnum: event.seqnum().unwrap_or(0),
+            over_current_port: event.property("OVER_CURRENT_PORT").map(PathBuf::from),
+        })
+    }
+}
+
+// A netlink socket receiving uevents straight from the kernel, bypassing udevd.
+struct KernelSocket(RawFd);
+
+impl KernelSocket {
+    fn new() -> io::Result<Self> {
+        // The kernel broadcasts uevents to multicast group 1.
+        const UEVENT_GROUP: u32 = 1;
+
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = KernelSocket(fd);
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = UEVENT_GROUP;
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(socket)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Uevent>> {
+        let mut buf = [0u8; 8192];
+        loop {
+            let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            let mut addr_len = mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+            // With MSG_TRUNC, netlink returns the full length of a message that didn't fit.
+            let len = unsafe {
+                libc::recvfrom(
+                    self.0,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_TRUNC,
+                    &mut addr as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+                    &mut addr_len,
+                )
+            };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(None),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            }
+
+            // Only trust messages sent by the kernel itself.
+            if addr.nl_pid != 0 {
+                continue;
+            }
+            // The rest of a truncated message is gone, and parsing what's left could report a
+            // device with the wrong properties. Report it lost, like an overflowing socket.
+            if len as usize > buf.len() {
+                return Err(io::Error::from_raw_os_error(libc::ENOBUFS));
+            }
+            if let Some(uevent) = Uevent::parse(&buf[..len as usize]) {
+                return Ok(Some(uevent));
+            }
+        }
+    }
+}
+
+impl AsRawFd for KernelSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for KernelSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+enum Socket {
+    Udev(udev::MonitorSocket),
+    Kernel(KernelSocket),
+}
+
+impl Socket {
+    fn receive(&mut self) -> io::Result<Option<Uevent>> {
+        match self {
+            Socket::Udev(socket) => Ok(socket.next().map(|event| Uevent::from_udev(&event))),
+            Socket::Kernel(socket) => socket.receive(),
+        }
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Socket::Udev(socket) => socket.as_raw_fd(),
+            Socket::Kernel(socket) => socket.as_raw_fd(),
+        }
+    }
+}
+
+enum Source {
+    Socket(Socket, reactor::Registration),
+    // Periodically rescan for devices instead of listening for uevents.
+    Poll(Interval),
+}
+
+enum Received {
+    Uevent(Uevent),
+    // The polling interval elapsed.
+    Tick,
+    // The kernel dropped messages because the socket buffer overflowed.
+    Lost,
+}
+
+impl Source {
+    fn poll_receive(&mut self) -> Result<Async<Option<Received>>, UsbError> {
+        match self {
+            Source::Socket(socket, reg) => {
+                reg.register(&mio::unix::EventedFd(&socket.as_raw_fd()))?;
+
+                // Readiness is edge-triggered, so the socket must be drained before waiting on
+                // it.
+                loop {
+                    match socket.receive() {
+                        Ok(Some(uevent)) => return Ok(Async::Ready(Some(Received::Uevent(uevent)))),
+                        Ok(None) => {
+                            if let Async::NotReady = reg.poll_read_ready()? {
+                                return Ok(Async::NotReady);
+                            }
+                        }
+                        Err(ref err) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                            return Ok(Async::Ready(Some(Received::Lost)));
+                        }
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+            Source::Poll(interval) => {
+                match interval.poll().map_err(|_| UsbError::Io(io::ErrorKind::Other))? {
+                    Async::Ready(Some(_)) => Ok(Async::Ready(Some(Received::Tick))),
+                    Async::Ready(None) => Ok(Async::Ready(None)),
+                    Async::NotReady => Ok(Async::NotReady),
+                }
+            }
+        }
+    }
+}
+
 pub struct Monitor<'a> {
     context: &'a Context,
-    socket: udev::MonitorSocket,
-    reg: reactor::Registration,
+    source: Source,
+    seqnum: Option<u64>,
+    pending: VecDeque<Event>,
+}
+
+impl Monitor<'_> {
+    pub fn sequence_number(&self) -> Option<u64> {
+        self.seqnum
+    }
+
+    // Record the SEQNUM of a uevent, returning true if any uevents were skipped since the last
+    // one. udevd may deliver events slightly out of order, so older numbers are ignored rather
+    // than treated as a gap.
+    fn track_seqnum(&mut self, seqnum: u64) -> bool {
+        if seqnum == 0 {
+            // The event carried no SEQNUM.
+            return false;
+        }
+
+        match self.seqnum {
+            Some(last) if seqnum <= last => false,
+            last => {
+                self.seqnum = Some(seqnum);
+                last.map_or(false, |last| seqnum > last + 1)
+            }
+        }
+    }
+
+    fn handle_event(&self, event: &Uevent) -> Option<Event> {
+        let subsystem = event.subsystem.as_ref().and_then(|subsystem| subsystem.to_str());
+        if !subsystem.map_or(false, |subsystem| self.context.subsystems.iter().any(|s| s == subsystem)) {
+            return None;
+        }
+
+        let syspath = self.context.sysfs_path(&event.syspath);
+        if let Some(port) = &event.over_current_port {
+            return self.over_current(port);
+        }
+        if self.context.interface_events {
+            if let Some(event) = self.interface_event(&syspath, event.action) {
+                return Some(event);
+            }
+        }
+        match event.action {
+            // Some stacks send more than one add uevent for a device; only report the first one
+            // of an attachment.
+            Action::Add if self.context.find_device_by_path(&syspath).is_some() => None,
+            Action::Add => self.context.add_device(&syspath).map(Event::Add),
+            Action::Remove => self.context.remove_device_by_path(&syspath).map(Event::Remove),
+            Action::Change => self.context.find_device_by_path(&syspath).map(Event::Change),
+            Action::Other => None,
+        }
+    }
+
+    // Hubs report over-current with a change uevent of their interface, naming the port's device
+    // path.
+    fn over_current(&self, port: &Path) -> Option<Event> {
+        let mut syspath = OsString::from("/sys");
+        syspath.push(port);
+        let port = self.context.sysfs_path(Path::new(&syspath));
+        let number = port_number(&port)?;
+        let hub = self.context.find_device_by_path(port.parent()?.parent()?)?;
+        Some(Event::OverCurrent(hub, number))
+    }
+
+    // Interfaces are named "<device>:<config>.<interface>", in decimal, and sit right below their
+    // device. The name is all that's left to go by once an interface is removed.
+    fn interface_event(&self, syspath: &Path, action: Action) -> Option<Event> {
+        let name = syspath.file_name()?.to_str()?;
+        let (_, interface) = name.split_at(name.find(':')?);
+        let number = interface[interface.find('.')? + 1..].parse().ok()?;
+        let device = self.context.find_device_by_path(syspath.parent()?)?;
+        match action {
+            Action::Add => Some(Event::InterfaceAdd(device, number)),
+            Action::Remove => Some(Event::InterfaceRemove(device, number)),
+            Action::Change | Action::Other => None,
+        }
+    }
+
+    // Compare the devices present now with the ones we know about, and queue the differences as
+    // events.
+    pub fn rescan(&mut self) -> Result<(), UsbError> {
+        let present = self.context.scan()?;
+        let known = self.context.connected_paths();
+
+        for path in known.iter().filter(|path| !present.contains(path)) {
+            if let Some(id) = self.context.remove_device_by_path(path) {
+                self.pending.push_back(Event::Remove(id));
+            }
+        }
+        // A device replugged unnoticed is still at the same path, but the new attachment gets
+        // a new ID.
+        for path in present.iter().filter(|path| known.contains(path)) {
+            let id = match self.context.find_device_by_path(path) {
+                Some(id) if self.context.replaced(id, path) => id,
+                _ => continue,
+            };
+            self.context.remove_device_by_path(path);
+            self.pending.push_back(Event::Remove(id));
+            if let Some(id) = self.context.add_device(path) {
+                self.pending.push_back(Event::Add(id));
+            }
+        }
+        for path in present.iter().filter(|path| !known.contains(path)) {
+            if let Some(id) = self.context.add_device(path) {
+                self.pending.push_back(Event::Add(id));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Stream for Monitor<'_> {
@@ -62,84 +421,704 @@ impl Stream for Monitor<'_> {
     type Error = UsbError; // Can this actually fail?
 
     fn poll(&mut self) -> Result<Async<Option<Event>>, UsbError> {
-        self.reg
-            .register(&mio::unix::EventedFd(&self.socket.as_raw_fd()))?;
-
-        match self.reg.poll_read_ready()? {
-            Async::Ready(readiness) => {
-                if readiness.is_readable() {
-                    if let Some(event) = self.socket.next() {
-                        let device = event.device();
-                        let path = device.syspath();
-                        println!("Got {} event on {}", event.device().property_value("ACTION").unwrap().to_str().unwrap(), path.display());
-
-                        match event.event_type() {
-                            udev::EventType::Add => {
-                                match self.context.add_device(path) {
-                                    Some(id) => Ok(Async::Ready(Some(Event::Add(id)))),
-                                    None => Ok(Async::NotReady),
-                                }
-                            },
-                            udev::EventType::Remove => {
-                                match self.context.remove_device_by_path(path) {
-                                    Some(id) => Ok(Async::Ready(Some(Event::Remove(id)))),
-                                    None => Ok(Async::NotReady),
-                                }
-                            },
-                            udev::EventType::Change | udev::EventType::Unknown => {
-                                Ok(Async::NotReady) // For now
-                            }
-                        }
-                    } else {
-                        Ok(Async::NotReady)
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            let uevent = match self.source.poll_receive()? {
+                Async::Ready(Some(Received::Uevent(uevent))) => uevent,
+                Async::Ready(Some(Received::Tick)) => {
+                    self.rescan()?;
+                    continue;
+                }
+                Async::Ready(Some(Received::Lost)) => {
+                    self.seqnum = None;
+                    return Ok(Async::Ready(Some(Event::EventsLost)));
+                }
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+
+            // The socket is not filtered by subsystem so that every SEQNUM is observed.
+            let lost = self.track_seqnum(uevent.seqnum);
+            let event = self.handle_event(&uevent);
+            if lost {
+                self.pending.extend(event);
+                return Ok(Async::Ready(Some(Event::EventsLost)));
+            }
+            if let Some(event) = event {
+                return Ok(Async::Ready(Some(event)));
+            }
+        }
+    }
+}
+
+pub struct AttributeWatch {
+    source: Source,
+    path: PathBuf,
+    // The path uevents refer to the device by, which differs from `path` with an alternate sysfs
+    // root.
+    uevent_path: PathBuf,
+    name: String,
+    value: Option<String>,
+}
+
+impl Stream for AttributeWatch {
+    type Item = String;
+    type Error = UsbError;
+
+    fn poll(&mut self) -> Result<Async<Option<String>>, UsbError> {
+        loop {
+            match self.source.poll_receive()? {
+                Async::Ready(Some(Received::Uevent(uevent))) => {
+                    if uevent.syspath != self.uevent_path {
+                        continue;
+                    }
+                    match uevent.action {
+                        Action::Remove => return Ok(Async::Ready(None)),
+                        Action::Add | Action::Change => {}
+                        Action::Other => continue,
+                    }
+                }
+                // Nothing to go on but sysfs itself, so check the device is still there and
+                // re-read the attribute.
+                Async::Ready(Some(Received::Tick)) | Async::Ready(Some(Received::Lost)) => {
+                    if !self.path.exists() {
+                        return Ok(Async::Ready(None));
                     }
-                } else {
-                    Ok(Async::NotReady)
                 }
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+
+            let value = read_attribute(&self.path, &self.name);
+            if value != self.value {
+                self.value = value.clone();
+                if let Some(value) = value {
+                    return Ok(Async::Ready(Some(value)));
+                }
+            }
+        }
+    }
+}
+
+// A device's sysfs directory, for reading its attributes away from the context, which can't be
+// sent to another thread. Everything is read from sysfs, even when the context goes through
+// libudev.
+pub struct DeviceDir {
+    root: PathBuf,
+    path: PathBuf,
+    devfs: PathBuf,
+}
+
+impl DeviceDir {
+    fn attribute(&self, name: &str) -> Result<String, UsbError> {
+        sysfs_attribute_walk(&self.root, &self.path, name).ok_or_else(|| {
+            if self.path.exists() {
+                UsbError::Io(io::ErrorKind::NotFound)
+            } else {
+                UsbError::NotConnected
+            }
+        })
+    }
+
+    fn dec(&self, name: &str) -> Result<u32, UsbError> {
+        self.attribute(name)?.parse().map_err(|_| UsbError::NotConnected)
+    }
+
+    pub fn manufacturer_string(&self) -> Result<String, UsbError> {
+        self.attribute("manufacturer")
+    }
+
+    pub fn product_string(&self) -> Result<String, UsbError> {
+        self.attribute("product")
+    }
+
+    pub fn serial_number(&self) -> Result<String, UsbError> {
+        self.attribute("serial")
+    }
+
+    pub fn bus_number(&self) -> Result<u8, UsbError> {
+        Ok(self.dec("busnum")? as u8)
+    }
+
+    pub fn devnode(&self) -> Result<PathBuf, UsbError> {
+        let busnum = self.dec("busnum")?;
+        let devnum = self.dec("devnum")?;
+        Ok(self.devfs.join(format!("bus/usb/{:03}/{:03}", busnum, devnum)))
+    }
+
+    pub fn interfaces(&self) -> Result<Vec<Interface>, UsbError> {
+        Ok(read_interfaces(&interface_paths(&self.path)?))
+    }
+}
+
+// A device node opened with one of its interfaces claimed, released when dropped.
+pub struct ClaimedInterface {
+    // Shared by the interfaces claimed from a device while it's locked.
+    device: Arc<fs::File>,
+    number: u8,
+    // The sysfs path of the interface.
+    path: PathBuf,
+}
+
+impl ClaimedInterface {
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    pub fn endpoints(&self) -> Result<Vec<Endpoint>, UsbError> {
+        fn read_endpoint(path: &Path) -> Option<Endpoint> {
+            let hex = |name| u16::from_str_radix(&read_attribute(path, name)?, 16).ok();
+            let transfer_type = match hex("bmAttributes")? & 0x3 {
+                0 => TransferType::Control,
+                1 => TransferType::Isochronous,
+                2 => TransferType::Bulk,
+                _ => TransferType::Interrupt,
+            };
+            Some(Endpoint {
+                address: hex("bEndpointAddress")? as u8,
+                transfer_type,
+                max_packet_size: hex("wMaxPacketSize")?,
+                interval: hex("bInterval")? as u8,
+            })
+        }
+
+        // The kernel replaces the "ep_<address>" children when the alternate setting changes.
+        let mut endpoints = Vec::new();
+        for entry in fs::read_dir(&self.path).map_err(|_| UsbError::NotConnected)? {
+            let entry = entry?;
+            if entry.file_name().as_bytes().starts_with(b"ep_") {
+                endpoints.extend(read_endpoint(&entry.path()));
             }
-            Async::NotReady => Ok(Async::NotReady),
         }
+        endpoints.sort_by_key(|endpoint| endpoint.address);
+
+        Ok(endpoints)
+    }
+
+    pub fn set_alternate_setting(&self, alternate_setting: u8) -> Result<(), UsbError> {
+        let mut argument = SetInterface {
+            interface: self.number.into(),
+            alternate_setting: alternate_setting.into(),
+        };
+        self.ioctl(USBDEVFS_SETINTERFACE, &mut argument as *mut _ as *mut libc::c_void)
+    }
+
+    pub fn clear_halt(&self, endpoint: u8) -> Result<(), UsbError> {
+        // usbfs lets any endpoint of the device be cleared; keep to the ones of this interface.
+        if !self.endpoints()?.iter().any(|current| current.address == endpoint) {
+            return Err(UsbError::Io(io::ErrorKind::InvalidInput));
+        }
+        let mut endpoint = libc::c_uint::from(endpoint);
+        self.ioctl(USBDEVFS_CLEAR_HALT, &mut endpoint as *mut _ as *mut libc::c_void)
+    }
+
+    pub fn control(
+        &self,
+        setup: &SetupPacket,
+        data: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, UsbError> {
+        control_transfer(&self.device, setup, data, timeout)
+    }
+
+    pub fn read(&self, endpoint: u8, buffer: &mut [u8], timeout: Duration) -> Result<usize, UsbError> {
+        self.bulk(endpoint, buffer.as_mut_ptr(), buffer.len(), timeout)
+    }
+
+    pub fn write(&self, endpoint: u8, data: &[u8], timeout: Duration) -> Result<usize, UsbError> {
+        // The kernel only reads from the buffer of an OUT transfer.
+        self.bulk(endpoint, data.as_ptr() as *mut u8, data.len(), timeout)
+    }
+
+    // Despite its name, USBDEVFS_BULK also does interrupt transfers, picking the pipe from the
+    // endpoint's type.
+    fn bulk(
+        &self,
+        endpoint: u8,
+        data: *mut u8,
+        length: usize,
+        timeout: Duration,
+    ) -> Result<usize, UsbError> {
+        let mut transfer = BulkTransfer {
+            endpoint: endpoint.into(),
+            length: length.min(u32::MAX as usize) as libc::c_uint,
+            timeout: timeout_millis(timeout),
+            data: data as *mut libc::c_void,
+        };
+        let length = unsafe { libc::ioctl(self.device.as_raw_fd(), USBDEVFS_BULK, &mut transfer) };
+        if length < 0 {
+            return Err(UsbError::transfer());
+        }
+        Ok(length as usize)
+    }
+
+    fn ioctl(&self, request: libc::Ioctl, argument: *mut libc::c_void) -> Result<(), UsbError> {
+        if unsafe { libc::ioctl(self.device.as_raw_fd(), request, argument) } < 0 {
+            return Err(UsbError::transfer());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ClaimedInterface {
+    fn drop(&mut self) {
+        // Closing the node releases the interface too, but only once every duplicate of the
+        // descriptor is closed.
+        let mut number = libc::c_uint::from(self.number);
+        let _ = self.ioctl(USBDEVFS_RELEASEINTERFACE, &mut number as *mut _ as *mut libc::c_void);
+    }
+}
+
+// Convert a timeout to the milliseconds usbfs takes, where 0 means no timeout. Any other
+// duration is rounded up, so it doesn't turn into waiting forever.
+fn timeout_millis(timeout: Duration) -> libc::c_uint {
+    let millis = timeout.as_nanos().div_ceil(1_000_000);
+    millis.min(u128::from(u32::MAX)) as libc::c_uint
+}
+
+// The argument of the USBDEVFS_CONTROL ioctl, struct usbdevfs_ctrltransfer.
+#[repr(C)]
+struct ControlTransfer {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+    timeout: u32,
+    data: *mut libc::c_void,
+}
+
+// Make a control transfer through a usbfs node. The data stage is at most as long as `data`,
+// which the kernel reads from for OUT requests and writes to for IN ones.
+fn control_transfer(
+    device: &fs::File,
+    setup: &SetupPacket,
+    data: &mut [u8],
+    timeout: Duration,
+) -> Result<usize, UsbError> {
+    let mut transfer = ControlTransfer {
+        request_type: setup.request_type,
+        request: setup.request,
+        value: setup.value,
+        index: setup.index,
+        length: setup.length.min(data.len().min(usize::from(u16::MAX)) as u16),
+        timeout: timeout_millis(timeout),
+        data: data.as_mut_ptr() as *mut libc::c_void,
+    };
+    let length = unsafe { libc::ioctl(device.as_raw_fd(), USBDEVFS_CONTROL, &mut transfer) };
+    if length < 0 {
+        return Err(UsbError::transfer());
+    }
+    Ok(length as usize)
+}
+
+// _IOWR('U', 0, struct usbdevfs_ctrltransfer).
+const USBDEVFS_CONTROL: libc::Ioctl = usbfs_ioctl(3, 0, mem::size_of::<ControlTransfer>());
+
+// The argument of the USBDEVFS_BULK ioctl, struct usbdevfs_bulktransfer.
+#[repr(C)]
+struct BulkTransfer {
+    endpoint: libc::c_uint,
+    length: libc::c_uint,
+    timeout: libc::c_uint,
+    data: *mut libc::c_void,
+}
+
+// _IOWR('U', 2, struct usbdevfs_bulktransfer).
+const USBDEVFS_BULK: libc::Ioctl = usbfs_ioctl(3, 2, mem::size_of::<BulkTransfer>());
+
+// The argument of the USBDEVFS_SETINTERFACE ioctl, struct usbdevfs_setinterface.
+#[repr(C)]
+struct SetInterface {
+    interface: libc::c_uint,
+    alternate_setting: libc::c_uint,
+}
+
+// The argument of the USBDEVFS_DISCONNECT_CLAIM ioctl, struct usbdevfs_disconnect_claim.
+#[repr(C)]
+struct DisconnectClaim {
+    interface: libc::c_uint,
+    flags: libc::c_uint,
+    // NUL-terminated, USBDEVFS_MAXDRIVERNAME + 1 long.
+    driver: [libc::c_char; 256],
+}
+
+// Only disconnect the driver named in the argument, or any driver except that one.
+const USBDEVFS_DISCONNECT_CLAIM_IF_DRIVER: libc::c_uint = 0x01;
+const USBDEVFS_DISCONNECT_CLAIM_EXCEPT_DRIVER: libc::c_uint = 0x02;
+
+// _IOC(direction, 'U', number, size). The request argument of ioctl() is signed on musl and
+// Android, so the number is put together unsigned and then reinterpreted.
+const fn usbfs_ioctl(direction: u32, number: u32, size: usize) -> libc::Ioctl {
+    (direction << 30 | (size as u32) << 16 | (b'U' as u32) << 8 | number) as libc::Ioctl
+}
+
+// _IOR('U', number, T) for the usbfs ioctls that only take an argument.
+const fn usbfs_read(number: u32, size: usize) -> libc::Ioctl {
+    usbfs_ioctl(2, number, size)
+}
+
+const USBDEVFS_SETINTERFACE: libc::Ioctl = usbfs_read(4, mem::size_of::<SetInterface>());
+const USBDEVFS_CLAIMINTERFACE: libc::Ioctl = usbfs_read(15, mem::size_of::<libc::c_uint>());
+const USBDEVFS_RELEASEINTERFACE: libc::Ioctl = usbfs_read(16, mem::size_of::<libc::c_uint>());
+const USBDEVFS_CLEAR_HALT: libc::Ioctl = usbfs_read(21, mem::size_of::<libc::c_uint>());
+const USBDEVFS_DISCONNECT_CLAIM: libc::Ioctl = usbfs_read(27, mem::size_of::<DisconnectClaim>());
+
+// Read a sysfs attribute of the device at `path`, without its trailing newline.
+fn read_attribute(path: &Path, name: &str) -> Option<String> {
+    let value = fs::read_to_string(path.join(name)).ok()?;
+    Some(String::from(value.trim_end_matches('\n')))
+}
+
+// Read an attribute of a device, or failing that of the closest parent that has it.
+fn sysfs_attribute_walk(root: &Path, path: &Path, name: &str) -> Option<String> {
+    match read_attribute(path, name) {
+        Some(value) => Some(value),
+        None if path != root && path.starts_with(root) => {
+            sysfs_attribute_walk(root, path.parent()?, name)
+        }
+        None => None,
     }
 }
 
+// List the sysfs directories of a device's interfaces. Interfaces of the active configuration are
+// children named "<device>:<config>.<number>".
+fn interface_paths(path: &Path) -> Result<Vec<PathBuf>, UsbError> {
+    let mut prefix = path.file_name().unwrap_or_default().to_os_string();
+    prefix.push(":");
+    let prefix = prefix.as_bytes();
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(path).map_err(|_| UsbError::NotConnected)? {
+        let entry = entry?;
+        if entry.file_name().as_bytes().starts_with(prefix) {
+            paths.push(entry.path());
+        }
+    }
+
+    Ok(paths)
+}
+
+// Read interfaces from their sysfs directories, in order of interface number.
+fn read_interfaces(paths: &[PathBuf]) -> Vec<Interface> {
+    fn read_interface(path: &Path) -> Option<Interface> {
+        let hex = |name| u8::from_str_radix(&read_attribute(path, name)?, 16).ok();
+        Some(Interface {
+            number: hex("bInterfaceNumber")?,
+            // Unlike the other attributes, this one is decimal.
+            alternate_setting: read_attribute(path, "bAlternateSetting")?.trim().parse().ok()?,
+            class: hex("bInterfaceClass")?,
+            subclass: hex("bInterfaceSubClass")?,
+            protocol: hex("bInterfaceProtocol")?,
+            num_endpoints: hex("bNumEndpoints")?,
+            // Only present if the device provides an iInterface string.
+            name: read_attribute(path, "interface"),
+            driver: link_name(&path.join("driver")),
+        })
+    }
+
+    let mut interfaces: Vec<_> = paths.iter().filter_map(|path| read_interface(path)).collect();
+    interfaces.sort_by_key(|interface| interface.number);
+    interfaces
+}
+
+// Read the bus number and address of a USB device. Interfaces and devices of other subsystems
+// have neither.
+fn read_address(path: &Path) -> Option<(u8, u8)> {
+    let busnum = read_attribute(path, "busnum")?.parse().ok()?;
+    let devnum = read_attribute(path, "devnum")?.parse().ok()?;
+    Some((busnum, devnum))
+}
+
+// Retrieve the name of whatever a sysfs symlink such as "driver" or "subsystem" points to.
+fn link_name(path: &Path) -> Option<String> {
+    let target = fs::read_link(path).ok()?;
+    target.file_name()?.to_str().map(String::from)
+}
+
+// Retrieve the vendor and device IDs of a PCI device.
+fn pci_id(path: &Path) -> Option<(u16, u16)> {
+    if link_name(&path.join("subsystem"))? != "pci" {
+        return None;
+    }
+    let hex = |name| {
+        let value = read_attribute(path, name)?;
+        u16::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+    };
+    Some((hex("vendor")?, hex("device")?))
+}
+
+// Get the number of a hub port from the name of its device, like "1-4-port2" or "usb1-port2".
+fn port_number(path: &Path) -> Option<u8> {
+    let name = path.file_name()?.to_str()?;
+    name.rsplit("-port").next()?.parse().ok()
+}
+
 pub struct Context {
     udev: udev::Context,
+    // Set when reading sysfs directly rather than through libudev.
+    sysfs: Option<PathBuf>,
+    devfs: PathBuf,
+    subsystems: Vec<String>,
+    devtypes: Vec<String>,
+    properties: Vec<(String, String)>,
+    tags: Vec<String>,
+    interface_events: bool,
+    lock_devices: bool,
+    // The locked device nodes of claimed interfaces, so other interfaces of the same devices can
+    // be claimed through them.
+    locked: RefCell<HashMap<Id, Weak<fs::File>>>,
     paths: RefCell<Vec<Option<PathBuf>>>,
+    // When each device was registered, indexed like `paths`.
+    connected_at: RefCell<Vec<SystemTime>>,
+    // The DEVTYPE of each device as it was registered, since it can't be read once it's gone.
+    registered_devtypes: RefCell<Vec<Option<String>>>,
+    // The bus number and address of each device as it was registered, to tell a device that was
+    // replugged into the same port from the one that was there before.
+    registered_addresses: RefCell<Vec<Option<(u8, u8)>>>,
 }
 
 impl Context {
-    pub fn new() -> Result<Self, Box<dyn error::Error>> {
+    pub fn new(builder: &ContextBuilder) -> Result<Self, Box<dyn error::Error>> {
+        let (sysfs, devfs) = match &builder.sysfs {
+            Some((sysfs, devfs)) => (Some(sysfs.canonicalize()?), devfs.clone()),
+            None => (None, PathBuf::from("/dev")),
+        };
         let context = Self {
             udev: udev::Context::new()?,
+            sysfs,
+            devfs,
+            subsystems: builder.subsystems.clone(),
+            devtypes: builder.devtypes.clone(),
+            properties: builder.properties.clone(),
+            tags: builder.tags.clone(),
+            interface_events: builder.interface_events,
+            lock_devices: builder.lock_devices,
+            locked: RefCell::new(HashMap::new()),
             paths: RefCell::new(Vec::new()),
+            connected_at: RefCell::new(Vec::new()),
+            registered_devtypes: RefCell::new(Vec::new()),
+            registered_addresses: RefCell::new(Vec::new()),
         };
 
-        {
-            // Scan for currently connected devices.
-            let mut enumerator = udev::Enumerator::new(&context.udev)?;
-            enumerator.match_subsystem("usb")?;
-            for dev in enumerator.scan_devices()? {
-                let _ = context.add_device(dev.syspath());
-            }
+        // Scan for currently connected devices.
+        for path in context.scan().map_err(crate::Error::from)? {
+            let _ = context.add_device(&path);
         }
 
         Ok(context)
     }
 
-    pub fn monitor(&self) -> Result<Monitor<'_>, Box<dyn error::Error>> {
-        let mut monitor = udev::MonitorBuilder::new(&self.udev)?;
-        monitor.match_subsystem("usb")?;
+    // List the syspaths of everything in the matched subsystems right now, including devices
+    // that will be filtered out.
+    fn scan(&self) -> Result<Vec<PathBuf>, UsbError> {
+        match &self.sysfs {
+            Some(sysfs) => {
+                // The entries are symlinks into the device tree; resolve them so they match the
+                // paths uevents refer to.
+                let mut paths = Vec::new();
+                for subsystem in &self.subsystems {
+                    let bus = sysfs.join("bus").join(subsystem).join("devices");
+                    let dir = if bus.is_dir() {
+                        bus
+                    } else {
+                        sysfs.join("class").join(subsystem)
+                    };
+                    let entries = match fs::read_dir(dir) {
+                        Ok(entries) => entries,
+                        // Nothing of this subsystem has ever been registered.
+                        Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+                        Err(err) => return Err(err.into()),
+                    };
+                    for entry in entries {
+                        if let Ok(path) = entry?.path().canonicalize() {
+                            paths.push(path);
+                        }
+                    }
+                }
+                Ok(paths)
+            }
+            None => {
+                let mut enumerator = udev::Enumerator::new(&self.udev)?;
+                for subsystem in &self.subsystems {
+                    enumerator.match_subsystem(subsystem)?;
+                }
+                Ok(enumerator
+                    .scan_devices()?
+                    .map(|dev| dev.syspath().to_path_buf())
+                    .collect())
+            }
+        }
+    }
+
+    // Read the udev properties of the device at `path`. Without udev, only the properties the
+    // kernel provides are available.
+    fn read_properties(&self, path: &Path) -> Option<HashMap<String, String>> {
+        match &self.sysfs {
+            Some(_) => {
+                let uevent = fs::read_to_string(path.join("uevent")).ok()?;
+                let mut properties: HashMap<_, _> = uevent
+                    .lines()
+                    .filter_map(|line| {
+                        let split = line.find('=')?;
+                        Some((String::from(&line[..split]), String::from(&line[split + 1..])))
+                    })
+                    .collect();
+                // udev adds this one itself.
+                if let Some(subsystem) = link_name(&path.join("subsystem")) {
+                    properties.insert(String::from("SUBSYSTEM"), subsystem);
+                }
+                Some(properties)
+            }
+            None => {
+                let dev = self.udev.device_from_syspath(path).ok()?;
+                let properties = dev
+                    .properties()
+                    .filter_map(|property| {
+                        let name = property.name().to_str()?;
+                        let value = property.value().to_str()?;
+                        Some((String::from(name), String::from(value)))
+                    })
+                    .collect();
+                Some(properties)
+            }
+        }
+    }
+
+    // Check a device against the configured matches.
+    fn matches(&self, path: &Path, properties: &HashMap<String, String>) -> bool {
+        let property = |name: &str| properties.get(name).map(String::as_str);
+        let subsystem = match property("SUBSYSTEM") {
+            Some(subsystem) => subsystem,
+            None => return false,
+        };
+
+        if !self.subsystems.iter().any(|wanted| wanted == subsystem) {
+            return false;
+        }
+
+        let devtype = property("DEVTYPE").unwrap_or_default();
+        let devtype_matches = if self.devtypes.is_empty() {
+            // By default, only track USB devices themselves and not their interfaces.
+            subsystem != "usb" || devtype == "usb_device"
+        } else {
+            self.devtypes.iter().any(|wanted| wanted == devtype)
+        };
+        if !devtype_matches {
+            return false;
+        }
+
+        if !self
+            .properties
+            .iter()
+            .all(|(name, value)| property(name) == Some(value.as_str()))
+        {
+            return false;
+        }
+
+        // udev lists tags as ":tag1:tag2:".
+        let tags = property("TAGS").unwrap_or_default();
+        if !self.tags.iter().all(|tag| tags.split(':').any(|current| current == tag)) {
+            return false;
+        }
+
+        // Whatever the subsystem, the device has to be or belong to a USB device.
+        let root = self.sysfs.as_ref().map_or(Path::new("/sys"), PathBuf::as_path);
+        path.ancestors()
+            .take_while(|ancestor| ancestor.starts_with(root))
+            .any(|ancestor| ancestor.join("idVendor").is_file())
+    }
+
+    fn connected_paths(&self) -> Vec<PathBuf> {
+        self.paths.borrow().iter().flatten().cloned().collect()
+    }
+
+    // Translate a syspath from a uevent, which is always relative to /sys, to the sysfs root in
+    // use.
+    fn sysfs_path(&self, syspath: &Path) -> PathBuf {
+        match (&self.sysfs, syspath.strip_prefix("/sys")) {
+            (Some(sysfs), Ok(relative)) => sysfs.join(relative),
+            _ => syspath.to_path_buf(),
+        }
+    }
+
+    fn source(&self, source: EventSource) -> Result<Source, Box<dyn error::Error>> {
+        // How often EventSource::Auto rescans if it has to fall back to polling.
+        const FALLBACK_INTERVAL: Duration = Duration::from_secs(1);
+
+        // No subsystem filter: the monitor needs to see every uevent to detect lost ones.
+        let socket = |socket| Source::Socket(socket, reactor::Registration::new());
+        Ok(match source {
+            EventSource::Udev => socket(Socket::Udev(udev::MonitorBuilder::new(&self.udev)?.listen()?)),
+            EventSource::Kernel => socket(Socket::Kernel(KernelSocket::new()?)),
+            EventSource::Poll(period) => Source::Poll(Interval::new_interval(period)),
+            EventSource::Auto => {
+                // libudev silently receives nothing if udevd isn't running, so check for its
+                // control socket first.
+                let udev = if Path::new("/run/udev/control").exists() {
+                    udev::MonitorBuilder::new(&self.udev)
+                        .and_then(udev::MonitorBuilder::listen)
+                        .ok()
+                } else {
+                    None
+                };
+                match udev {
+                    Some(monitor) => socket(Socket::Udev(monitor)),
+                    None => match KernelSocket::new() {
+                        Ok(kernel) => socket(Socket::Kernel(kernel)),
+                        Err(_) => Source::Poll(Interval::new_interval(FALLBACK_INTERVAL)),
+                    },
+                }
+            }
+        })
+    }
+
+    pub fn monitor(&self, source: EventSource) -> Result<Monitor<'_>, Box<dyn error::Error>> {
         Ok(Monitor {
             context: self,
-            socket: monitor.listen()?,
-            reg: reactor::Registration::new(),
+            source: self.source(source)?,
+            seqnum: None,
+            pending: VecDeque::new(),
         })
     }
 
+    pub fn watch_attribute(
+        &self,
+        id: Id,
+        name: &str,
+        source: EventSource,
+    ) -> Result<AttributeWatch, Box<dyn error::Error>> {
+        let path = self.path(id).map_err(crate::Error::from)?;
+        let uevent_path = match &self.sysfs {
+            Some(sysfs) => Path::new("/sys").join(path.strip_prefix(sysfs)?),
+            None => path.clone(),
+        };
+        Ok(AttributeWatch {
+            source: self.source(source)?,
+            value: read_attribute(&path, name),
+            path,
+            uevent_path,
+            name: String::from(name),
+        })
+    }
+
+    // Registering a path that is already connected returns its Id rather than a new one, so
+    // duplicate uevents and overlapping scans can't make two Ids for one attachment.
     fn add_device(&self, path: &Path) -> Option<Id> {
-        let dev = self.udev.device_from_syspath(path).ok()?;
-        let _ = dev.attribute_value("idVendor")?;
+        if let Some(id) = self.find_device_by_path(path) {
+            return Some(id);
+        }
+        let mut properties = self.read_properties(path)?;
+        if !self.matches(path, &properties) {
+            return None;
+        }
         self.paths.borrow_mut().push(Some(path.to_path_buf()));
+        self.connected_at.borrow_mut().push(SystemTime::now());
+        self.registered_devtypes.borrow_mut().push(properties.remove("DEVTYPE"));
+        self.registered_addresses.borrow_mut().push(read_address(path));
         Some(Id((self.paths.borrow().len() - 1) as u32))
     }
 
@@ -159,8 +1138,16 @@ impl Context {
         }
     }
 
-    // In case Event::Change is used, we'll need to look up Ids by Path.
-    fn _find_device_by_path(&self, path: &Path) -> Option<Id> {
+    // Whether the device registered at a path has since been replaced by another one, which gets
+    // a new address on the bus even when it's plugged into the same port.
+    fn replaced(&self, id: Id, path: &Path) -> bool {
+        match self.registered_addresses.borrow().get(usize::from(id)) {
+            Some(Some(address)) => read_address(path) != Some(*address),
+            _ => false,
+        }
+    }
+
+    fn find_device_by_path(&self, path: &Path) -> Option<Id> {
         self.paths
             .borrow()
             .iter()
@@ -176,6 +1163,23 @@ impl Context {
             })
     }
 
+    pub fn find_by_syspath(&self, syspath: &Path) -> Option<Id> {
+        // Accept the symlinks in /sys/bus/usb/devices as well as the paths they point to.
+        let path = self.sysfs_path(syspath);
+        let path = path.canonicalize().unwrap_or(path);
+        self.find_device_by_path(&path)
+    }
+
+    // Find the device an open device node belongs to, through its device number.
+    pub fn find_by_file(&self, file: &fs::File) -> Option<Id> {
+        let metadata = file.metadata().ok()?;
+        if !metadata.file_type().is_char_device() {
+            return None;
+        }
+        let (major, minor) = (libc::major(metadata.rdev()), libc::minor(metadata.rdev()));
+        self.find_by_syspath(Path::new(&format!("/sys/dev/char/{}:{}", major, minor)))
+    }
+
     fn id(&self, id: Id) -> Result<usize, UsbError> {
         let id = id.into();
         if id < self.paths.borrow().len() {
@@ -194,33 +1198,34 @@ impl Context {
         self.id(id).is_ok()
     }
 
-    fn udev_lookup_hex(&self, id: Id, attr: &str) -> Result<u16, UsbError> {
-        fn udev_attribute_walk(dev: &udev::Device, name: &str) -> Option<u16> {
-            let attr = dev.attributes().find(|attr| attr.name() == name);
-            if let Some(attr) = attr {
-                let attr = attr.value()?.to_str()?;
-                Some(u16::from_str_radix(attr, 16).ok()?)
-            } else {
-                udev_attribute_walk(&dev.parent()?, name)
-            }
-        }
+    pub fn device_dir(&self, id: Id) -> Result<DeviceDir, UsbError> {
+        Ok(DeviceDir {
+            root: self.sysfs.clone().unwrap_or_else(|| PathBuf::from("/sys")),
+            path: self.path(id)?,
+            devfs: self.devfs.clone(),
+        })
+    }
+
+    // Unlike everything else about a device, this stays available once it's disconnected.
+    pub fn connected_at(&self, id: Id) -> Option<SystemTime> {
+        self.connected_at.borrow().get(usize::from(id)).copied()
+    }
 
+    // Also kept after disconnection, to make sense of remove events.
+    pub fn devtype(&self, id: Id) -> Option<String> {
+        self.registered_devtypes.borrow().get(usize::from(id)).cloned().flatten()
+    }
+
+    fn path(&self, id: Id) -> Result<PathBuf, UsbError> {
         let id = self.id(id)?;
 
         // unwrap() is safe here because the above line would have propagated an Err if it was not
         // currently connected.
-        let device = self.udev.device_from_syspath(self.paths.borrow()[id].as_ref().unwrap()).map_err(|_| {
-            self.paths.borrow_mut()[id] = None;
-            UsbError::NotConnected
-        })?;
-        udev_attribute_walk(&device, attr).ok_or_else(|| {
-            self.paths.borrow_mut()[id] = None;
-            UsbError::NotConnected
-        })
+        Ok(self.paths.borrow()[id].clone().unwrap())
     }
 
-    fn udev_lookup_string(&self, id: Id, attr: &str) -> Result<String, UsbError> {
-        fn udev_attribute_walk<'a>(dev: &'a udev::Device, name: &str) -> Option<String> {
+    fn lookup_attribute(&self, id: Id, attr: &str) -> Result<String, UsbError> {
+        fn udev_attribute_walk(dev: &udev::Device, name: &str) -> Option<String> {
             let attr = dev.attributes().find(|attr| attr.name() == name);
             if let Some(attr) = attr {
                 Some(String::from(attr.value()?.to_str()?))
@@ -229,35 +1234,671 @@ impl Context {
             }
         }
 
-        let id = self.id(id)?;
-
-        let device = self.udev.device_from_syspath(self.paths.borrow()[id].as_ref().unwrap()).map_err(|_| {
-            self.paths.borrow_mut()[id] = None;
-            UsbError::NotConnected
-        })?;
-        udev_attribute_walk(&device, attr).ok_or_else(|| {
-            self.paths.borrow_mut()[id] = None;
-            UsbError::NotConnected
+        let path = self.path(id)?;
+        let id = usize::from(id);
+        let value = match &self.sysfs {
+            Some(sysfs) => sysfs_attribute_walk(sysfs, &path, attr),
+            None => {
+                let device = self.udev.device_from_syspath(&path).map_err(|_| {
+                    self.paths.borrow_mut()[id] = None;
+                    UsbError::NotConnected
+                })?;
+                udev_attribute_walk(&device, attr)
+            }
+        };
+        value.ok_or_else(|| {
+            if path.exists() {
+                // The device is still there; it just doesn't have this attribute.
+                UsbError::Io(io::ErrorKind::NotFound)
+            } else {
+                self.paths.borrow_mut()[id] = None;
+                UsbError::NotConnected
+            }
         })
     }
 
+    fn lookup_hex(&self, id: Id, attr: &str) -> Result<u16, UsbError> {
+        let value = self.lookup_attribute(id, attr)?;
+        u16::from_str_radix(&value, 16).map_err(|_| UsbError::NotConnected)
+    }
+
+    fn lookup_dec(&self, id: Id, attr: &str) -> Result<u32, UsbError> {
+        let value = self.lookup_attribute(id, attr)?;
+        value.parse().map_err(|_| UsbError::NotConnected)
+    }
+
     pub fn vendor_id(&self, id: Id) -> Result<u16, UsbError> {
-        self.udev_lookup_hex(id, "idVendor")
+        self.lookup_hex(id, "idVendor")
     }
 
     pub fn product_id(&self, id: Id) -> Result<u16, UsbError> {
-        self.udev_lookup_hex(id, "idProduct")
+        self.lookup_hex(id, "idProduct")
     }
 
     pub fn manufacturer_string(&self, id: Id) -> Result<String, UsbError> {
-        self.udev_lookup_string(id, "manufacturer")
+        self.lookup_attribute(id, "manufacturer")
     }
 
     pub fn product_string(&self, id: Id) -> Result<String, UsbError> {
-        self.udev_lookup_string(id, "product")
+        self.lookup_attribute(id, "product")
+    }
+
+    pub fn serial_number(&self, id: Id) -> Result<String, UsbError> {
+        self.lookup_attribute(id, "serial")
+    }
+
+    pub fn devnode(&self, id: Id) -> Result<PathBuf, UsbError> {
+        let busnum = self.lookup_dec(id, "busnum")?;
+        let devnum = self.lookup_dec(id, "devnum")?;
+        Ok(self.devfs.join(format!("bus/usb/{:03}/{:03}", busnum, devnum)))
+    }
+
+    #[cfg(feature = "raw-udev")]
+    pub fn udev_device(&self, id: Id) -> Result<udev::Device, UsbError> {
+        let path = self.path(id)?;
+        Ok(self.udev.device_from_syspath(&path)?)
+    }
+
+    // Open a device node for reading and writing. The inner error is for failures that may go
+    // away in a moment: the device being busy, or udev not having granted access yet.
+    pub fn try_open(&self, id: Id) -> Result<Result<fs::File, io::ErrorKind>, UsbError> {
+        match self.open_locked(id) {
+            Ok(file) => Ok(Ok(Arc::try_unwrap(file).or_else(|file| file.try_clone())?)),
+            Err(UsbError::Io(kind @ io::ErrorKind::ResourceBusy))
+            | Err(UsbError::Io(kind @ io::ErrorKind::PermissionDenied)) => Ok(Err(kind)),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Open a device node for reading and writing, taking an exclusive advisory lock on it if the
+    // context locks devices. The lock belongs to the open file, so a node this context already
+    // holds locked is shared rather than opened again.
+    fn open_locked(&self, id: Id) -> Result<Arc<fs::File>, UsbError> {
+        let devnode = self.devnode(id)?;
+        if !self.lock_devices {
+            return Ok(Arc::new(fs::OpenOptions::new().read(true).write(true).open(devnode)?));
+        }
+        let mut locked = self.locked.borrow_mut();
+        if let Some(file) = locked.get(&id).and_then(Weak::upgrade) {
+            return Ok(file);
+        }
+        let file = fs::OpenOptions::new().read(true).write(true).open(devnode)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EWOULDBLOCK) => UsbError::Io(io::ErrorKind::ResourceBusy),
+                _ => err.into(),
+            });
+        }
+        let file = Arc::new(file);
+        locked.retain(|_, file| file.strong_count() > 0);
+        locked.insert(id, Arc::downgrade(&file));
+        Ok(file)
+    }
+
+    // Find the processes with a device's node open, by going through their file descriptors.
+    // Processes of other users are skipped unless running as root.
+    pub fn devnode_users(&self, id: Id) -> Result<Vec<Process>, UsbError> {
+        // Compare device numbers rather than paths, which differ between mount namespaces.
+        let rdev = fs::metadata(self.devnode(id)?)?.rdev();
+        let mut users = Vec::new();
+        for entry in fs::read_dir("/proc")? {
+            let entry = entry?;
+            let pid = match entry.file_name().to_str().and_then(|pid| pid.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+            let fds = match fs::read_dir(entry.path().join("fd")) {
+                Ok(fds) => fds,
+                Err(_) => continue,
+            };
+            let uses = fds.filter_map(Result::ok).any(|fd| {
+                fs::metadata(fd.path()).map_or(false, |metadata| {
+                    metadata.file_type().is_char_device() && metadata.rdev() == rdev
+                })
+            });
+            if uses {
+                users.push(Process {
+                    pid,
+                    command: read_attribute(&entry.path(), "comm"),
+                });
+            }
+        }
+        Ok(users)
+    }
+
+    pub fn languages(&self, id: Id) -> Result<Vec<u16>, UsbError> {
+        let request = SetupPacket::get_descriptor(descriptor_type::STRING, 0, 0, 255);
+        let mut data = [0u8; 255];
+        let length = self.control(id, &request, &mut data)?;
+
+        // Devices without strings stall the request instead of returning an empty list.
+        let length = length.min(usize::from(data[0]));
+        if length < 2 || data[1] != descriptor_type::STRING {
+            return Err(UsbError::Io(io::ErrorKind::InvalidData));
+        }
+        Ok(data[2..length]
+            .chunks_exact(2)
+            .map(|id| u16::from_le_bytes([id[0], id[1]]))
+            .collect())
+    }
+
+    // Make a control transfer to a device, waiting at most a second.
+    pub fn control(&self, id: Id, setup: &SetupPacket, data: &mut [u8]) -> Result<usize, UsbError> {
+        // usbfs only accepts control transfers on nodes opened for writing.
+        let device = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.devnode(id)?)?;
+        control_transfer(&device, setup, data, Duration::from_secs(1))
+    }
+
+    pub fn claim_interface(
+        &self,
+        id: Id,
+        number: u8,
+        disconnect: Option<&DisconnectDriver>,
+    ) -> Result<ClaimedInterface, UsbError> {
+        // Fails with io::ErrorKind::ResourceBusy while another process holds the device locked.
+        let device = self.open_locked(id)?;
+        self.claim_interface_of(id, number, disconnect, device)
+    }
+
+    // Claim an interface through an open usbfs node of the device.
+    pub fn claim_interface_of(
+        &self,
+        id: Id,
+        number: u8,
+        disconnect: Option<&DisconnectDriver>,
+        device: Arc<fs::File>,
+    ) -> Result<ClaimedInterface, UsbError> {
+        let path = self
+            .interface_paths(id)?
+            .into_iter()
+            .find(|path| {
+                read_attribute(path, "bInterfaceNumber")
+                    .and_then(|current| u8::from_str_radix(&current, 16).ok())
+                    == Some(number)
+            })
+            .ok_or(UsbError::Io(io::ErrorKind::NotFound))?;
+        // Fails with EBUSY, io::ErrorKind::ResourceBusy, while a kernel driver or another
+        // process has the interface, or the driver bound isn't one to disconnect.
+        let claimed = match disconnect {
+            None => {
+                let mut argument = libc::c_uint::from(number);
+                unsafe { libc::ioctl(device.as_raw_fd(), USBDEVFS_CLAIMINTERFACE, &mut argument) }
+            }
+            Some(driver) => {
+                let (flags, name) = match driver {
+                    DisconnectDriver::Any => (0, ""),
+                    DisconnectDriver::Only(name) => {
+                        (USBDEVFS_DISCONNECT_CLAIM_IF_DRIVER, &name[..])
+                    }
+                    DisconnectDriver::Except(name) => {
+                        (USBDEVFS_DISCONNECT_CLAIM_EXCEPT_DRIVER, &name[..])
+                    }
+                };
+                let mut argument = DisconnectClaim {
+                    interface: number.into(),
+                    flags,
+                    driver: [0; 256],
+                };
+                // Leave room for the terminating NUL.
+                if name.len() >= argument.driver.len() || name.contains('\0') {
+                    return Err(UsbError::Io(io::ErrorKind::InvalidInput));
+                }
+                for (c, byte) in argument.driver.iter_mut().zip(name.bytes()) {
+                    *c = byte as libc::c_char;
+                }
+                unsafe { libc::ioctl(device.as_raw_fd(), USBDEVFS_DISCONNECT_CLAIM, &mut argument) }
+            }
+        };
+        if claimed < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(ClaimedInterface {
+            device,
+            number,
+            path,
+        })
+    }
+
+    pub fn properties(&self, id: Id) -> Result<HashMap<String, String>, UsbError> {
+        let path = self.path(id)?;
+        self.read_properties(&path).ok_or_else(|| {
+            self.paths.borrow_mut()[usize::from(id)] = None;
+            UsbError::NotConnected
+        })
+    }
+
+    pub fn bus_number(&self, id: Id) -> Result<u8, UsbError> {
+        Ok(self.lookup_dec(id, "busnum")? as u8)
+    }
+
+    pub fn device_address(&self, id: Id) -> Result<u8, UsbError> {
+        Ok(self.lookup_dec(id, "devnum")? as u8)
+    }
+
+    pub fn buses(&self) -> Vec<Bus> {
+        let mut buses: Vec<_> = self
+            .devices()
+            .filter_map(|id| {
+                // Root hubs are the only USB devices named "usb<bus number>".
+                let path = self.path(id).ok()?;
+                if !path.file_name()?.as_bytes().starts_with(b"usb") {
+                    return None;
+                }
+                let controller = path.parent()?;
+                Some(Bus {
+                    number: self.bus_number(id).ok()?,
+                    root_hub: id.into(),
+                    controller: String::from(controller.file_name()?.to_str()?),
+                    driver: link_name(&controller.join("driver")),
+                    pci_id: pci_id(controller),
+                })
+            })
+            .collect();
+        buses.sort_by_key(|bus| bus.number);
+        buses
+    }
+
+    pub fn bus_usage(&self, number: u8) -> Result<BusUsage, UsbError> {
+        // debugfs is usually only readable by root.
+        let root = self.sysfs.as_ref().map_or(Path::new("/sys"), PathBuf::as_path);
+        let devices = fs::read_to_string(root.join("kernel/debug/usb/devices"))?;
+
+        // Each root hub's "T:" line is followed by a "B:" line for its bus, like
+        // "B:  Alloc= 12/800 us ( 2%), #Int=  3, #Iso=  0".
+        let mut bus = None;
+        for line in devices.lines() {
+            if let Some(topology) = line.strip_prefix("T:") {
+                bus = topology
+                    .split_whitespace()
+                    .find_map(|field| field.strip_prefix("Bus="))
+                    .and_then(|bus| bus.parse::<u8>().ok());
+            } else if let (Some(line), Some(current)) = (line.strip_prefix("B:"), bus) {
+                if current != number {
+                    continue;
+                }
+                let numbers = line
+                    .split(|c: char| !c.is_ascii_digit())
+                    .filter(|field| !field.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<Vec<u32>, _>>()
+                    .map_err(|_| UsbError::Io(io::ErrorKind::InvalidData))?;
+                return match numbers[..] {
+                    [allocated, available, _, interrupt, isochronous] => Ok(BusUsage {
+                        allocated_us: allocated,
+                        available_us: available,
+                        interrupt_transfers: interrupt,
+                        isochronous_transfers: isochronous,
+                        // Counted by the caller.
+                        transfers: Default::default(),
+                    }),
+                    _ => Err(UsbError::Io(io::ErrorKind::InvalidData)),
+                };
+            }
+        }
+        Err(UsbError::Io(io::ErrorKind::NotFound))
+    }
+
+    pub fn tunnel(&self, id: Id) -> Result<Option<Tunnel>, UsbError> {
+        let path = self.path(id)?;
+        let sysfs = self.sysfs.as_ref().map_or(Path::new("/sys"), PathBuf::as_path);
+
+        // The host controller is the parent of the root hub, "usb<bus number>".
+        let controller = path
+            .ancestors()
+            .find(|ancestor| {
+                ancestor
+                    .file_name()
+                    .map_or(false, |name| name.as_bytes().starts_with(b"usb"))
+            })
+            .and_then(Path::parent)
+            .ok_or(UsbError::NotConnected)?;
+
+        // PCI devices below an external facing port, such as one tunnelled through USB4 or
+        // Thunderbolt, are marked removable.
+        let tunnelled = controller.ancestors().any(|device| {
+            read_attribute(device, "removable").map_or(false, |value| value == "removable")
+        });
+        if !tunnelled {
+            return Ok(None);
+        }
+
+        // Pick the domain whose host interface is closest to the controller in the PCI hierarchy.
+        let devices = sysfs.join("bus/thunderbolt/devices");
+        let mut domains = Vec::new();
+        let mut routers = Vec::new();
+        if let Ok(entries) = fs::read_dir(&devices) {
+            for entry in entries {
+                let name = entry?.file_name().to_string_lossy().into_owned();
+                if name.starts_with("domain") {
+                    domains.push(name);
+                } else if name
+                    .split('-')
+                    .nth(1)
+                    .map_or(false, |route| u64::from_str_radix(route, 16).is_ok())
+                {
+                    routers.push(name);
+                }
+            }
+        }
+        let shared = |domain: &String| {
+            let host = devices.join(domain).canonicalize().ok();
+            let host = host.as_ref().and_then(|host| host.parent());
+            host.map_or(0, |host| {
+                host.components()
+                    .zip(controller.components())
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            })
+        };
+        let domain = domains.into_iter().max_by_key(shared);
+
+        // The route of the host router is 0. Only a single device router in the domain tells
+        // which one the tunnel ends at.
+        let index = domain.as_ref().map(|domain| domain.trim_start_matches("domain"));
+        let mut routers = routers.into_iter().filter(|router| {
+            let mut parts = router.splitn(2, '-');
+            parts.next() == index && parts.next() != Some("0")
+        });
+        let router = match (routers.next(), routers.next()) {
+            (Some(name), None) => {
+                let router = devices.join(&name);
+                Some(Router {
+                    vendor_name: read_attribute(&router, "vendor_name"),
+                    device_name: read_attribute(&router, "device_name"),
+                    name,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Some(Tunnel { domain, router }))
+    }
+
+    pub fn is_hub(&self, id: Id) -> Result<bool, UsbError> {
+        // Hubs always declare their class at the device level.
+        const HUB_CLASS: u16 = 0x09;
+        Ok(self.lookup_hex(id, "bDeviceClass")? == HUB_CLASS)
+    }
+
+    pub fn device_class(&self, id: Id) -> Result<u8, UsbError> {
+        Ok(self.lookup_hex(id, "bDeviceClass")? as u8)
+    }
+
+    pub fn port_path(&self, id: Id) -> Result<String, UsbError> {
+        let path = self.path(id)?;
+        // Devices are named after the bus and the hub ports leading to them, like "1-4.2".
+        path.file_name()
+            .and_then(OsStr::to_str)
+            .map(String::from)
+            .ok_or(UsbError::NotConnected)
+    }
+
+    pub fn is_removable(&self, id: Id) -> Result<Option<bool>, UsbError> {
+        let path = self.path(id)?;
+        // Kernels before 3.13 don't have the attribute at all.
+        match read_attribute(&path, "removable").as_deref() {
+            Some("removable") => Ok(Some(true)),
+            Some("fixed") => Ok(Some(false)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn wakeup(&self, id: Id) -> Result<Option<bool>, UsbError> {
+        let path = self.path(id)?;
+        // The attribute is empty for devices that can't wake the system, and missing if power
+        // management is compiled out.
+        match read_attribute(&path, "power/wakeup").as_deref() {
+            Some("enabled") => Ok(Some(true)),
+            Some("disabled") => Ok(Some(false)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn set_wakeup(&self, id: Id, enabled: bool) -> Result<(), UsbError> {
+        let path = self.path(id)?;
+        fs::write(path.join("power/wakeup"), if enabled { "enabled" } else { "disabled" })?;
+        Ok(())
+    }
+
+    pub fn link_rate(&self, id: Id) -> Result<LinkRate, UsbError> {
+        let path = self.path(id)?;
+        let lanes = |name| read_attribute(&path, name).and_then(|lanes| lanes.parse().ok());
+        let rx_lanes = lanes("rx_lanes").unwrap_or(1);
+        let tx_lanes = lanes("tx_lanes").unwrap_or(1);
+
+        // The speed is in Mbit/s, for all lanes together: Gen 1x2 and Gen 2x1 both report 10000
+        // and only Gen 2x2 reports 20000.
+        let (speed, generation) = match self.lookup_attribute(id, "speed")?.as_str() {
+            "1.5" => (Speed::Low, None),
+            "12" => (Speed::Full, None),
+            // Wireless USB also reports 480.
+            "480" => (Speed::High, None),
+            "5000" => (Speed::Super, Some(1)),
+            "10000" if rx_lanes > 1 => (Speed::SuperPlus, Some(1)),
+            "10000" | "20000" => (Speed::SuperPlus, Some(2)),
+            _ => return Err(UsbError::Io(io::ErrorKind::InvalidData)),
+        };
+
+        Ok(LinkRate { speed, generation, rx_lanes, tx_lanes })
+    }
+
+    pub fn authorize(&self, id: Id, authorized: bool) -> Result<(), UsbError> {
+        let path = self.path(id)?;
+        fs::write(path.join("authorized"), if authorized { "1" } else { "0" })?;
+        Ok(())
+    }
+
+    pub fn set_configuration(&self, id: Id, configuration: u8) -> Result<(), UsbError> {
+        let path = self.path(id)?;
+        fs::write(path.join("bConfigurationValue"), configuration.to_string())?;
+        Ok(())
+    }
+
+    pub fn num_ports(&self, id: Id) -> Result<u8, UsbError> {
+        // The hub driver fills this in from bNbrPorts of the hub descriptor; it's 0 otherwise.
+        Ok(self.lookup_dec(id, "maxchild")? as u8)
+    }
+
+    pub fn ports(&self, id: Id) -> Result<Vec<Port>, UsbError> {
+        let path = self.path(id)?;
+        let name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+
+        // The hub driver registers a device for each port below the hub's interface.
+        let mut ports = Vec::new();
+        for interface in self.interface_paths(id)? {
+            for entry in fs::read_dir(&interface).map_err(|_| UsbError::NotConnected)? {
+                let port = entry?.path();
+                let number = match port_number(&port) {
+                    Some(number) => number,
+                    None => continue,
+                };
+                // Devices on root hub ports are named "<bus>-<port>", and "<hub>.<port>" below
+                // other hubs.
+                let child = match name.strip_prefix("usb") {
+                    Some(bus) => format!("{}-{}", bus, number),
+                    None => format!("{}.{}", name, number),
+                };
+                ports.push(Port {
+                    number,
+                    // Kernels before 5.3 don't count over-current conditions.
+                    over_current_count: read_attribute(&port, "over_current_count")
+                        .and_then(|count| count.parse().ok()),
+                    connect_type: read_attribute(&port, "connect_type"),
+                    device: self.find_device_by_path(&path.join(child)).map(Into::into),
+                });
+            }
+        }
+        ports.sort_by_key(|port| port.number);
+
+        Ok(ports)
+    }
+
+    pub fn typec_port(&self, id: Id) -> Result<Option<TypeCPort>, UsbError> {
+        // The selected role is the one in brackets, as in "[host] device".
+        fn role(path: &Path, name: &str) -> Option<String> {
+            let value = read_attribute(path, name)?;
+            let selected = value.split_whitespace().find(|role| role.starts_with('['));
+            Some(String::from(selected.unwrap_or(&value).trim_matches(|c| c == '[' || c == ']')))
+        }
+
+        // Newer kernels link a USB port to the Type-C connector it's wired to. Devices behind a
+        // hub (say, in a dock) are plugged into the connector of the hub.
+        let path = self.path(id)?;
+        let port = path
+            .ancestors()
+            .find_map(|device| device.join("port/connector").canonicalize().ok());
+        let port = match port {
+            Some(port) => port,
+            None => return Ok(None),
+        };
+        let name = port.file_name().and_then(OsStr::to_str).unwrap_or_default().to_string();
+
+        // Alternate modes entered with the partner are children named "<port>-partner.<index>".
+        let partner = port.join(format!("{}-partner", name));
+        let mut alternate_modes = Vec::new();
+        if let Ok(entries) = fs::read_dir(&partner) {
+            for entry in entries {
+                let path = entry?.path();
+                let svid = read_attribute(&path, "svid")
+                    .and_then(|svid| u16::from_str_radix(&svid, 16).ok());
+                let mode = read_attribute(&path, "mode").and_then(|mode| mode.parse().ok());
+                if let (Some(svid), Some(mode)) = (svid, mode) {
+                    alternate_modes.push(AlternateMode {
+                        svid,
+                        mode,
+                        active: read_attribute(&path, "active").map_or(false, |active| active == "yes"),
+                        description: read_attribute(&path, "description"),
+                    });
+                }
+            }
+        }
+
+        Ok(Some(TypeCPort {
+            data_role: role(&port, "data_role"),
+            power_role: role(&port, "power_role"),
+            orientation: read_attribute(&port, "orientation"),
+            partner: partner.is_dir(),
+            alternate_modes,
+            name,
+        }))
+    }
+
+    pub fn interfaces(&self, id: Id) -> Result<Vec<Interface>, UsbError> {
+        Ok(read_interfaces(&self.interface_paths(id)?))
+    }
+
+    pub fn configuration(&self, id: Id) -> Result<Option<u8>, UsbError> {
+        // Unconfigured devices, such as unauthorized ones, have an empty bConfigurationValue.
+        match self.lookup_dec(id, "bConfigurationValue") {
+            Ok(value) => Ok(Some(value as u8)),
+            Err(UsbError::NotConnected) if self.path(id).is_ok() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn descriptors(&self, id: Id) -> Result<Vec<u8>, UsbError> {
+        Ok(fs::read(self.path(id)?.join("descriptors"))?)
+    }
+
+    // List the sysfs paths of the interfaces of a device's active configuration.
+    fn interface_paths(&self, id: Id) -> Result<Vec<PathBuf>, UsbError> {
+        interface_paths(&self.path(id)?)
+    }
+
+    // List the hidraw device nodes of a device's HID interfaces, with their interface numbers.
+    // They sit at "<interface>/<hid device>/hidraw/hidrawN".
+    pub fn hidraw_nodes(&self, id: Id) -> Result<Vec<(u8, PathBuf)>, UsbError> {
+        let mut nodes = Vec::new();
+        for interface in self.interface_paths(id)? {
+            let number = match read_attribute(&interface, "bInterfaceNumber")
+                .and_then(|number| u8::from_str_radix(&number, 16).ok())
+            {
+                Some(number) => number,
+                None => continue,
+            };
+            for hid in fs::read_dir(&interface)? {
+                let hidraw = match fs::read_dir(hid?.path().join("hidraw")) {
+                    Ok(hidraw) => hidraw,
+                    Err(_) => continue,
+                };
+                for node in hidraw {
+                    nodes.push((number, self.devfs.join(node?.file_name())));
+                }
+            }
+        }
+        nodes.sort();
+        Ok(nodes)
+    }
+
+    fn interface_path(&self, id: Id, interface: u8) -> Result<PathBuf, UsbError> {
+        self.interface_paths(id)?
+            .into_iter()
+            .find(|path| {
+                read_attribute(path, "bInterfaceNumber")
+                    .and_then(|number| u8::from_str_radix(&number, 16).ok())
+                    == Some(interface)
+            })
+            .ok_or(UsbError::Io(io::ErrorKind::NotFound))
+    }
+
+    pub fn unbind_driver(&self, id: Id, interface: u8) -> Result<(), UsbError> {
+        let path = self.interface_path(id, interface)?;
+        let driver = path.join("driver");
+        if !driver.exists() {
+            // Nothing is bound.
+            return Ok(());
+        }
+        // The driver's unbind file takes the name of the interface to release.
+        fs::write(driver.join("unbind"), path.file_name().unwrap_or_default().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn bind_driver(&self, id: Id, interface: u8, driver: &str) -> Result<(), UsbError> {
+        // The name is joined into a sysfs path, so it mustn't lead out of the drivers directory.
+        if driver.is_empty() || driver.contains('/') || driver == "." || driver == ".." {
+            return Err(UsbError::Io(io::ErrorKind::InvalidInput));
+        }
+        let path = self.interface_path(id, interface)?;
+        let sysfs = self.sysfs.as_ref().map_or(Path::new("/sys"), PathBuf::as_path);
+        let bind = sysfs.join("bus/usb/drivers").join(driver).join("bind");
+        fs::write(bind, path.file_name().unwrap_or_default().as_bytes())?;
+        Ok(())
     }
 
     pub fn devices(&self) -> impl Iterator<Item = Id> {
         (0..(self.paths.borrow().len())).map(|id| Id(id as u32))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ioctl_numbers() {
+        // As <linux/usbdevice_fs.h> defines them on 64-bit targets.
+        if mem::size_of::<usize>() != 8 {
+            return;
+        }
+        assert_eq!(USBDEVFS_CONTROL as u32, 0xc018_5500);
+        assert_eq!(USBDEVFS_BULK as u32, 0xc018_5502);
+        assert_eq!(USBDEVFS_SETINTERFACE as u32, 0x8008_5504);
+        assert_eq!(USBDEVFS_CLAIMINTERFACE as u32, 0x8004_550f);
+        assert_eq!(USBDEVFS_RELEASEINTERFACE as u32, 0x8004_5510);
+        assert_eq!(USBDEVFS_CLEAR_HALT as u32, 0x8004_5515);
+        assert_eq!(USBDEVFS_DISCONNECT_CLAIM as u32, 0x8108_551b);
+    }
+
+    #[test]
+    fn timeouts_round_up() {
+        assert_eq!(timeout_millis(Duration::from_secs(0)), 0);
+        assert_eq!(timeout_millis(Duration::from_nanos(1)), 1);
+        assert_eq!(timeout_millis(Duration::from_micros(1500)), 2);
+        assert_eq!(timeout_millis(Duration::from_millis(1000)), 1000);
+        assert_eq!(timeout_millis(Duration::from_secs(u64::MAX)), u32::MAX);
+    }
+}
@@ -0,0 +1,191 @@
+//! Switching modems out of mass storage mode, as usb_modeswitch does.
+//!
+//! Many LTE modems and other dongles first show up as a CD-ROM holding their Windows drivers,
+//! and only become a modem once sent a message over the bulk OUT endpoint of that storage
+//! interface. The device then disconnects and comes back, usually with another product ID.
+//!
+//! Start a hotplug monitor with `Context::monitor` before switching a device, then wait for
+//! the device to come back with `wait_for`.
+use std::{io, time::Duration};
+
+use tokio::prelude::*;
+
+use crate::{
+    descriptor::TransferType, ClaimError, ClaimOptions, Context, DisconnectDriver, Error, Event,
+    HotplugMonitor, Id, ProductId, VendorId,
+};
+
+// The class code of mass storage interfaces.
+const MASS_STORAGE_CLASS: u8 = 0x08;
+
+// How long to wait for each message to be sent, and for each response.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+// The length of a command block wrapper, and of the command status wrapper answering it.
+const COMMAND_BLOCK_WRAPPER_LENGTH: usize = 31;
+const COMMAND_STATUS_WRAPPER_LENGTH: usize = 13;
+
+// Huawei's switching message, `HuaweiNewMode` in usb_modeswitch.
+#[rustfmt::skip]
+const HUAWEI_MESSAGE: [u8; COMMAND_BLOCK_WRAPPER_LENGTH] = [
+    0x55, 0x53, 0x42, 0x43, 0x12, 0x34, 0x56, 0x78, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11,
+    0x06, 0x20, 0x00, 0x00, 0x01, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00,
+];
+
+// ZTE's switching message, sent after a standard eject, reading 32 bytes back.
+#[rustfmt::skip]
+const ZTE_MESSAGE: [u8; COMMAND_BLOCK_WRAPPER_LENGTH] = [
+    0x55, 0x53, 0x42, 0x43, 0x12, 0x34, 0x56, 0x70, 0x20, 0x00, 0x00, 0x00, 0x80, 0x00, 0x0c,
+    0x85, 0x01, 0x01, 0x01, 0x18, 0x01, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00,
+];
+
+/// How to switch a device.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    /// Eject the medium with SCSI `PREVENT ALLOW MEDIUM REMOVAL` and `START STOP UNIT`, which
+    /// many devices take as the signal to switch.
+    Eject,
+    /// Send Huawei's switching message.
+    Huawei,
+    /// Eject the medium, then send ZTE's switching message.
+    Zte,
+    /// Send the given messages in order, such as a `MessageContent` from a usb_modeswitch
+    /// configuration file.
+    ///
+    /// Messages that are command block wrappers get their data and status read back.
+    Messages(Vec<Vec<u8>>),
+}
+
+impl Method {
+    /// The messages the method sends to the bulk OUT endpoint, in order.
+    pub fn messages(&self) -> Vec<Vec<u8>> {
+        let eject = vec![
+            command_block_wrapper(1, &[0x1e, 0, 0, 0, 0, 0]),
+            command_block_wrapper(2, &[0x1b, 0, 0, 0, 0x02, 0]),
+        ];
+        match self {
+            Method::Eject => eject,
+            Method::Huawei => vec![HUAWEI_MESSAGE.to_vec()],
+            Method::Zte => eject.into_iter().chain(Some(ZTE_MESSAGE.to_vec())).collect(),
+            Method::Messages(messages) => messages.clone(),
+        }
+    }
+}
+
+// Wrap a SCSI command transferring no data in a mass storage command block wrapper for LUN 0.
+fn command_block_wrapper(tag: u32, command: &[u8]) -> Vec<u8> {
+    let mut wrapper = Vec::with_capacity(COMMAND_BLOCK_WRAPPER_LENGTH);
+    wrapper.extend_from_slice(b"USBC");
+    wrapper.extend_from_slice(&tag.to_le_bytes());
+    // Transfer length, flags and LUN.
+    wrapper.extend_from_slice(&[0; 6]);
+    wrapper.push(command.len() as u8);
+    wrapper.extend_from_slice(command);
+    wrapper.resize(COMMAND_BLOCK_WRAPPER_LENGTH, 0);
+    wrapper
+}
+
+// How many bytes a command block wrapper expects the device to send back before its status, if
+// the message is one.
+fn data_in_length(message: &[u8]) -> Option<usize> {
+    if message.len() != COMMAND_BLOCK_WRAPPER_LENGTH || !message.starts_with(b"USBC") {
+        return None;
+    }
+    let length = u32::from_le_bytes([message[8], message[9], message[10], message[11]]);
+    Some(if message[12] & 0x80 == 0 { 0 } else { length as usize })
+}
+
+/// Switch a device by sending the messages of `method` to its mass storage interface.
+///
+/// This takes the interface from its kernel driver as it claims it, so it needs root. It
+/// fails with `io::ErrorKind::NotFound` if the device has no mass storage interface, or the
+/// interface has no bulk OUT endpoint. Devices often disconnect as soon as they have the
+/// message, so the responses are read but their errors ignored. This blocks; see
+/// `InterfaceHandle`.
+pub fn switch(context: &Context, id: Id, method: &Method) -> Result<(), ClaimError> {
+    let storage = context
+        .interfaces(id)?
+        .into_iter()
+        .find(|interface| interface.class == MASS_STORAGE_CLASS)
+        .ok_or(Error::Io(io::ErrorKind::NotFound))?;
+    let options = ClaimOptions::default().disconnect_driver(DisconnectDriver::Any);
+    let interface = context.claim_interface_with(id, storage.number, &options)?;
+
+    let endpoints = interface.endpoints()?;
+    let bulk = |direction_in: bool| {
+        endpoints.iter().find(|endpoint| {
+            endpoint.transfer_type == TransferType::Bulk
+                && (endpoint.address & 0x80 != 0) == direction_in
+        })
+    };
+    let bulk_out = match bulk(false) {
+        Some(endpoint) => interface.bulk_out(endpoint.address)?,
+        None => return Err(Error::Io(io::ErrorKind::NotFound).into()),
+    };
+    let bulk_in = match bulk(true) {
+        Some(endpoint) => Some(interface.bulk_in(endpoint.address)?),
+        None => None,
+    };
+
+    for message in method.messages() {
+        bulk_out.write(&message, TIMEOUT)?;
+        if let (Some(bulk_in), Some(length)) = (&bulk_in, data_in_length(&message)) {
+            if length > 0 {
+                let _ = bulk_in.read(&mut vec![0; length], TIMEOUT);
+            }
+            let _ = bulk_in.read(&mut [0; COMMAND_STATUS_WRAPPER_LENGTH], TIMEOUT);
+        }
+    }
+    Ok(())
+}
+
+/// Wait for a device with the given IDs to be added, such as a switched device coming back.
+///
+/// The future fails with `Error::NotConnected` if the monitor's stream ends first. Combine it
+/// with a timeout to give up on devices that never come back.
+pub fn wait_for<'a>(
+    monitor: HotplugMonitor<'a>,
+    vendor_id: VendorId,
+    product_id: ProductId,
+) -> impl Future<Item = Id, Error = Error> + 'a {
+    let context = monitor.context;
+    monitor
+        .filter_map(move |event| match event {
+            Event::Add(id)
+                if context.vendor_id(id) == Some(vendor_id)
+                    && context.product_id(id) == Some(product_id) =>
+            {
+                Some(id)
+            }
+            _ => None,
+        })
+        .into_future()
+        .map_err(|(err, _)| err)
+        .and_then(|(id, _)| id.ok_or(Error::NotConnected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        #[rustfmt::skip]
+        let eject = [
+            0x55, 0x53, 0x42, 0x43, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x06, 0x1b, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let messages = Method::Eject.messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1], eject);
+        assert_eq!(Method::Zte.messages()[..2], messages[..]);
+
+        assert_eq!(data_in_length(&messages[1]), Some(0));
+        assert_eq!(data_in_length(&ZTE_MESSAGE), Some(32));
+        assert_eq!(data_in_length(&HUAWEI_MESSAGE), Some(0));
+        assert_eq!(data_in_length(b"USBC"), None);
+    }
+}
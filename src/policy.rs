@@ -0,0 +1,610 @@
+//! Rules deciding which devices may be used.
+//!
+//! A policy can act as a simple USB firewall: have the kernel leave new devices unauthorized by
+//! writing 0 to each root hub's `authorized_default` file, then apply the policy to each device
+//! as the hotplug monitor reports it.
+//!
+//! Rules can be read from and written as USBGuard rules, as long as they only use the parts of
+//! the rule language policies support: a target, `id`, `serial`, `via-port` and a
+//! `with-interface` matching an interface class, like
+//! `allow id 046d:* serial "1234" via-port "1-4.2" with-interface 03:*:*`.
+//!
+//! As in USBGuard, `with-interface 03:*:*` only matches devices all of whose interfaces are of
+//! class 03, so allowing it doesn't let through a keyboard that is also a mass storage device.
+//! `with-interface one-of { 03:*:* }` matches devices with any interface of class 03.
+use std::{error::Error as StdError, fmt, ops::RangeInclusive};
+
+use crate::{descriptor, Context, Error, Id, PortPath, ProductId, VendorId};
+
+/// What a policy decides to do with a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    /// Authorize the device.
+    Allow,
+    /// Keep the device from being used.
+    Block,
+}
+
+/// A rule matching devices, and the verdict for the devices it matches.
+///
+/// A rule matches devices satisfying every condition set on it, so a rule without conditions
+/// matches every device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    verdict: Verdict,
+    vendor_ids: Option<RangeInclusive<VendorId>>,
+    product_ids: Option<RangeInclusive<ProductId>>,
+    class: Option<u8>,
+    interface_class: Option<u8>,
+    serial_number: Option<String>,
+    port_path: Option<PortPath>,
+    hub: Option<PortPath>,
+}
+
+impl Rule {
+    /// A rule allowing the devices it matches.
+    pub fn allow() -> Self {
+        Self::new(Verdict::Allow)
+    }
+
+    /// A rule blocking the devices it matches.
+    pub fn block() -> Self {
+        Self::new(Verdict::Block)
+    }
+
+    fn new(verdict: Verdict) -> Self {
+        Self {
+            verdict,
+            vendor_ids: None,
+            product_ids: None,
+            class: None,
+            interface_class: None,
+            serial_number: None,
+            port_path: None,
+            hub: None,
+        }
+    }
+
+    /// Match devices whose vendor ID is in a range, such as `VendorId::LOGITECH..=VendorId::LOGITECH`.
+    pub fn vendor_ids(mut self, vendor_ids: RangeInclusive<VendorId>) -> Self {
+        self.vendor_ids = Some(vendor_ids);
+        self
+    }
+
+    /// Match devices whose product ID is in a range.
+    pub fn product_ids(mut self, product_ids: RangeInclusive<ProductId>) -> Self {
+        self.product_ids = Some(product_ids);
+        self
+    }
+
+    /// Match devices of a class, declared either by the device or by any of its interfaces.
+    ///
+    /// Interfaces of every configuration count, so this works on devices that aren't
+    /// configured yet, such as those left unauthorized.
+    pub fn class(mut self, class: u8) -> Self {
+        self.class = Some(class);
+        self
+    }
+
+    /// Match devices all of whose interfaces are of a class, in every configuration.
+    ///
+    /// Devices without interfaces don't match.
+    pub fn every_interface_class(mut self, class: u8) -> Self {
+        self.interface_class = Some(class);
+        self
+    }
+
+    /// Match devices with a serial number.
+    pub fn serial_number(mut self, serial_number: &str) -> Self {
+        self.serial_number = Some(String::from(serial_number));
+        self
+    }
+
+    /// Match devices plugged into a port.
+    pub fn port_path(mut self, port_path: PortPath) -> Self {
+        self.port_path = Some(port_path);
+        self
+    }
+
+    /// Match devices behind a hub, directly or through other hubs.
+    pub fn behind(mut self, hub: PortPath) -> Self {
+        self.hub = Some(hub);
+        self
+    }
+
+    /// The verdict for the devices the rule matches.
+    pub fn verdict(&self) -> Verdict {
+        self.verdict
+    }
+
+    /// Does the rule match a device?
+    pub fn matches(&self, context: &Context, id: Id) -> Result<bool, Error> {
+        if let Some(vendor_ids) = &self.vendor_ids {
+            if !vendor_ids.contains(&context.vendor_id(id).ok_or(Error::NotConnected)?) {
+                return Ok(false);
+            }
+        }
+        if let Some(product_ids) = &self.product_ids {
+            if !product_ids.contains(&context.product_id(id).ok_or(Error::NotConnected)?) {
+                return Ok(false);
+            }
+        }
+        if self.class.is_some() || self.interface_class.is_some() {
+            // Unauthorized devices aren't configured yet, so their interfaces are only in their
+            // descriptors, not in sysfs.
+            let interface_classes = descriptor::interface_classes(&context.descriptors(id)?);
+            if !self.matches_classes(context.device_class(id)?, &interface_classes) {
+                return Ok(false);
+            }
+        }
+        if let Some(serial_number) = &self.serial_number {
+            if context.serial_number(id).ok().as_ref() != Some(serial_number) {
+                return Ok(false);
+            }
+        }
+        if self.port_path.is_some() || self.hub.is_some() {
+            let port_path = context.port_path(id)?;
+            if self.port_path.as_ref().map_or(false, |wanted| *wanted != port_path)
+                || self.hub.as_ref().map_or(false, |hub| !port_path.is_behind(hub))
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Check the class conditions against the class of a device and those of its interfaces.
+    fn matches_classes(&self, device_class: u8, interface_classes: &[(u8, u8, u8)]) -> bool {
+        if let Some(class) = self.class {
+            if device_class != class
+                && !interface_classes
+                    .iter()
+                    .any(|(current, _, _)| *current == class)
+            {
+                return false;
+            }
+        }
+        if let Some(class) = self.interface_class {
+            if interface_classes.is_empty()
+                || !interface_classes
+                    .iter()
+                    .all(|(current, _, _)| *current == class)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// An ordered list of rules; the first rule matching a device decides its verdict.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    default: Verdict,
+}
+
+impl Policy {
+    /// Create a policy without rules, giving every device the default verdict.
+    pub fn new(default: Verdict) -> Self {
+        Self {
+            rules: Vec::new(),
+            default,
+        }
+    }
+
+    /// Add a rule, after the existing ones.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// List the rules in order.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Decide the verdict for a device.
+    pub fn evaluate(&self, context: &Context, id: Id) -> Result<Verdict, Error> {
+        for rule in &self.rules {
+            if rule.matches(context, id)? {
+                return Ok(rule.verdict);
+            }
+        }
+        Ok(self.default)
+    }
+
+    /// Decide the verdict for a device, and authorize or deauthorize it accordingly.
+    ///
+    /// This needs root.
+    pub fn apply(&self, context: &Context, id: Id) -> Result<Verdict, Error> {
+        let verdict = self.evaluate(context, id)?;
+        context.authorize(id, verdict == Verdict::Allow)?;
+        Ok(verdict)
+    }
+}
+
+/// A USBGuard rule that couldn't be parsed, or uses features policies don't support.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseRuleError {
+    line: usize,
+    reason: String,
+}
+
+impl ParseRuleError {
+    fn new(reason: &str) -> Self {
+        Self {
+            line: 0,
+            reason: String::from(reason),
+        }
+    }
+
+    /// The line the error is on, counting from 1, or 0 if parsing a single rule.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.reason)
+        } else {
+            write!(f, "line {}: {}", self.line, self.reason)
+        }
+    }
+}
+
+impl StdError for ParseRuleError {}
+
+// Split a rule into words, unquoting and unescaping strings. Each word says whether it was quoted.
+fn tokenize(rule: &str) -> Result<Vec<(String, bool)>, ParseRuleError> {
+    let mut tokens = Vec::new();
+    let mut chars = rule.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut token = String::new();
+        let quoted = c == '"';
+        if quoted {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => token.push(c),
+                        None => return Err(ParseRuleError::new("unterminated string")),
+                    },
+                    Some(c) => token.push(c),
+                    None => return Err(ParseRuleError::new("unterminated string")),
+                }
+            }
+        } else {
+            token.push(c);
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push((token, quoted));
+    }
+    Ok(tokens)
+}
+
+impl Rule {
+    /// Parse a USBGuard rule.
+    ///
+    /// `reject` rules become blocking rules, since a policy never removes devices.
+    pub fn from_usbguard(rule: &str) -> Result<Self, ParseRuleError> {
+        fn hex_or_any(value: &str) -> Result<Option<u16>, ParseRuleError> {
+            if value == "*" {
+                return Ok(None);
+            }
+            u16::from_str_radix(value, 16)
+                .map(Some)
+                .map_err(|_| ParseRuleError::new("invalid hexadecimal number"))
+        }
+
+        fn interface_class(value: &str) -> Result<u8, ParseRuleError> {
+            match value.split(':').collect::<Vec<_>>().as_slice() {
+                [class, "*", "*"] => u8::from_str_radix(class, 16)
+                    .map_err(|_| ParseRuleError::new("invalid interface class")),
+                _ => Err(ParseRuleError::new(
+                    "only interface classes like 03:*:* are supported",
+                )),
+            }
+        }
+
+        let tokens = tokenize(rule)?;
+        let mut tokens = tokens.iter().map(|(token, quoted)| (token.as_str(), *quoted));
+        let mut rule = match tokens.next().map(|(token, _)| token) {
+            Some("allow") => Self::allow(),
+            Some("block") | Some("reject") => Self::block(),
+            _ => return Err(ParseRuleError::new("expected allow, block or reject")),
+        };
+
+        // Attributes take either a value or a set of values, which policies don't support.
+        const SET_OPERATORS: &[&str] = &[
+            "{",
+            "all-of",
+            "one-of",
+            "none-of",
+            "equals",
+            "equals-ordered",
+            "match-all",
+        ];
+        while let Some((attribute, _)) = tokens.next() {
+            if attribute == "with-interface"
+                && (rule.class.is_some() || rule.interface_class.is_some())
+            {
+                return Err(ParseRuleError::new("only one with-interface is supported"));
+            }
+            let value = match tokens.next() {
+                // A device with any interface of the class, as a set of a single value.
+                Some(("one-of", false)) if attribute == "with-interface" => {
+                    match (tokens.next(), tokens.next(), tokens.next()) {
+                        (Some(("{", false)), Some((value, _)), Some(("}", false))) => {
+                            rule.class = Some(interface_class(value)?);
+                            continue;
+                        }
+                        _ => {
+                            return Err(ParseRuleError::new(
+                                "only one-of sets of a single interface class are supported",
+                            ))
+                        }
+                    }
+                }
+                Some((value, false)) if SET_OPERATORS.contains(&value) => {
+                    return Err(ParseRuleError::new("attribute sets are not supported"))
+                }
+                Some((value, _)) => value,
+                None => return Err(ParseRuleError::new("expected a value")),
+            };
+
+            match attribute {
+                "id" => {
+                    let mut parts = value.splitn(2, ':');
+                    let vendor_id = hex_or_any(parts.next().unwrap_or_default())?;
+                    let product_id = parts
+                        .next()
+                        .ok_or_else(|| ParseRuleError::new("expected vendor:product"))?;
+                    let product_id = hex_or_any(product_id)?;
+                    if vendor_id.is_none() && product_id.is_some() {
+                        return Err(ParseRuleError::new("a product ID needs a vendor ID"));
+                    }
+                    rule.vendor_ids = vendor_id.map(|id| VendorId(id)..=VendorId(id));
+                    rule.product_ids = product_id.map(|id| ProductId(id)..=ProductId(id));
+                }
+                "serial" => rule.serial_number = Some(String::from(value)),
+                "via-port" => {
+                    rule.port_path = Some(
+                        value
+                            .parse()
+                            .map_err(|_| ParseRuleError::new("invalid port path"))?,
+                    )
+                }
+                // A single value stands for the set of every interface, so each must match.
+                "with-interface" => rule.interface_class = Some(interface_class(value)?),
+                _ => return Err(ParseRuleError::new("unsupported rule attribute")),
+            }
+        }
+
+        Ok(rule)
+    }
+
+    /// Write the rule as a USBGuard rule.
+    ///
+    /// Returns `None` if the rule matches a range of IDs, devices behind a hub or two class
+    /// conditions, which USBGuard can't express. USBGuard doesn't look at the device class, so a
+    /// rule matching devices of a class only checks their interfaces once written.
+    pub fn to_usbguard(&self) -> Option<String> {
+        if self.hub.is_some() || (self.class.is_some() && self.interface_class.is_some()) {
+            return None;
+        }
+
+        fn id<T: Copy + Into<u16>>(ids: &Option<RangeInclusive<T>>) -> Option<String> {
+            let ids = ids.as_ref().map(|ids| ((*ids.start()).into(), (*ids.end()).into()));
+            match ids {
+                None => Some(String::from("*")),
+                Some((start, end)) if start == end => Some(format!("{:04x}", start)),
+                Some((0, 0xffff)) => Some(String::from("*")),
+                Some(_) => None,
+            }
+        }
+        fn quote(value: &str) -> String {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+
+        let mut rule = String::from(match self.verdict {
+            Verdict::Allow => "allow",
+            Verdict::Block => "block",
+        });
+        if self.vendor_ids.is_some() || self.product_ids.is_some() {
+            let (vendor_id, product_id) = (id(&self.vendor_ids)?, id(&self.product_ids)?);
+            if vendor_id == "*" && product_id != "*" {
+                return None;
+            }
+            rule.push_str(&format!(" id {}:{}", vendor_id, product_id));
+        }
+        if let Some(serial_number) = &self.serial_number {
+            rule.push_str(&format!(" serial {}", quote(serial_number)));
+        }
+        if let Some(port_path) = &self.port_path {
+            rule.push_str(&format!(" via-port {}", quote(&port_path.to_string())));
+        }
+        if let Some(class) = self.class {
+            rule.push_str(&format!(" with-interface one-of {{ {:02x}:*:* }}", class));
+        }
+        if let Some(class) = self.interface_class {
+            rule.push_str(&format!(" with-interface {:02x}:*:*", class));
+        }
+        Some(rule)
+    }
+}
+
+impl Policy {
+    /// Parse a USBGuard rules file, one rule per line.
+    ///
+    /// Like USBGuard, devices no rule matches are blocked.
+    pub fn from_usbguard(rules: &str) -> Result<Self, ParseRuleError> {
+        let mut policy = Self::new(Verdict::Block);
+        for (number, line) in rules.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = Rule::from_usbguard(line).map_err(|err| ParseRuleError {
+                line: number + 1,
+                ..err
+            })?;
+            policy.rules.push(rule);
+        }
+        Ok(policy)
+    }
+
+    /// Write the rules as a USBGuard rules file.
+    ///
+    /// USBGuard has no default verdict, so a policy allowing unmatched devices ends with a rule
+    /// allowing everything. Returns `None` if a rule can't be expressed.
+    pub fn to_usbguard(&self) -> Option<String> {
+        let mut rules = String::new();
+        for rule in &self.rules {
+            rules.push_str(&rule.to_usbguard()?);
+            rules.push('\n');
+        }
+        if self.default == Verdict::Allow {
+            rules.push_str("allow\n");
+        }
+        Some(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(token: &str, quoted: bool) -> (String, bool) {
+        (String::from(token), quoted)
+    }
+
+    #[test]
+    fn tokenize_words_and_strings() {
+        assert_eq!(
+            tokenize(r#"allow  serial "a b\"c\\" with-interface one-of { 03:*:* }"#).unwrap(),
+            [
+                token("allow", false),
+                token("serial", false),
+                token(r#"a b"c\"#, true),
+                token("with-interface", false),
+                token("one-of", false),
+                token("{", false),
+                token("03:*:*", false),
+                token("}", false),
+            ],
+        );
+        assert_eq!(tokenize("").unwrap(), []);
+        assert!(tokenize(r#"allow serial "1234"#).is_err());
+        assert!(tokenize(r#"allow serial "1234\"#).is_err());
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule =
+            Rule::from_usbguard(r#"allow id 046d:c52b serial "1234" via-port "1-4.2""#).unwrap();
+        assert_eq!(
+            rule,
+            Rule::allow()
+                .vendor_ids(VendorId(0x046d)..=VendorId(0x046d))
+                .product_ids(ProductId(0xc52b)..=ProductId(0xc52b))
+                .serial_number("1234")
+                .port_path("1-4.2".parse().unwrap()),
+        );
+        assert_eq!(
+            Rule::from_usbguard("reject id 046d:*").unwrap(),
+            Rule::block().vendor_ids(VendorId(0x046d)..=VendorId(0x046d)),
+        );
+        assert_eq!(Rule::from_usbguard("block").unwrap(), Rule::block());
+        assert_eq!(
+            Rule::from_usbguard("allow with-interface 03:*:*").unwrap(),
+            Rule::allow().every_interface_class(0x03),
+        );
+        assert_eq!(
+            Rule::from_usbguard("block with-interface one-of { 08:*:* }").unwrap(),
+            Rule::block().class(0x08),
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_rules() {
+        for rule in &[
+            "",
+            "permit",
+            "allow id",
+            "allow id 046d",
+            "allow id *:c52b",
+            "allow id 1046d:c52b",
+            "allow hash \"abc\"",
+            "allow via-port \"1-x\"",
+            "allow with-interface 03:01:*",
+            "allow with-interface { 03:*:* 08:*:* }",
+            "allow with-interface one-of { 03:*:* 08:*:* }",
+            "allow with-interface all-of { 03:*:* }",
+            "allow with-interface 03:*:* with-interface 08:*:*",
+            "allow serial one-of { \"1234\" }",
+        ] {
+            assert!(Rule::from_usbguard(rule).is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for rule in &[
+            "allow",
+            "block id 046d:*",
+            "allow id 046d:c52b serial \"a \\\"quoted\\\" \\\\ serial\" via-port \"1-4.2\"",
+            "allow with-interface 03:*:*",
+            "block with-interface one-of { 08:*:* }",
+        ] {
+            let parsed = Rule::from_usbguard(rule).unwrap();
+            assert_eq!(parsed.to_usbguard().as_deref(), Some(*rule));
+        }
+
+        let rules = "# Keyboards only.\n\nallow with-interface 03:*:*\n";
+        let policy = Policy::from_usbguard(rules).unwrap();
+        assert_eq!(policy.rules(), [Rule::allow().every_interface_class(0x03)]);
+        assert_eq!(policy.to_usbguard().unwrap(), "allow with-interface 03:*:*\n");
+        let err = Policy::from_usbguard("allow\nallow id nope\n").unwrap_err();
+        assert_eq!(err.line(), 2);
+    }
+
+    #[test]
+    fn inexpressible_rules() {
+        let hub = "1-4".parse().unwrap();
+        assert_eq!(Rule::allow().behind(hub).to_usbguard(), None);
+        let logitech = VendorId(0x046d)..=VendorId(0x046e);
+        assert_eq!(Rule::allow().vendor_ids(logitech).to_usbguard(), None);
+        let both = Rule::allow().class(0x03).every_interface_class(0x03);
+        assert_eq!(both.to_usbguard(), None);
+    }
+
+    #[test]
+    fn class_semantics() {
+        const KEYBOARD: &[(u8, u8, u8)] = &[(0x03, 0x01, 0x01), (0x03, 0x00, 0x00)];
+        // A keyboard that also presents mass storage, as BadUSB devices do.
+        const COMPOSITE: &[(u8, u8, u8)] = &[(0x03, 0x01, 0x01), (0x08, 0x06, 0x50)];
+
+        let imported = Rule::from_usbguard("allow with-interface 03:*:*").unwrap();
+        assert!(imported.matches_classes(0x00, KEYBOARD));
+        assert!(!imported.matches_classes(0x00, COMPOSITE));
+        assert!(!imported.matches_classes(0x03, &[]));
+
+        let any = Rule::block().class(0x08);
+        assert!(any.matches_classes(0x00, COMPOSITE));
+        assert!(!any.matches_classes(0x00, KEYBOARD));
+        assert!(any.matches_classes(0x08, &[]));
+    }
+}
@@ -0,0 +1,107 @@
+//! Known-broken device behaviors, and how to work around them.
+
+use std::time::Duration;
+
+use crate::{Context, Endpoint, Error, Id, InterfaceHandle};
+
+// How long to pause after each control request for `Quirks::delay_control_messages`, as Linux
+// does for USB_QUIRK_DELAY_CTRL_MSG.
+pub(crate) const CONTROL_MESSAGE_DELAY: Duration = Duration::from_millis(200);
+
+// How long to pause after setting the configuration for
+// `Quirks::delay_after_set_configuration`.
+pub(crate) const CONFIGURATION_DELAY: Duration = Duration::from_millis(100);
+
+/// The quirks of a device.
+///
+/// Quirks affecting what the crate reads about devices are applied by `Context`, and the ones
+/// affecting transfers by `Context` and `InterfaceHandle` for the transfers they make. The
+/// others are for code issuing transfers to the device some other way.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    /// The device's string descriptors are garbage, so `Context` doesn't report them.
+    pub ignore_strings: bool,
+    /// Every unit of the model reports the same serial number, so `Context` doesn't report it
+    /// and it can't be mistaken for an identity.
+    pub non_unique_serial: bool,
+    /// The device needs a pause after `SET_CONFIGURATION` before it accepts requests.
+    ///
+    /// `Context::set_configuration` pauses before returning.
+    pub delay_after_set_configuration: bool,
+    /// The device needs a pause between control requests.
+    ///
+    /// The crate pauses after each of its own control requests, including those behind
+    /// `InterfaceHandle::set_alternate_setting` and `InterfaceHandle::clear_halt`.
+    pub delay_control_messages: bool,
+    /// The device chokes on zero-length packets terminating transfers.
+    ///
+    /// Writing nothing to an OUT endpoint of an `InterfaceHandle` then sends nothing.
+    pub no_zero_length_packets: bool,
+}
+
+// Linux's USB_QUIRK_DELAY_INIT, and that along with USB_QUIRK_DELAY_CTRL_MSG.
+const DELAY_INIT: Quirks = Quirks {
+    ignore_strings: false,
+    non_unique_serial: false,
+    delay_after_set_configuration: true,
+    delay_control_messages: false,
+    no_zero_length_packets: false,
+};
+const DELAY_INIT_AND_CONTROL_MESSAGES: Quirks = Quirks {
+    delay_control_messages: true,
+    ..DELAY_INIT
+};
+
+// Vendor ID, product ID and quirks of devices known to be broken, from the entries of Linux's
+// usb_quirk_list that matter to userspace.
+const BUILTIN: &[(u16, u16, Quirks)] = &[
+    // Logitech HD Pro Webcams C920, C920-C, C922, C925e and C930e.
+    (0x046d, 0x082d, DELAY_INIT),
+    (0x046d, 0x0841, DELAY_INIT),
+    (0x046d, 0x0843, DELAY_INIT),
+    (0x046d, 0x085b, DELAY_INIT),
+    (0x046d, 0x085c, DELAY_INIT),
+    // Corsair K70 RGB, Strafe and Strafe RGB keyboards.
+    (0x1b1c, 0x1b13, DELAY_INIT_AND_CONTROL_MESSAGES),
+    (0x1b1c, 0x1b15, DELAY_INIT_AND_CONTROL_MESSAGES),
+    (0x1b1c, 0x1b20, DELAY_INIT_AND_CONTROL_MESSAGES),
+    // Corsair K70 LUX RGB and K70 LUX keyboards.
+    (0x1b1c, 0x1b33, DELAY_INIT),
+    (0x1b1c, 0x1b36, DELAY_INIT),
+    // Corsair K70 RGB RAPIDFIRE keyboard.
+    (0x1b1c, 0x1b38, DELAY_INIT_AND_CONTROL_MESSAGES),
+];
+
+// Look up the built-in quirks of a device model.
+pub(crate) fn builtin(vendor_id: u16, product_id: u16) -> Quirks {
+    BUILTIN
+        .iter()
+        .find(|(vendor, product, _)| (*vendor, *product) == (vendor_id, product_id))
+        .map(|(_, _, quirks)| *quirks)
+        .unwrap_or_default()
+}
+
+/// Workarounds for a device model that `Quirks` can't express, registered with
+/// `Context::register_quirk_hook`.
+///
+/// Every hook does nothing by default. A hook failing fails the operation it runs before, with
+/// its error.
+pub trait Quirk: Send + Sync {
+    /// Called before `Context::open_devnode`, `Context::claim_interface` or
+    /// `Context::claim_interface_retry` first opens the device's usbfs node.
+    fn before_open(&self, _context: &Context, _id: Id) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once `Context::claim_interface` or `Context::claim_interface_retry` has claimed an
+    /// interface, before handing it out. Failing releases the interface.
+    fn after_claim(&self, _interface: &InterfaceHandle) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called before each transfer on an endpoint of a claimed interface, with the length of
+    /// the data or buffer. It isn't called again when a stalled transfer is retried.
+    fn before_transfer(&self, _endpoint: &Endpoint, _length: usize) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Following a device as it's disconnected and connected again.
+//!
+//! Devices come and go: they're unplugged and plugged back in, reset themselves after a firmware
+//! update, or drop off the bus when a hub loses power. A `Reconnecting` stream claims an
+//! interface of the device a rule matches whenever it's connected, and hands it to a setup
+//! closure, so applications don't each need a state machine of their own for this.
+use std::time::Duration;
+
+use tokio::prelude::*;
+
+use crate::{policy::Rule, ClaimError, Context, Error, Event, HotplugMonitor, Id, InterfaceHandle};
+
+// How long to keep trying to claim the interface of a device that was just connected, while
+// udev rules set its permissions and kernel drivers probe it.
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What happened to the device a `Reconnecting` stream follows.
+#[derive(Debug)]
+pub enum Connection<T> {
+    /// A matching device was connected, its interface claimed, and the setup closure returned
+    /// this.
+    Connected(Id, T),
+    /// A matching device was connected, but claiming its interface or setting it up failed.
+    ///
+    /// The device is still followed, and reported disconnected when it goes; only then is
+    /// another matching device picked up.
+    Failed(Id, ClaimError),
+    /// The device was disconnected. Whatever the setup closure returned for it should be
+    /// dropped, since its transfers fail from now on.
+    Disconnected(Id),
+}
+
+/// A stream claiming an interface of a device whenever it's connected.
+///
+/// One device is followed at a time: the first one connected that the rule matches, including
+/// one already connected when the stream is created. Once it's disconnected, the next matching
+/// device to be connected is picked up, so a rule matching the serial number follows one device
+/// and a rule matching vendor and product IDs follows whichever of them is there.
+///
+/// The setup closure gets the claimed interface and returns what the application keeps for it,
+/// such as the interface along with its endpoints. The stream ends when the monitor does.
+pub struct Reconnecting<'a, F> {
+    monitor: HotplugMonitor<'a>,
+    rule: Rule,
+    interface: u8,
+    setup: F,
+    // The device being followed.
+    current: Option<Id>,
+    claiming: Option<Box<dyn Future<Item = InterfaceHandle, Error = ClaimError> + 'a>>,
+}
+
+impl<'a, F, T> Reconnecting<'a, F>
+where
+    F: FnMut(Id, InterfaceHandle) -> Result<T, Error>,
+{
+    /// Follow the devices `rule` matches, claiming interface `interface` of each and passing it
+    /// to `setup`.
+    ///
+    /// Only the conditions of the rule are used, not its verdict.
+    pub fn new(monitor: HotplugMonitor<'a>, rule: Rule, interface: u8, setup: F) -> Self {
+        let context = monitor.context;
+        let mut reconnecting = Self {
+            monitor,
+            rule,
+            interface,
+            setup,
+            current: None,
+            claiming: None,
+        };
+        let connected = context
+            .connected_devices()
+            .find(|&id| reconnecting.rule.matches(context, id).unwrap_or(false));
+        if let Some(id) = connected {
+            reconnecting.claim(id);
+        }
+        reconnecting
+    }
+
+    fn claim(&mut self, id: Id) {
+        let context: &'a Context = self.monitor.context;
+        self.current = Some(id);
+        self.claiming = Some(Box::new(context.claim_interface_retry(
+            id,
+            self.interface,
+            CLAIM_TIMEOUT,
+        )));
+    }
+}
+
+impl<F, T> Stream for Reconnecting<'_, F>
+where
+    F: FnMut(Id, InterfaceHandle) -> Result<T, Error>,
+{
+    type Item = Connection<T>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Connection<T>>>, Error> {
+        loop {
+            if let (Some(id), Some(claiming)) = (self.current, &mut self.claiming) {
+                let connection = match claiming.poll() {
+                    Ok(Async::Ready(interface)) => match (self.setup)(id, interface) {
+                        Ok(value) => Some(Connection::Connected(id, value)),
+                        Err(err) => Some(Connection::Failed(id, err.into())),
+                    },
+                    Ok(Async::NotReady) => None,
+                    Err(err) => Some(Connection::Failed(id, err)),
+                };
+                if connection.is_some() {
+                    self.claiming = None;
+                    return Ok(Async::Ready(connection));
+                }
+            }
+
+            let event = match self.monitor.poll()? {
+                Async::Ready(Some(event)) => event,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            match event {
+                Event::Add(id)
+                    if self.current.is_none()
+                        && self.rule.matches(self.monitor.context, id).unwrap_or(false) =>
+                {
+                    self.claim(id)
+                }
+                Event::Remove(id) if self.current == Some(id) => {
+                    // The device may go before its interface was claimed.
+                    self.current = None;
+                    self.claiming = None;
+                    return Ok(Async::Ready(Some(Connection::Disconnected(id))));
+                }
+                // The device may have been removed among the lost events.
+                Event::EventsLost => self.monitor.rescan()?,
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! An on-disk record of every device ever seen.
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ProductId, VendorId};
+
+/// A device the registry has seen.
+///
+/// Devices are identified by their vendor ID, product ID and serial number, so devices without
+/// a serial number share an entry with every other device of the same model.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The USB vendor ID.
+    pub vendor_id: VendorId,
+    /// The USB product ID.
+    pub product_id: ProductId,
+    /// The USB serial number string, if the device has one.
+    pub serial_number: Option<String>,
+    /// The USB manufacturer string when the device was last seen, if it has one.
+    pub manufacturer_string: Option<String>,
+    /// The USB product string when the device was last seen, if it has one.
+    pub product_string: Option<String>,
+    /// When the device was first seen.
+    pub first_seen: SystemTime,
+    /// When the device was last seen.
+    pub last_seen: SystemTime,
+    /// A label assigned by the user.
+    pub label: Option<String>,
+}
+
+impl Entry {
+    fn is(&self, vendor_id: VendorId, product_id: ProductId, serial_number: Option<&str>) -> bool {
+        self.vendor_id == vendor_id
+            && self.product_id == product_id
+            && self.serial_number.as_deref() == serial_number
+    }
+}
+
+/// A registry of devices, stored as a JSON file.
+///
+/// Use `Context::record` to add devices to it.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// Open a registry file, or start an empty registry if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entries = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self { path, entries })
+    }
+
+    /// Write the registry back to its file.
+    ///
+    /// The file is replaced atomically, so a crash never leaves a truncated registry behind.
+    pub fn save(&self) -> io::Result<()> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let data = serde_json::to_vec_pretty(&self.entries)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &self.path)
+    }
+
+    /// List every device seen.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Find a device in the registry.
+    pub fn find(&self, vendor_id: VendorId, product_id: ProductId, serial_number: Option<&str>) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.is(vendor_id, product_id, serial_number))
+    }
+
+    /// Label a device, or remove its label with `None`.
+    ///
+    /// Returns false if the registry hasn't seen the device.
+    pub fn set_label(
+        &mut self,
+        vendor_id: VendorId,
+        product_id: ProductId,
+        serial_number: Option<&str>,
+        label: Option<String>,
+    ) -> bool {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is(vendor_id, product_id, serial_number))
+        {
+            Some(entry) => {
+                entry.label = label;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn see(
+        &mut self,
+        vendor_id: VendorId,
+        product_id: ProductId,
+        serial_number: Option<String>,
+        manufacturer_string: Option<String>,
+        product_string: Option<String>,
+    ) {
+        let now = SystemTime::now();
+        let serial = serial_number.as_deref();
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is(vendor_id, product_id, serial))
+        {
+            entry.manufacturer_string = manufacturer_string;
+            entry.product_string = product_string;
+            entry.last_seen = now;
+            return;
+        }
+
+        self.entries.push(Entry {
+            vendor_id,
+            product_id,
+            serial_number,
+            manufacturer_string,
+            product_string,
+            first_seen: now,
+            last_seen: now,
+            label: None,
+        });
+    }
+}
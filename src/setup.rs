@@ -0,0 +1,467 @@
+//! Encoding and decoding control transfer SETUP packets.
+//!
+//! This needs no device or OS backend, so it can be used to build requests for any USB stack, or
+//! to make sense of captured traffic.
+
+/// bRequest values of the standard requests.
+pub mod request {
+    /// GET_STATUS.
+    pub const GET_STATUS: u8 = 0x00;
+    /// CLEAR_FEATURE.
+    pub const CLEAR_FEATURE: u8 = 0x01;
+    /// SET_FEATURE.
+    pub const SET_FEATURE: u8 = 0x03;
+    /// SET_ADDRESS.
+    pub const SET_ADDRESS: u8 = 0x05;
+    /// GET_DESCRIPTOR.
+    pub const GET_DESCRIPTOR: u8 = 0x06;
+    /// SET_DESCRIPTOR.
+    pub const SET_DESCRIPTOR: u8 = 0x07;
+    /// GET_CONFIGURATION.
+    pub const GET_CONFIGURATION: u8 = 0x08;
+    /// SET_CONFIGURATION.
+    pub const SET_CONFIGURATION: u8 = 0x09;
+    /// GET_INTERFACE.
+    pub const GET_INTERFACE: u8 = 0x0a;
+    /// SET_INTERFACE.
+    pub const SET_INTERFACE: u8 = 0x0b;
+    /// SYNCH_FRAME.
+    pub const SYNCH_FRAME: u8 = 0x0c;
+}
+
+/// Standard feature selectors, for `SET_FEATURE` and `CLEAR_FEATURE` requests.
+pub mod feature {
+    /// ENDPOINT_HALT, to halt an endpoint or clear its halt.
+    pub const ENDPOINT_HALT: u16 = 0;
+    /// DEVICE_REMOTE_WAKEUP, to allow a device to wake the host.
+    pub const DEVICE_REMOTE_WAKEUP: u16 = 1;
+    /// TEST_MODE, to put a high speed device into one of the electrical test modes.
+    pub const TEST_MODE: u16 = 2;
+}
+
+/// Descriptor types, for `GET_DESCRIPTOR` requests.
+pub mod descriptor_type {
+    /// A device descriptor.
+    pub const DEVICE: u8 = 0x01;
+    /// A configuration descriptor, followed by its interfaces and endpoints.
+    pub const CONFIGURATION: u8 = 0x02;
+    /// A string descriptor, or the list of supported languages at index 0.
+    pub const STRING: u8 = 0x03;
+    /// An interface descriptor.
+    pub const INTERFACE: u8 = 0x04;
+    /// An endpoint descriptor.
+    pub const ENDPOINT: u8 = 0x05;
+    /// A device qualifier descriptor, describing a high speed device at the other speed.
+    pub const DEVICE_QUALIFIER: u8 = 0x06;
+    /// An interface association descriptor, grouping the interfaces of one function.
+    pub const INTERFACE_ASSOCIATION: u8 = 0x0b;
+    /// A Binary Object Store descriptor.
+    pub const BOS: u8 = 0x0f;
+    /// A HID class descriptor.
+    pub const HID: u8 = 0x21;
+    /// A HID report descriptor.
+    pub const HID_REPORT: u8 = 0x22;
+}
+
+/// The status of a device, as returned by a `GET_STATUS` request to it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DeviceStatus {
+    /// The device is running on its own power supply rather than the bus.
+    pub self_powered: bool,
+    /// The device is allowed to wake the host from suspend.
+    pub remote_wakeup: bool,
+}
+
+impl DeviceStatus {
+    /// Decode the two bytes the device returns.
+    pub fn from_bytes(bytes: [u8; 2]) -> Self {
+        Self {
+            self_powered: bytes[0] & 0x01 != 0,
+            remote_wakeup: bytes[0] & 0x02 != 0,
+        }
+    }
+}
+
+/// The status of an interface, as returned by a `GET_STATUS` request to it.
+///
+/// Interfaces only have a status from USB 3.0 on; older devices return zeros.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct InterfaceStatus {
+    /// The interface's function can wake the host from suspend.
+    pub remote_wake_capable: bool,
+    /// The interface's function is allowed to wake the host from suspend.
+    pub remote_wakeup: bool,
+}
+
+impl InterfaceStatus {
+    /// Decode the two bytes the device returns.
+    pub fn from_bytes(bytes: [u8; 2]) -> Self {
+        Self {
+            remote_wake_capable: bytes[0] & 0x01 != 0,
+            remote_wakeup: bytes[0] & 0x02 != 0,
+        }
+    }
+}
+
+/// The electrical test modes of USB 2.0 high speed devices, for compliance testing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TestMode {
+    /// Drive a constant J state.
+    J,
+    /// Drive a constant K state.
+    K,
+    /// Answer IN tokens with NAK, leaving the bus in SE0 otherwise.
+    Se0Nak,
+    /// Send the test packet over and over.
+    Packet,
+    /// Force a downstream port of a hub into the enabled state.
+    ForceEnable,
+}
+
+/// Which way the data stage of a control transfer goes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// From the host to the device.
+    Out,
+    /// From the device to the host.
+    In,
+}
+
+/// Who defines a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    /// The USB specification.
+    Standard,
+    /// A device class specification.
+    Class,
+    /// The vendor.
+    Vendor,
+    /// A reserved value.
+    Reserved,
+}
+
+/// What a request is addressed to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Recipient {
+    /// The device.
+    Device,
+    /// An interface, numbered by `wIndex`.
+    Interface,
+    /// An endpoint, addressed by `wIndex`.
+    Endpoint,
+    /// Something else, or a reserved value.
+    Other,
+}
+
+/// The SETUP packet starting a control transfer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SetupPacket {
+    /// The direction, kind and recipient of the request (`bmRequestType`).
+    pub request_type: u8,
+    /// The request (`bRequest`).
+    pub request: u8,
+    /// `wValue`, whose meaning depends on the request.
+    pub value: u16,
+    /// `wIndex`, usually an interface number or endpoint address.
+    pub index: u16,
+    /// How many bytes the data stage carries at most (`wLength`).
+    pub length: u16,
+}
+
+impl SetupPacket {
+    /// Make a SETUP packet, assembling `bmRequestType` from its parts.
+    pub fn new(
+        direction: Direction,
+        kind: RequestKind,
+        recipient: Recipient,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Self {
+        let direction = match direction {
+            Direction::Out => 0x00,
+            Direction::In => 0x80,
+        };
+        let kind = match kind {
+            RequestKind::Standard => 0x00,
+            RequestKind::Class => 0x20,
+            RequestKind::Vendor => 0x40,
+            RequestKind::Reserved => 0x60,
+        };
+        let recipient = match recipient {
+            Recipient::Device => 0x00,
+            Recipient::Interface => 0x01,
+            Recipient::Endpoint => 0x02,
+            Recipient::Other => 0x03,
+        };
+        Self {
+            request_type: direction | kind | recipient,
+            request,
+            value,
+            index,
+            length,
+        }
+    }
+
+    /// Request a descriptor of the device.
+    ///
+    /// `language` is the language ID for string descriptors, and 0 otherwise.
+    pub fn get_descriptor(descriptor_type: u8, index: u8, language: u16, length: u16) -> Self {
+        Self::new(
+            Direction::In,
+            RequestKind::Standard,
+            Recipient::Device,
+            request::GET_DESCRIPTOR,
+            u16::from(descriptor_type) << 8 | u16::from(index),
+            language,
+            length,
+        )
+    }
+
+    /// Request the status of the device, an interface numbered by `index`, or an endpoint
+    /// addressed by `index`.
+    ///
+    /// `DeviceStatus` and `InterfaceStatus` decode the reply; for an endpoint, bit 0 is set
+    /// while it's halted.
+    pub fn get_status(recipient: Recipient, index: u16) -> Self {
+        Self::new(
+            Direction::In,
+            RequestKind::Standard,
+            recipient,
+            request::GET_STATUS,
+            0,
+            index,
+            2,
+        )
+    }
+
+    /// Request the current configuration value.
+    pub fn get_configuration() -> Self {
+        Self::new(
+            Direction::In,
+            RequestKind::Standard,
+            Recipient::Device,
+            request::GET_CONFIGURATION,
+            0,
+            0,
+            1,
+        )
+    }
+
+    /// Select a configuration by its value, or 0 to unconfigure the device.
+    pub fn set_configuration(configuration: u8) -> Self {
+        Self::new(
+            Direction::Out,
+            RequestKind::Standard,
+            Recipient::Device,
+            request::SET_CONFIGURATION,
+            configuration.into(),
+            0,
+            0,
+        )
+    }
+
+    /// Select an alternate setting of an interface.
+    pub fn set_interface(interface: u8, alternate_setting: u8) -> Self {
+        Self::new(
+            Direction::Out,
+            RequestKind::Standard,
+            Recipient::Interface,
+            request::SET_INTERFACE,
+            alternate_setting.into(),
+            interface.into(),
+            0,
+        )
+    }
+
+    /// Set a feature of the device, an interface numbered by `index`, or an endpoint addressed
+    /// by `index`, such as `feature::ENDPOINT_HALT`.
+    pub fn set_feature(recipient: Recipient, feature: u16, index: u16) -> Self {
+        Self::new(
+            Direction::Out,
+            RequestKind::Standard,
+            recipient,
+            request::SET_FEATURE,
+            feature,
+            index,
+            0,
+        )
+    }
+
+    /// Clear a feature of the device, an interface numbered by `index`, or an endpoint
+    /// addressed by `index`.
+    pub fn clear_feature(recipient: Recipient, feature: u16, index: u16) -> Self {
+        Self::new(
+            Direction::Out,
+            RequestKind::Standard,
+            recipient,
+            request::CLEAR_FEATURE,
+            feature,
+            index,
+            0,
+        )
+    }
+
+    /// Clear the halt condition of an endpoint.
+    pub fn clear_halt(endpoint: u8) -> Self {
+        Self::clear_feature(Recipient::Endpoint, feature::ENDPOINT_HALT, endpoint.into())
+    }
+
+    /// Put the device into a test mode. The test selector goes in the high byte of `wIndex`.
+    pub fn set_test_mode(mode: TestMode) -> Self {
+        let selector: u16 = match mode {
+            TestMode::J => 1,
+            TestMode::K => 2,
+            TestMode::Se0Nak => 3,
+            TestMode::Packet => 4,
+            TestMode::ForceEnable => 5,
+        };
+        Self::set_feature(Recipient::Device, feature::TEST_MODE, selector << 8)
+    }
+
+    /// Decode a SETUP packet.
+    ///
+    /// Returns `None` if `bytes` isn't 8 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 8 {
+            return None;
+        }
+        let word = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        Some(Self {
+            request_type: bytes[0],
+            request: bytes[1],
+            value: word(2),
+            index: word(4),
+            length: word(6),
+        })
+    }
+
+    /// Encode the SETUP packet as sent on the wire.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let [value_low, value_high] = self.value.to_le_bytes();
+        let [index_low, index_high] = self.index.to_le_bytes();
+        let [length_low, length_high] = self.length.to_le_bytes();
+        [
+            self.request_type,
+            self.request,
+            value_low,
+            value_high,
+            index_low,
+            index_high,
+            length_low,
+            length_high,
+        ]
+    }
+
+    /// Which way the data stage goes.
+    pub fn direction(&self) -> Direction {
+        if self.request_type & 0x80 == 0 {
+            Direction::Out
+        } else {
+            Direction::In
+        }
+    }
+
+    /// Who defines the request.
+    pub fn kind(&self) -> RequestKind {
+        match self.request_type & 0x60 {
+            0x00 => RequestKind::Standard,
+            0x20 => RequestKind::Class,
+            0x40 => RequestKind::Vendor,
+            _ => RequestKind::Reserved,
+        }
+    }
+
+    /// What the request is addressed to.
+    pub fn recipient(&self) -> Recipient {
+        match self.request_type & 0x1f {
+            0x00 => Recipient::Device,
+            0x01 => Recipient::Interface,
+            0x02 => Recipient::Endpoint,
+            _ => Recipient::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        // The request lsusb makes for the list of languages.
+        let packet = SetupPacket::get_descriptor(descriptor_type::STRING, 0, 0, 255);
+        assert_eq!(packet.to_bytes(), [0x80, 0x06, 0x00, 0x03, 0x00, 0x00, 0xff, 0x00]);
+        assert_eq!(
+            SetupPacket::get_descriptor(descriptor_type::STRING, 2, 0x0409, 255).to_bytes(),
+            [0x80, 0x06, 0x02, 0x03, 0x09, 0x04, 0xff, 0x00],
+        );
+        assert_eq!(
+            SetupPacket::set_interface(1, 2).to_bytes(),
+            [0x01, 0x0b, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00],
+        );
+        assert_eq!(
+            SetupPacket::clear_halt(0x81).to_bytes(),
+            [0x02, 0x01, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00],
+        );
+        let vendor = SetupPacket::new(
+            Direction::Out,
+            RequestKind::Vendor,
+            Recipient::Other,
+            0x42,
+            0x1234,
+            0x5678,
+            0x9abc,
+        );
+        assert_eq!(vendor.to_bytes(), [0x43, 0x42, 0x34, 0x12, 0x78, 0x56, 0xbc, 0x9a]);
+    }
+
+    #[test]
+    fn decode() {
+        let packet = SetupPacket::from_bytes(&[0xa1, 0x01, 0x00, 0x01, 0x02, 0x00, 0x40, 0x00]);
+        let packet = packet.unwrap();
+        assert_eq!(packet.direction(), Direction::In);
+        assert_eq!(packet.kind(), RequestKind::Class);
+        assert_eq!(packet.recipient(), Recipient::Interface);
+        assert_eq!((packet.request, packet.value, packet.index, packet.length), (1, 0x100, 2, 64));
+        assert_eq!(SetupPacket::from_bytes(&packet.to_bytes()), Some(packet));
+
+        assert_eq!(SetupPacket::from_bytes(&[0; 7]), None);
+        assert_eq!(SetupPacket::from_bytes(&[0; 9]), None);
+        assert_eq!(SetupPacket::from_bytes(&[0x60; 8]).unwrap().kind(), RequestKind::Reserved);
+    }
+
+    #[test]
+    fn statuses() {
+        assert_eq!(
+            SetupPacket::get_status(Recipient::Endpoint, 0x81).to_bytes(),
+            [0x82, 0x00, 0x00, 0x00, 0x81, 0x00, 0x02, 0x00],
+        );
+        assert_eq!(DeviceStatus::from_bytes([0, 0]), DeviceStatus::default());
+        assert_eq!(
+            DeviceStatus::from_bytes([0x03, 0x00]),
+            DeviceStatus {
+                self_powered: true,
+                remote_wakeup: true,
+            },
+        );
+        assert!(!DeviceStatus::from_bytes([0x00, 0x01]).self_powered);
+        assert!(InterfaceStatus::from_bytes([0x01, 0x00]).remote_wake_capable);
+        assert!(!InterfaceStatus::from_bytes([0x01, 0x00]).remote_wakeup);
+    }
+
+    #[test]
+    fn features() {
+        let wakeup = SetupPacket::set_feature(Recipient::Device, feature::DEVICE_REMOTE_WAKEUP, 0);
+        assert_eq!(wakeup.to_bytes(), [0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            SetupPacket::clear_feature(Recipient::Endpoint, feature::ENDPOINT_HALT, 0x02),
+            SetupPacket::clear_halt(0x02),
+        );
+        assert_eq!(
+            SetupPacket::set_test_mode(TestMode::Packet).to_bytes(),
+            [0x00, 0x03, 0x02, 0x00, 0x00, 0x04, 0x00, 0x00],
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Parsing of the uevent messages the kernel broadcasts when devices change.
+//!
+//! The kernel multicasts uevents on group 1 of `NETLINK_KOBJECT_UEVENT` sockets, one per
+//! datagram; pass each datagram to `Uevent::parse`. udevd rebroadcasts them on group 2 in its own
+//! binary format, which isn't parsed. Check that the sender's port ID is 0 before trusting a
+//! message, since other processes may send to the group too.
+//!
+//! Parsing borrows from the message and never allocates, so it is safe to feed it untrusted
+//! input of any size.
+use std::{
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+/// What happened to a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// The device was added.
+    Add,
+    /// The device was removed.
+    Remove,
+    /// The device changed.
+    Change,
+    /// Any other action, such as `bind` or `unbind`.
+    Other,
+}
+
+/// A kernel uevent.
+///
+/// The commonly needed fields are parsed up front; the rest can be looked up with `property`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Uevent<'a> {
+    buf: &'a [u8],
+    action: Action,
+    devpath: &'a OsStr,
+    subsystem: Option<&'a OsStr>,
+    seqnum: Option<u64>,
+}
+
+impl<'a> Uevent<'a> {
+    /// Parse a uevent message: an `ACTION@DEVPATH` header followed by NUL-separated
+    /// `KEY=VALUE` pairs.
+    ///
+    /// Returns `None` if the message is malformed or lacks an `ACTION` or `DEVPATH`.
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        let mut fields = buf.split(|&b| b == 0).filter(|field| !field.is_empty());
+        let header = fields.next()?;
+        if !header.contains(&b'@') {
+            return None;
+        }
+
+        let mut action = None;
+        let mut devpath = None;
+        let mut subsystem = None;
+        let mut seqnum = None;
+        for field in fields {
+            let split = match field.iter().position(|&b| b == b'=') {
+                Some(split) => split,
+                None => continue,
+            };
+            let (key, value) = (&field[..split], &field[split + 1..]);
+            match key {
+                b"ACTION" => {
+                    action = Some(match value {
+                        b"add" => Action::Add,
+                        b"remove" => Action::Remove,
+                        b"change" => Action::Change,
+                        _ => Action::Other,
+                    })
+                }
+                b"DEVPATH" => devpath = Some(OsStr::from_bytes(value)),
+                b"SUBSYSTEM" => subsystem = Some(OsStr::from_bytes(value)),
+                b"SEQNUM" => {
+                    seqnum = std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.parse().ok())
+                }
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            buf,
+            action: action?,
+            devpath: devpath?,
+            subsystem,
+            seqnum,
+        })
+    }
+
+    /// What happened to the device.
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    /// The path of the device below sysfs, such as `/devices/pci0000:00/0000:00:14.0/usb1/1-2`.
+    pub fn devpath(&self) -> &'a Path {
+        Path::new(self.devpath)
+    }
+
+    /// The subsystem of the device, such as `usb`.
+    pub fn subsystem(&self) -> Option<&'a OsStr> {
+        self.subsystem
+    }
+
+    /// The kernel's sequence number for the event.
+    pub fn seqnum(&self) -> Option<u64> {
+        self.seqnum
+    }
+
+    /// Look up the value of a key, such as `DEVTYPE`.
+    pub fn property(&self, key: &str) -> Option<&'a OsStr> {
+        self.properties()
+            .find(|(name, _)| name.as_bytes() == key.as_bytes())
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate through the `KEY=VALUE` pairs of the event, including `ACTION`, `DEVPATH`,
+    /// `SUBSYSTEM` and `SEQNUM`, in the order the kernel sent them.
+    pub fn properties(&self) -> impl Iterator<Item = (&'a OsStr, &'a OsStr)> + 'a {
+        self.buf.split(|&b| b == 0).skip(1).filter_map(|field| {
+            let split = field.iter().position(|&b| b == b'=')?;
+            Some((OsStr::from_bytes(&field[..split]), OsStr::from_bytes(&field[split + 1..])))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADD: &[u8] = b"add@/devices/pci0000:00/0000:00:14.0/usb1/1-2\0ACTION=add\0\
+        DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-2\0SUBSYSTEM=usb\0\
+        DEVTYPE=usb_device\0SEQNUM=4242\0";
+
+    #[test]
+    fn parse() {
+        let uevent = Uevent::parse(ADD).unwrap();
+        assert_eq!(uevent.action(), Action::Add);
+        assert_eq!(uevent.devpath(), Path::new("/devices/pci0000:00/0000:00:14.0/usb1/1-2"));
+        assert_eq!(uevent.subsystem(), Some(OsStr::new("usb")));
+        assert_eq!(uevent.seqnum(), Some(4242));
+
+        let unbind = Uevent::parse(b"unbind@/x\0ACTION=unbind\0DEVPATH=/x\0SEQNUM=no\0").unwrap();
+        assert_eq!(unbind.action(), Action::Other);
+        assert_eq!(unbind.subsystem(), None);
+        assert_eq!(unbind.seqnum(), None);
+    }
+
+    #[test]
+    fn reject_malformed() {
+        assert_eq!(Uevent::parse(b""), None);
+        assert_eq!(Uevent::parse(b"\0\0"), None);
+        // udevd's messages start with "libudev" rather than a header.
+        assert_eq!(Uevent::parse(b"libudev\0ACTION=add\0DEVPATH=/x\0"), None);
+        assert_eq!(Uevent::parse(b"add@/x\0DEVPATH=/x\0"), None);
+        assert_eq!(Uevent::parse(b"add@/x\0ACTION=add\0"), None);
+    }
+
+    #[test]
+    fn properties() {
+        let uevent = Uevent::parse(ADD).unwrap();
+        assert_eq!(uevent.property("DEVTYPE"), Some(OsStr::new("usb_device")));
+        assert_eq!(uevent.property("DEVTYPE=usb_device"), None);
+        assert_eq!(uevent.property("MISSING"), None);
+        let keys: Vec<_> = uevent.properties().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["ACTION", "DEVPATH", "SUBSYSTEM", "DEVTYPE", "SEQNUM"]);
+
+        // Values may contain '=' themselves, and fields without one are skipped.
+        let message = b"add@/x\0ACTION=add\0DEVPATH=/x\0JUNK\0MODALIAS=a=b\0";
+        let uevent = Uevent::parse(message).unwrap();
+        assert_eq!(uevent.property("MODALIAS"), Some(OsStr::new("a=b")));
+        assert_eq!(uevent.properties().count(), 3);
+    }
+}
@@ -0,0 +1,133 @@
+//! Vendor and product names from the usb.ids database.
+
+use std::collections::HashMap;
+#[cfg(not(feature = "usb-ids-embedded"))]
+use std::fs;
+
+// Where distributions install usb.ids.
+#[cfg(not(feature = "usb-ids-embedded"))]
+const PATHS: &[&str] = &[
+    "/usr/share/hwdata/usb.ids",
+    "/usr/share/misc/usb.ids",
+    "/usr/share/usb.ids",
+    "/var/lib/usbutils/usb.ids",
+];
+
+struct Vendor {
+    name: String,
+    products: HashMap<u16, String>,
+}
+
+#[derive(Default)]
+pub struct UsbIds {
+    vendors: HashMap<u16, Vendor>,
+}
+
+impl UsbIds {
+    // Load the copy of usb.ids compiled in at build time.
+    #[cfg(feature = "usb-ids-embedded")]
+    pub fn load() -> Self {
+        const EMBEDDED: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/usb.ids"));
+        Self::parse(&String::from_utf8_lossy(EMBEDDED))
+    }
+
+    // Load the system copy of usb.ids, or an empty database if there isn't one.
+    #[cfg(not(feature = "usb-ids-embedded"))]
+    pub fn load() -> Self {
+        PATHS
+            .iter()
+            .find_map(|path| fs::read(path).ok())
+            .map(|data| Self::parse(&String::from_utf8_lossy(&data)))
+            .unwrap_or_default()
+    }
+
+    // Parse the contents of a usb.ids file.
+    //
+    // Vendors start at the beginning of a line and their products are indented by one tab.
+    // Everything else (interfaces, device classes, HID usages, ...) is skipped.
+    pub fn parse(data: &str) -> Self {
+        // Parse "xxxx  name", where xxxx is a hex ID.
+        fn entry(line: &str) -> Option<(u16, &str)> {
+            let id = u16::from_str_radix(line.get(..4)?, 16).ok()?;
+            let name = line.get(4..)?.trim();
+            if name.is_empty() {
+                None
+            } else {
+                Some((id, name))
+            }
+        }
+
+        let mut vendors = HashMap::new();
+        let mut vendor = None;
+        for line in data.lines() {
+            if line.starts_with('#') || line.trim().is_empty() || line.starts_with("\t\t") {
+                continue;
+            }
+
+            if let Some(product) = line.strip_prefix('\t') {
+                let products = vendor.and_then(|vendor| vendors.get_mut(&vendor));
+                if let (Some(Vendor { products, .. }), Some((id, name))) = (products, entry(product)) {
+                    products.insert(id, String::from(name));
+                }
+            } else {
+                // The sections after the vendor list begin with lines like "C 00  ...", which
+                // don't parse as vendors and so end the current one.
+                vendor = entry(line).map(|(id, name)| {
+                    vendors.entry(id).or_insert_with(|| Vendor {
+                        name: String::from(name),
+                        products: HashMap::new(),
+                    });
+                    id
+                });
+            }
+        }
+
+        Self { vendors }
+    }
+
+    pub fn vendor(&self, vendor_id: u16) -> Option<&str> {
+        self.vendors.get(&vendor_id).map(|vendor| vendor.name.as_str())
+    }
+
+    pub fn product(&self, vendor_id: u16, product_id: u16) -> Option<&str> {
+        self.vendors
+            .get(&vendor_id)?
+            .products
+            .get(&product_id)
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USB_IDS: &str = "\
+# Comment, followed by a blank line
+
+1d6b  Linux Foundation
+\t0001  1.1 root hub
+\t0002  2.0 root hub
+\t\t0002  an interface, which is skipped
+046d  Logitech, Inc.
+\tc52b  Unifying Receiver
+\t0000
+C 00  (Defined at Interface level)
+\t01  Audio
+";
+
+    #[test]
+    fn parse() {
+        let ids = UsbIds::parse(USB_IDS);
+        assert_eq!(ids.vendor(0x1d6b), Some("Linux Foundation"));
+        assert_eq!(ids.product(0x1d6b, 0x0002), Some("2.0 root hub"));
+        assert_eq!(ids.vendor(0x046d), Some("Logitech, Inc."));
+        assert_eq!(ids.product(0x046d, 0xc52b), Some("Unifying Receiver"));
+        // Entries without a name are skipped.
+        assert_eq!(ids.product(0x046d, 0x0000), None);
+        // The device class list doesn't add products to the last vendor.
+        assert_eq!(ids.product(0x046d, 0x0001), None);
+        assert_eq!(ids.vendor(0x0000), None);
+        assert_eq!(ids.product(0xffff, 0x0001), None);
+    }
+}
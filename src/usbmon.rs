@@ -0,0 +1,350 @@
+//! Capture USB traffic through the kernel's usbmon interface.
+//!
+//! This needs the `usbmon` kernel module to be loaded and, usually, root.
+use std::{
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{prelude::*, reactor};
+
+use crate::{Context, Error, Id};
+
+// The size of the header usbmon prepends to each event when read().
+const HEADER_LEN: usize = 48;
+
+// The pcapng link type of usbmon's 48-byte header, LINKTYPE_USB_LINUX.
+const LINKTYPE_USB_LINUX: u16 = 189;
+
+// usbmon captures at most a fifth of its 300 KiB default buffer per event.
+const MAX_DATA_LEN: usize = 64 * 1024;
+
+/// What a captured packet records.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PacketKind {
+    /// A transfer was submitted.
+    Submit,
+    /// A transfer completed.
+    Complete,
+    /// A transfer couldn't be submitted.
+    Error,
+}
+
+/// The type of a captured transfer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TransferType {
+    /// An isochronous transfer.
+    Isochronous,
+    /// An interrupt transfer.
+    Interrupt,
+    /// A control transfer.
+    Control,
+    /// A bulk transfer.
+    Bulk,
+}
+
+/// A captured USB transfer event.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Packet {
+    /// Identifies the transfer, tying its submission and completion together.
+    pub urb_id: u64,
+    /// What the packet records.
+    pub kind: PacketKind,
+    /// The type of the transfer.
+    pub transfer_type: TransferType,
+    /// The endpoint address, including the direction bit (0x80 for IN).
+    pub endpoint: u8,
+    /// The address of the device on its bus.
+    pub device_address: u8,
+    /// The number of the bus the device is attached to.
+    pub bus_number: u16,
+    /// The SETUP packet of a control transfer submission, which `setup::SetupPacket` decodes.
+    pub setup: Option<[u8; 8]>,
+    /// When the event happened.
+    pub timestamp: SystemTime,
+    /// The URB status, a negated errno; 0 on success.
+    pub status: i32,
+    /// The length of the transfer, which may be more than was captured in `data`.
+    pub length: u32,
+    /// The captured data.
+    pub data: Vec<u8>,
+    header: [u8; HEADER_LEN],
+}
+
+impl Packet {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN {
+            return None;
+        }
+        // The header is in host byte order.
+        let u16_at = |at: usize| u16::from_ne_bytes(buf[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_ne_bytes(buf[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_ne_bytes(buf[at..at + 8].try_into().unwrap());
+
+        let kind = match buf[8] {
+            b'S' => PacketKind::Submit,
+            b'C' => PacketKind::Complete,
+            b'E' => PacketKind::Error,
+            _ => return None,
+        };
+        let transfer_type = match buf[9] {
+            0 => TransferType::Isochronous,
+            1 => TransferType::Interrupt,
+            2 => TransferType::Control,
+            3 => TransferType::Bulk,
+            _ => return None,
+        };
+        // The flags are 0 when the setup packet or data is present.
+        let setup = if buf[14] == 0 {
+            let mut setup = [0; 8];
+            setup.copy_from_slice(&buf[40..48]);
+            Some(setup)
+        } else {
+            None
+        };
+        let timestamp = UNIX_EPOCH
+            + Duration::from_secs(u64_at(16))
+            + Duration::from_micros(u64::from(u32_at(24)));
+        let mut header = [0; HEADER_LEN];
+        header.copy_from_slice(&buf[..HEADER_LEN]);
+
+        Some(Self {
+            urb_id: u64_at(0),
+            kind,
+            transfer_type,
+            endpoint: buf[10],
+            device_address: buf[11],
+            bus_number: u16_at(12),
+            setup,
+            timestamp,
+            status: u32_at(28) as i32,
+            length: u32_at(32),
+            data: buf[HEADER_LEN..].to_vec(),
+            header,
+        })
+    }
+}
+
+/// A stream of the USB traffic on a bus.
+pub struct Capture {
+    file: File,
+    reg: reactor::Registration,
+    device_address: Option<u8>,
+    buf: Vec<u8>,
+}
+
+impl Capture {
+    /// Capture the traffic of every device on a bus, or on all buses if `bus` is 0.
+    pub fn bus(bus: u8) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(format!("/dev/usbmon{}", bus))?;
+        Ok(Self {
+            file,
+            reg: reactor::Registration::new(),
+            device_address: None,
+            buf: vec![0; HEADER_LEN + MAX_DATA_LEN],
+        })
+    }
+
+    /// Capture the traffic of a single device.
+    pub fn device(context: &Context, id: Id) -> Result<Self, Error> {
+        let mut capture = Self::bus(context.bus_number(id)?).map_err(|err| Error::Io(err.kind()))?;
+        capture.device_address = Some(context.device_address(id)?);
+        Ok(capture)
+    }
+}
+
+impl Stream for Capture {
+    type Item = Packet;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Packet>>, io::Error> {
+        self.reg
+            .register(&mio::unix::EventedFd(&self.file.as_raw_fd()))?;
+
+        loop {
+            // Each read returns a single event, as long as the buffer can hold it.
+            match self.file.read(&mut self.buf) {
+                Ok(len) => {
+                    let packet = match Packet::parse(&self.buf[..len]) {
+                        Some(packet) => packet,
+                        None => continue,
+                    };
+                    if self.device_address.map_or(true, |address| address == packet.device_address) {
+                        return Ok(Async::Ready(Some(packet)));
+                    }
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if let Async::NotReady = self.reg.poll_read_ready()? {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Writes captured packets to a pcapng file, which Wireshark can open.
+pub struct PcapngWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Start a capture file, writing its section and interface headers.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        // Section header block: byte order magic, version 1.0, unknown section length.
+        write_block(&mut writer, 0x0a0d_0d0a, &[
+            &0x1a2b_3c4d_u32.to_ne_bytes(),
+            &1_u16.to_ne_bytes(),
+            &0_u16.to_ne_bytes(),
+            &(-1_i64).to_ne_bytes(),
+        ])?;
+        // Interface description block; timestamps default to microseconds, like usbmon's.
+        write_block(&mut writer, 1, &[
+            &LINKTYPE_USB_LINUX.to_ne_bytes(),
+            &0_u16.to_ne_bytes(),
+            &((HEADER_LEN + MAX_DATA_LEN) as u32).to_ne_bytes(),
+        ])?;
+        Ok(Self { writer })
+    }
+
+    /// Append a packet to the capture.
+    pub fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        let timestamp = packet
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_micros() as u64)
+            .unwrap_or(0);
+        let captured = HEADER_LEN + packet.data.len();
+        let original = captured.max(HEADER_LEN + packet.length as usize);
+        let padding = [0; 3];
+
+        // Enhanced packet block, for interface 0.
+        write_block(&mut self.writer, 6, &[
+            &0_u32.to_ne_bytes(),
+            &((timestamp >> 32) as u32).to_ne_bytes(),
+            &(timestamp as u32).to_ne_bytes(),
+            &(captured as u32).to_ne_bytes(),
+            &(original as u32).to_ne_bytes(),
+            &packet.header,
+            &packet.data,
+            &padding[..(4 - captured % 4) % 4],
+        ])
+    }
+
+    /// Flush the capture and return the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// Write a pcapng block, whose body is framed by its type and its total length.
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[&[u8]]) -> io::Result<()> {
+    let length = (12 + body.iter().map(|part| part.len()).sum::<usize>()) as u32;
+    writer.write_all(&block_type.to_ne_bytes())?;
+    writer.write_all(&length.to_ne_bytes())?;
+    for part in body {
+        writer.write_all(part)?;
+    }
+    writer.write_all(&length.to_ne_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A completed bulk IN transfer of 3 bytes on bus 2, as usbmon reads it.
+    fn bulk_in_completion() -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0x1234_5678_9abc_def0_u64.to_ne_bytes());
+        buf.extend_from_slice(&[b'C', 3, 0x81, 5]);
+        buf.extend_from_slice(&2_u16.to_ne_bytes());
+        // No setup packet, data present.
+        buf.extend_from_slice(&[b'-', 0]);
+        buf.extend_from_slice(&1_600_000_000_u64.to_ne_bytes());
+        buf.extend_from_slice(&250_000_u32.to_ne_bytes());
+        buf.extend_from_slice(&(-32_i32).to_ne_bytes());
+        buf.extend_from_slice(&3_u32.to_ne_bytes());
+        buf.extend_from_slice(&3_u32.to_ne_bytes());
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&[1, 2, 3]);
+        buf
+    }
+
+    #[test]
+    fn parse() {
+        let buf = bulk_in_completion();
+        let packet = Packet::parse(&buf).unwrap();
+        assert_eq!(packet.urb_id, 0x1234_5678_9abc_def0);
+        assert_eq!(packet.kind, PacketKind::Complete);
+        assert_eq!(packet.transfer_type, TransferType::Bulk);
+        assert_eq!((packet.endpoint, packet.device_address, packet.bus_number), (0x81, 5, 2));
+        assert_eq!(packet.setup, None);
+        assert_eq!(
+            packet.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000) + Duration::from_millis(250),
+        );
+        assert_eq!((packet.status, packet.length), (-32, 3));
+        assert_eq!(packet.data, [1, 2, 3]);
+
+        // A control submission carries its SETUP packet.
+        let mut buf = bulk_in_completion();
+        buf[8..10].copy_from_slice(&[b'S', 2]);
+        buf[14] = 0;
+        buf[40..48].copy_from_slice(&[0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]);
+        let packet = Packet::parse(&buf).unwrap();
+        assert_eq!(packet.kind, PacketKind::Submit);
+        assert_eq!(packet.setup, Some([0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]));
+    }
+
+    #[test]
+    fn reject_malformed() {
+        let buf = bulk_in_completion();
+        assert_eq!(Packet::parse(&buf[..HEADER_LEN - 1]), None);
+        let mut unknown_kind = buf.clone();
+        unknown_kind[8] = b'X';
+        assert_eq!(Packet::parse(&unknown_kind), None);
+        let mut unknown_type = buf;
+        unknown_type[9] = 4;
+        assert_eq!(Packet::parse(&unknown_type), None);
+    }
+
+    #[test]
+    fn pcapng_blocks() {
+        let packet = Packet::parse(&bulk_in_completion()).unwrap();
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        writer.write_packet(&packet).unwrap();
+        let file = writer.into_inner().unwrap();
+
+        let u32_at = |at: usize| u32::from_ne_bytes(file[at..at + 4].try_into().unwrap());
+        let mut blocks = Vec::new();
+        let mut at = 0;
+        while at < file.len() {
+            let length = u32_at(at + 4) as usize;
+            // Blocks are padded to 32 bits, and end with their length again.
+            assert_eq!(length % 4, 0);
+            assert_eq!(u32_at(at + length - 4) as usize, length);
+            blocks.push((u32_at(at), length));
+            at += length;
+        }
+        assert_eq!(at, file.len());
+        // Enhanced packet block: 32 bytes of framing and fields, then the padded packet.
+        assert_eq!(blocks, [(0x0a0d_0d0a, 28), (1, 20), (6, 32 + 52)]);
+
+        assert_eq!(u32_at(8), 0x1a2b_3c4d);
+        let packet_block = 28 + 20;
+        let timestamp = 1_600_000_000_250_000_u64;
+        assert_eq!(u32_at(packet_block + 12), (timestamp >> 32) as u32);
+        assert_eq!(u32_at(packet_block + 16), timestamp as u32);
+        assert_eq!((u32_at(packet_block + 20), u32_at(packet_block + 24)), (51, 51));
+        assert_eq!(&file[packet_block + 28 + HEADER_LEN..][..4], &[1, 2, 3, 0]);
+    }
+}